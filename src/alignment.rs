@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use colored::Colorize;
+use regex::Regex;
+
+use crate::mp4::read_segment_index;
+
+// 分片时长与目标相差超过这个比例时认为发生了偏移
+const DRIFT_RATIO: f64 = 0.1;
+
+// 实际视频分片时长的统计，不含最后一个分片（通常较短）
+#[derive(Debug, Clone)]
+pub struct SegmentStats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub average: f64,
+    pub drifted: usize,
+}
+
+// 分片对齐检查结果
+#[derive(Debug, Clone)]
+pub enum Alignment {
+    Aligned,
+    Drift(SegmentStats),
+    Exceeded(SegmentStats),
+    Unknown,
+}
+
+impl SegmentStats {
+    pub fn describe(&self) -> String {
+        format!(
+            "{}个分片, 时长 {:.2}~{:.2}秒, 平均 {:.2}秒, {}个偏离目标",
+            self.count, self.min, self.max, self.average, self.drifted
+        )
+    }
+}
+
+// 读取打包后MPD中第一个视频Representation的实际分片时长，与--time比较；
+// 超过目标时长的(1 + tolerance)倍时返回Exceeded
+pub fn check(dash_dir: &Path, target: u32, tolerance: f64) -> Alignment {
+    let Some(durations) = video_segment_durations(dash_dir) else {
+        println!("{} 无法读取分片时长, 跳过对齐检查", "⚠️".yellow());
+        return Alignment::Unknown;
+    };
+
+    let target = target as f64;
+    let full = &durations[..durations.len().saturating_sub(1)];
+    if full.is_empty() {
+        return Alignment::Aligned;
+    }
+    let stats = SegmentStats {
+        count: full.len(),
+        min: full.iter().cloned().fold(f64::MAX, f64::min),
+        max: full.iter().cloned().fold(0.0, f64::max),
+        average: full.iter().sum::<f64>() / full.len() as f64,
+        drifted: full.iter().filter(|d| (*d - target).abs() > target * DRIFT_RATIO).count(),
+    };
+
+    if stats.max > target * (1.0 + tolerance) {
+        println!("{} 分片时长超出容差: {}", "❌".red(), stats.describe());
+        Alignment::Exceeded(stats)
+    } else if stats.drifted > 0 {
+        println!("{} 分片时长与目标{}秒不一致: {}", "⚠️".yellow(), target, stats.describe());
+        Alignment::Drift(stats)
+    } else {
+        println!("{} 分片时长与关键帧对齐: {}", "✅".green(), stats.describe());
+        Alignment::Aligned
+    }
+}
+
+// 模板模式从SegmentTimeline读取，单文件模式从文件中的sidx读取
+fn video_segment_durations(dash_dir: &Path) -> Option<Vec<f64>> {
+    let content = std::fs::read_to_string(dash_dir.join("main.mpd")).ok()?;
+    let start = Regex::new(r#"<AdaptationSet[^>]*contentType="video"[^>]*>"#).unwrap().find(&content)?.end();
+    let end = content[start..].find("</AdaptationSet>").map(|i| start + i)?;
+    let adaptation_set = &content[start..end];
+
+    if adaptation_set.contains("<SegmentTimeline>") {
+        let timescale = Regex::new(r#"<SegmentTemplate[^>]*timescale="(\d+)""#)
+            .unwrap()
+            .captures(adaptation_set)
+            .and_then(|caps| caps[1].parse::<f64>().ok())
+            .unwrap_or(1.0);
+        let attribute = |element: &str, name: &str| {
+            Regex::new(&format!(r#"\b{}="(-?\d+)""#, name))
+                .unwrap()
+                .captures(element)
+                .and_then(|caps| caps[1].parse::<i64>().ok())
+        };
+        let mut durations = Vec::new();
+        for element in Regex::new(r"<S\s[^>]*>").unwrap().find_iter(adaptation_set) {
+            let duration = attribute(element.as_str(), "d")? as f64 / timescale;
+            let repeat = attribute(element.as_str(), "r").unwrap_or(0).max(0);
+            durations.extend(std::iter::repeat_n(duration, repeat as usize + 1));
+        }
+        return Some(durations);
+    }
+
+    let base_url = Regex::new(r"<BaseURL>([^<]+)</BaseURL>").unwrap().captures(adaptation_set)?[1].to_string();
+    let caps = Regex::new(r#"indexRange="(\d+)-(\d+)""#).unwrap().captures(adaptation_set)?;
+    let (first, last) = (caps[1].parse::<u64>().ok()?, caps[2].parse::<u64>().ok()?);
+    let mut file = File::open(dash_dir.join(base_url)).ok()?;
+    file.seek(SeekFrom::Start(first)).ok()?;
+    let mut data = vec![0u8; (last + 1).checked_sub(first)? as usize];
+    file.read_exact(&mut data).ok()?;
+    let (timescale, durations) = read_segment_index(&data)?;
+    Some(durations.iter().map(|d| *d as f64 / timescale.max(1) as f64).collect())
+}
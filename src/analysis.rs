@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use colored::Colorize;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::probe::MediaInfo;
+use crate::quality::sample_windows;
+
+// 检测采样的片段数
+const ANALYSIS_SAMPLES: u32 = 5;
+// 同一裁剪结果至少占多少比例才认为可信
+const CROP_CONFIDENCE: f64 = 0.8;
+// 隔行帧至少占多少比例才认为是隔行视频
+const INTERLACE_CONFIDENCE: f64 = 0.8;
+// 黑边小于这个像素数时不裁剪
+const MIN_CROP_PIXELS: u32 = 8;
+
+// 裁剪区域，同时记录源分辨率以便换算成四边裁剪量
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+    pub source_width: u32,
+    pub source_height: u32,
+}
+
+// 反交错滤镜
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Deinterlacer {
+    Yadif,
+    #[default]
+    Bwdif,
+}
+
+// 反交错设置：滤镜和场序
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deinterlace {
+    pub filter: Deinterlacer,
+    pub top_field_first: bool,
+}
+
+// 转码时应用的裁剪和反交错
+#[derive(Debug, Clone, Default)]
+pub struct VideoFilters {
+    pub crop: Option<Crop>,
+    pub deinterlace: Option<Deinterlace>,
+}
+
+// 单个文件的滤镜覆盖设置，来自视频旁边的 <文件名>.dash.json
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FilterOverride {
+    pub crop: Option<String>,
+    pub deinterlace: Option<String>,
+}
+
+impl Deinterlacer {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "yadif" => Some(Deinterlacer::Yadif),
+            "bwdif" => Some(Deinterlacer::Bwdif),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Deinterlacer::Yadif => "yadif",
+            Deinterlacer::Bwdif => "bwdif",
+        }
+    }
+
+    // QSVEncC64没有bwdif，使用硬件的运动自适应反交错代替
+    fn qsv_args(&self) -> &'static [&'static str] {
+        match self {
+            Deinterlacer::Yadif => &["--vpp-yadif"],
+            Deinterlacer::Bwdif => &["--vpp-deinterlace", "normal"],
+        }
+    }
+}
+
+impl Crop {
+    // 解析 W:H:X:Y 格式，裁剪区域必须非空且完全落在源画面内
+    fn parse(value: &str, info: &MediaInfo) -> Option<Self> {
+        let parts: Vec<u32> = value.split(':').filter_map(|v| v.trim().parse::<u32>().ok()).collect();
+        if parts.len() != 4 {
+            return None;
+        }
+        let crop = Crop {
+            width: parts[0],
+            height: parts[1],
+            x: parts[2],
+            y: parts[3],
+            source_width: info.width,
+            source_height: info.height,
+        };
+        if crop.width == 0
+            || crop.height == 0
+            || crop.x as u64 + crop.width as u64 > crop.source_width as u64
+            || crop.y as u64 + crop.height as u64 > crop.source_height as u64
+        {
+            return None;
+        }
+        Some(crop)
+    }
+
+    // 与源画面相比是否真的有黑边
+    fn is_significant(&self) -> bool {
+        self.source_width.saturating_sub(self.width) >= MIN_CROP_PIXELS
+            || self.source_height.saturating_sub(self.height) >= MIN_CROP_PIXELS
+    }
+}
+
+impl VideoFilters {
+    pub fn is_empty(&self) -> bool {
+        self.crop.is_none() && self.deinterlace.is_none()
+    }
+
+    // 输出分辨率，没有裁剪时为None
+    pub fn output_size(&self) -> Option<(u32, u32)> {
+        self.crop.map(|crop| (crop.width, crop.height))
+    }
+
+    // ffmpeg的滤镜链，先反交错再裁剪
+    pub fn ffmpeg_filter(&self) -> Option<String> {
+        let mut filters = Vec::new();
+        if let Some(deinterlace) = self.deinterlace {
+            filters.push(format!(
+                "{}=mode=send_frame:parity={}",
+                deinterlace.filter.name(),
+                if deinterlace.top_field_first { "tff" } else { "bff" }
+            ));
+        }
+        if let Some(crop) = self.crop {
+            filters.push(format!("crop={}:{}:{}:{}", crop.width, crop.height, crop.x, crop.y));
+        }
+
+        if filters.is_empty() {
+            None
+        } else {
+            Some(filters.join(","))
+        }
+    }
+
+    // QSVEncC64的裁剪和反交错参数
+    pub fn qsv_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(deinterlace) = self.deinterlace {
+            args.push(String::from("--interlace"));
+            args.push(String::from(if deinterlace.top_field_first { "tff" } else { "bff" }));
+            args.extend(deinterlace.filter.qsv_args().iter().map(|arg| arg.to_string()));
+        }
+        if let Some(crop) = self.crop {
+            let right = crop.source_width.saturating_sub(crop.x + crop.width);
+            let bottom = crop.source_height.saturating_sub(crop.y + crop.height);
+            args.push(String::from("--crop"));
+            args.push(format!("{},{},{},{}", crop.x, crop.y, right, bottom));
+        }
+        args
+    }
+
+    // 简短描述，用于日志和报告
+    pub fn describe(&self) -> String {
+        self.ffmpeg_filter().unwrap_or_else(|| String::from("无"))
+    }
+}
+
+// 视频旁边的覆盖设置文件路径
+pub fn override_path(video_path: &Path) -> PathBuf {
+    let file_name = video_path.file_name().unwrap_or_default().to_string_lossy();
+    video_path.with_file_name(format!("{}.dash.json", file_name))
+}
+
+// 读取单个文件的覆盖设置
+pub fn load_override(video_path: &Path) -> Option<FilterOverride> {
+    let path = override_path(video_path);
+    let content = fs::read_to_string(&path).ok()?;
+    match serde_json::from_str::<FilterOverride>(&content) {
+        Ok(filter_override) => {
+            println!("{} 使用覆盖设置: {}", "📄".blue(), path.display());
+            Some(filter_override)
+        }
+        Err(e) => {
+            println!("{} 解析覆盖设置失败 {}: {}", "⚠️".yellow(), path.display(), e);
+            None
+        }
+    }
+}
+
+// 根据覆盖设置和自动检测确定滤镜
+// 覆盖值可以是 auto(自动检测)、none(不处理)，或者具体的裁剪区域/反交错滤镜
+pub fn resolve_filters(
+    video_path: &Path,
+    info: &MediaInfo,
+    detect: bool,
+    deinterlacer: Deinterlacer,
+    filter_override: Option<&FilterOverride>,
+) -> VideoFilters {
+    let default_mode = if detect { "auto" } else { "none" };
+    let crop_mode = filter_override
+        .and_then(|o| o.crop.clone())
+        .unwrap_or_else(|| default_mode.to_string());
+    let deinterlace_mode = filter_override
+        .and_then(|o| o.deinterlace.clone())
+        .unwrap_or_else(|| default_mode.to_string());
+
+    let crop = match crop_mode.as_str() {
+        "none" => None,
+        "auto" => detect_crop(video_path, info),
+        value => Crop::parse(value, info).or_else(|| {
+            println!("{} 无效的裁剪设置: {}", "⚠️".yellow(), value);
+            None
+        }),
+    };
+
+    let deinterlace = match deinterlace_mode.as_str() {
+        "none" => None,
+        "auto" => detect_interlace(video_path, info).map(|top_field_first| Deinterlace {
+            filter: deinterlacer,
+            top_field_first,
+        }),
+        value => {
+            // 形如 bwdif 或 bwdif:bff，未指定场序时默认顶场优先
+            let mut parts = value.split(':');
+            let filter = parts.next().and_then(Deinterlacer::parse);
+            let top_field_first = match (parts.next(), parts.next()) {
+                (None | Some("tff"), None) => Some(true),
+                (Some("bff"), None) => Some(false),
+                _ => None,
+            };
+            match (filter, top_field_first) {
+                (Some(filter), Some(top_field_first)) => Some(Deinterlace { filter, top_field_first }),
+                _ => {
+                    println!("{} 无效的反交错设置: {}", "⚠️".yellow(), value);
+                    None
+                }
+            }
+        }
+    };
+
+    VideoFilters { crop, deinterlace }
+}
+
+// 在若干采样片段上运行cropdetect，结果一致时才返回裁剪区域
+fn detect_crop(video_path: &Path, info: &MediaInfo) -> Option<Crop> {
+    println!("{} 正在检测黑边: {}", "🔍".blue(), video_path.display());
+    let re = Regex::new(r"crop=(\d+:\d+:\d+:\d+)").unwrap();
+    let mut votes: HashMap<Crop, usize> = HashMap::new();
+    let mut total = 0;
+
+    for (start, length) in sample_windows(info.duration, ANALYSIS_SAMPLES) {
+        let stderr = run_analysis(video_path, start, length, "cropdetect=limit=24:round=2:reset=0")?;
+        // reset=0时每个片段最后一行是该片段的累计结果
+        if let Some(caps) = re.captures_iter(&stderr).last() {
+            if let Some(crop) = Crop::parse(&caps[1], info) {
+                *votes.entry(crop).or_insert(0) += 1;
+                total += 1;
+            }
+        }
+    }
+
+    let (crop, count) = votes.into_iter().max_by_key(|(_, count)| *count)?;
+    if (count as f64) < total as f64 * CROP_CONFIDENCE {
+        println!("{} 各片段黑边检测结果不一致, 不裁剪", "⚠️".yellow());
+        return None;
+    }
+    if !crop.is_significant() {
+        return None;
+    }
+
+    println!("{} 检测到黑边, 裁剪为 {}x{}+{}+{}", "✂️".blue(), crop.width, crop.height, crop.x, crop.y);
+    Some(crop)
+}
+
+// 在若干采样片段上运行idet，隔行帧占多数时返回场序（true为顶场优先）
+fn detect_interlace(video_path: &Path, info: &MediaInfo) -> Option<bool> {
+    println!("{} 正在检测隔行扫描: {}", "🔍".blue(), video_path.display());
+    let re = Regex::new(r"Multi frame detection: TFF:\s*(\d+)\s*BFF:\s*(\d+)\s*Progressive:\s*(\d+)").unwrap();
+    let (mut tff, mut bff, mut progressive) = (0u64, 0u64, 0u64);
+
+    for (start, length) in sample_windows(info.duration, ANALYSIS_SAMPLES) {
+        let stderr = run_analysis(video_path, start, length, "idet")?;
+        if let Some(caps) = re.captures_iter(&stderr).last() {
+            tff += caps[1].parse::<u64>().unwrap_or(0);
+            bff += caps[2].parse::<u64>().unwrap_or(0);
+            progressive += caps[3].parse::<u64>().unwrap_or(0);
+        }
+    }
+
+    let interlaced = tff + bff;
+    if interlaced == 0 || (interlaced as f64) < (interlaced + progressive) as f64 * INTERLACE_CONFIDENCE {
+        return None;
+    }
+
+    let top_field_first = tff >= bff;
+    println!("{} 检测到隔行扫描 ({})", "🔍".blue(), if top_field_first { "TFF" } else { "BFF" });
+    Some(top_field_first)
+}
+
+fn run_analysis(video_path: &Path, start: f64, length: f64, filter: &str) -> Option<String> {
+    let output = Command::new("ffmpeg")
+        .args([
+            "-hide_banner",
+            "-nostats",
+            "-ss", &format!("{:.3}", start),
+            "-t", &format!("{:.3}", length),
+            "-i", video_path.to_str().unwrap(),
+            "-map", "0:v:0",
+            "-an",
+            "-vf", filter,
+            "-f", "null",
+            "-",
+        ])
+        .output();
+
+    match output {
+        Ok(output) => Some(String::from_utf8_lossy(&output.stderr).to_string()),
+        Err(e) => {
+            println!("{} 执行ffmpeg失败: {}", "❌".red(), e);
+            None
+        }
+    }
+}
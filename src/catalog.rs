@@ -0,0 +1,90 @@
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::json_generator::{write_json, IndexOptions, ServerConfig, ServerIndex, VideoEntry};
+use crate::utils::natural_cmp;
+
+// 按输入文件夹结构组织的目录JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Catalog {
+    pub schema_version: u32,
+    pub generated_at: String,
+    pub server: String,
+    pub root: CatalogGroup,
+}
+
+// 目录中的一个分组，对应输入路径下的一个文件夹
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CatalogGroup {
+    pub name: String,
+    pub path: String,
+    pub groups: Vec<CatalogGroup>,
+    pub videos: Vec<VideoEntry>,
+}
+
+impl CatalogGroup {
+    fn insert(&mut self, folders: &[&str], entry: VideoEntry) {
+        let Some((name, rest)) = folders.split_first() else {
+            self.videos.push(entry);
+            return;
+        };
+
+        let index = match self.groups.iter().position(|group| group.name == *name) {
+            Some(index) => index,
+            None => {
+                let path = if self.path.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}/{}", self.path, name)
+                };
+                self.groups.push(CatalogGroup {
+                    name: name.to_string(),
+                    path,
+                    ..Default::default()
+                });
+                self.groups.len() - 1
+            }
+        };
+        self.groups[index].insert(rest, entry);
+    }
+
+    // 分组和视频都按名称自然排序
+    fn sort(&mut self) {
+        self.groups.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+        self.videos.sort_by(|a, b| natural_cmp(&a.title, &b.title));
+        for group in self.groups.iter_mut() {
+            group.sort();
+        }
+    }
+}
+
+// 根据视频所在的相对目录构建分组树
+pub fn build_catalog(videos: &[VideoEntry]) -> CatalogGroup {
+    let mut root = CatalogGroup::default();
+    for video in videos {
+        let folders: Vec<&str> = video.folder.split('/').filter(|name| !name.is_empty()).collect();
+        root.insert(&folders, video.clone());
+    }
+    root.sort();
+    root
+}
+
+// 根据合并后的服务器索引生成目录JSON文件
+pub fn generate_catalog_json(indexes: &[ServerIndex], servers: &[ServerConfig], options: &IndexOptions) {
+    if indexes.is_empty() {
+        return;
+    }
+
+    println!("{} 生成目录JSON文件...", "📝".blue());
+
+    for (i, (index, server)) in indexes.iter().zip(servers).enumerate() {
+        let catalog_path = options.index_path(&options.catalog_name, i + 1, server);
+        let catalog = Catalog {
+            schema_version: index.schema_version,
+            generated_at: index.generated_at.clone(),
+            server: index.server.clone(),
+            root: build_catalog(&index.videos),
+        };
+        write_json(&catalog_path, &catalog);
+    }
+}
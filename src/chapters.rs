@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use colored::Colorize;
+use regex::Regex;
+
+use crate::utils::is_video_file;
+
+// 章节标记：开始时间(秒)和标题
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub start: f64,
+    pub title: String,
+}
+
+// 输出目录中的WebVTT章节文件名
+pub const CHAPTERS_FILE: &str = "chapters.vtt";
+
+// 查找视频的章节文件：优先使用 文件名.chapters.txt；文件夹中只有这一个视频(或是多Period组)时也使用文件夹中的chapters.txt
+pub fn find_sidecar(source: &Path, whole_folder: bool) -> Option<PathBuf> {
+    let folder = source.parent()?;
+    let own = folder.join(format!("{}.chapters.txt", source.file_stem()?.to_string_lossy()));
+    if own.is_file() {
+        return Some(own);
+    }
+
+    let shared = folder.join("chapters.txt");
+    if !shared.is_file() {
+        return None;
+    }
+    let videos = fs::read_dir(folder)
+        .map(|entries| entries.filter_map(|e| e.ok()).filter(|e| is_video_file(&e.path())).count())
+        .unwrap_or(0);
+    if whole_folder || videos == 1 {
+        Some(shared)
+    } else {
+        None
+    }
+}
+
+// 读取章节文件，每行一个章节，如 "00:00 开场"、"1:02:03.5 - 总结"，#开头的行为注释
+pub fn read_sidecar(path: &Path) -> Vec<Chapter> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("{} 读取章节文件失败 {}: {}", "❌".red(), path.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let line_re = Regex::new(r"^\[?(\d+(?::\d{1,2}){1,2}(?:[.,]\d+)?)\]?\s*[-–—|:]?\s*(.*)$").unwrap();
+    let mut chapters = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line_re.captures(line).and_then(|caps| Some((parse_timestamp(&caps[1])?, caps[2].trim().to_string()))) {
+            Some((start, title)) => chapters.push(Chapter { start, title }),
+            None => println!("{} 忽略无法识别的章节行: {}", "⚠️".yellow(), line),
+        }
+    }
+    normalize(chapters)
+}
+
+// 解析 [HH:]MM:SS[.mmm] 形式的时间，小数点也可以是逗号
+fn parse_timestamp(value: &str) -> Option<f64> {
+    value
+        .replace(',', ".")
+        .split(':')
+        .try_fold(0.0, |total, part| part.parse::<f64>().ok().map(|v| total * 60.0 + v))
+}
+
+// 按开始时间排序并去掉重复的开始时间，没有标题的章节按序号命名
+pub fn normalize(mut chapters: Vec<Chapter>) -> Vec<Chapter> {
+    chapters.sort_by(|a, b| a.start.total_cmp(&b.start));
+    chapters.dedup_by(|b, a| (b.start - a.start).abs() < 0.001);
+    for (index, chapter) in chapters.iter_mut().enumerate() {
+        if chapter.title.is_empty() {
+            chapter.title = format!("第{}章", index + 1);
+        }
+    }
+    chapters
+}
+
+fn format_timestamp(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// 写出WebVTT章节轨道，每个章节到下一个章节开始为止，最后一个到视频结束
+pub fn write_vtt(dash_dir: &Path, chapters: &[Chapter], duration: f64) -> bool {
+    let mut content = String::from("WEBVTT\n");
+    for (index, chapter) in chapters.iter().enumerate() {
+        let end = chapters.get(index + 1).map(|next| next.start).unwrap_or(duration).max(chapter.start);
+        // 标题中不能出现 --> 和空行
+        let title = chapter.title.replace("-->", "->").replace('\n', " ");
+        content.push_str(&format!(
+            "\n{}\n{} --> {}\n{}\n",
+            index + 1,
+            format_timestamp(chapter.start),
+            format_timestamp(end),
+            title
+        ));
+    }
+
+    let path = dash_dir.join(CHAPTERS_FILE);
+    match fs::write(&path, content) {
+        Ok(_) => {
+            println!("{} 已生成 {} 个章节: {}", "📑".blue(), chapters.len(), path.display());
+            true
+        }
+        Err(e) => {
+            println!("{} 写入章节文件失败: {}", "❌".red(), e);
+            false
+        }
+    }
+}
+
+// 读取输出目录中的WebVTT章节，用于生成索引
+pub fn read_vtt(path: &Path) -> Vec<Chapter> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    let mut chapters = Vec::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let Some((start, _)) = line.split_once(" --> ") else {
+            continue;
+        };
+        if let Some(start) = parse_timestamp(start.trim()) {
+            chapters.push(Chapter {
+                start,
+                title: lines.next().unwrap_or_default().trim().to_string(),
+            });
+        }
+    }
+    chapters
+}
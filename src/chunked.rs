@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use colored::Colorize;
+use regex::Regex;
+
+use crate::probe::{count_video_frames, probe_media};
+
+// 场景切换检测的阈值
+const SCENE_THRESHOLD: f64 = 0.4;
+// 拼接后允许的音视频时长误差（秒）
+const SYNC_TOLERANCE: f64 = 0.1;
+
+// 分块并行编码的参数
+#[derive(Debug, Clone)]
+pub struct ChunkOptions {
+    pub min_seconds: f64,
+    pub workers: usize,
+}
+
+// 一个编码分块，帧范围为 [start_frame, end_frame)；seek_time为第一帧之前半帧的时间(秒)，
+// 从这里开始解码的第一帧就是start_frame
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub index: usize,
+    pub start_frame: u64,
+    pub end_frame: u64,
+    pub seek_time: f64,
+}
+
+// 使用ffmpeg的scene滤镜检测场景切换时间点
+pub fn detect_scene_cuts(video_path: &Path) -> Vec<f64> {
+    println!("{} 正在检测场景切换: {}", "🔍".blue(), video_path.display());
+
+    let output = Command::new("ffmpeg")
+        .args([
+            "-hide_banner",
+            "-nostats",
+            "-i", video_path.to_str().unwrap(),
+            "-map", "0:v:0",
+            "-an",
+            "-vf", &format!("scale=320:-2,select='gt(scene,{})',showinfo", SCENE_THRESHOLD),
+            "-f", "null",
+            "-",
+        ])
+        .output();
+
+    match output {
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let re = Regex::new(r"Parsed_showinfo.*pts_time:\s*([0-9.]+)").unwrap();
+            let cuts: Vec<f64> = re
+                .captures_iter(&stderr)
+                .filter_map(|caps| caps[1].parse::<f64>().ok())
+                .collect();
+            println!("{} 检测到 {} 个场景切换", "🔍".blue(), cuts.len());
+            cuts
+        }
+        Err(e) => {
+            println!("{} 执行ffmpeg失败: {}", "❌".red(), e);
+            Vec::new()
+        }
+    }
+}
+
+// 在场景切换处切分，保证每个分块不短于min_seconds
+pub fn plan_chunks(cuts: &[f64], duration: f64, fps: f64, total_frames: u64, min_seconds: f64) -> Vec<Chunk> {
+    let mut boundaries = vec![0u64];
+    let mut last = 0.0;
+
+    for &cut in cuts {
+        // 最后一块也不能太短
+        if cut - last >= min_seconds && duration - cut >= min_seconds / 2.0 {
+            let frame = (cut * fps).round() as u64;
+            if frame > *boundaries.last().unwrap() && frame < total_frames {
+                boundaries.push(frame);
+                last = cut;
+            }
+        }
+    }
+    boundaries.push(total_frames);
+
+    boundaries
+        .windows(2)
+        .enumerate()
+        .map(|(index, pair)| Chunk {
+            index,
+            start_frame: pair[0],
+            end_frame: pair[1],
+            seek_time: ((pair[0] as f64 - 0.5) / fps).max(0.0),
+        })
+        .collect()
+}
+
+// 使用concat分离器无损拼接各分块，并从源文件复制音频；各分块必须由同一个编码器生成，否则序列头不一致
+pub fn concat_chunks(chunk_files: &[PathBuf], source: &Path, output: &Path, work_dir: &Path) -> bool {
+    let list_path = work_dir.join("chunks.txt");
+    let list = chunk_files
+        .iter()
+        .map(|chunk| format!("file '{}'", chunk.to_string_lossy().replace('\\', "/").replace('\'', "'\\''")))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if let Err(e) = fs::write(&list_path, list) {
+        println!("{} 写入分块列表失败: {}", "❌".red(), e);
+        return false;
+    }
+
+    println!("{} 正在拼接 {} 个分块: {}", "🔗".blue(), chunk_files.len(), output.display());
+
+    let status = Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-v", "error",
+            "-f", "concat",
+            "-safe", "0",
+            "-i", list_path.to_str().unwrap(),
+            "-i", source.to_str().unwrap(),
+            "-map", "0:v:0",
+            "-map", "1:a?",
+            "-c", "copy",
+            output.to_str().unwrap(),
+        ])
+        .status();
+
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            println!("{} 拼接分块失败，退出码: {:?}", "❌".red(), status.code());
+            false
+        }
+        Err(e) => {
+            println!("{} 执行ffmpeg失败: {}", "❌".red(), e);
+            false
+        }
+    }
+}
+
+// 检查拼接结果的帧数和音视频时长是否与源视频一致
+pub fn verify_concat(source: &Path, output: &Path) -> Result<(), String> {
+    let source_frames = count_video_frames(source).ok_or("无法统计源视频帧数")?;
+    let output_frames = count_video_frames(output).ok_or("无法统计拼接后视频帧数")?;
+    if source_frames != output_frames {
+        return Err(format!("帧数不一致: 源 {} / 拼接后 {}", source_frames, output_frames));
+    }
+
+    let source_info = probe_media(source).ok_or("无法读取源视频信息")?;
+    let output_info = probe_media(output).ok_or("无法读取拼接后视频信息")?;
+
+    let source_video = source_info.video_duration.unwrap_or(source_info.duration);
+    let output_video = output_info.video_duration.unwrap_or(output_info.duration);
+    if (source_video - output_video).abs() > SYNC_TOLERANCE {
+        return Err(format!("视频时长不一致: 源 {:.3}s / 拼接后 {:.3}s", source_video, output_video));
+    }
+
+    if let (Some(video), Some(audio)) = (output_info.video_duration, output_info.audio_duration) {
+        let source_drift = source_info.audio_duration.map(|a| a - source_video).unwrap_or(0.0);
+        if ((audio - video) - source_drift).abs() > SYNC_TOLERANCE {
+            return Err(format!("音视频不同步: 视频 {:.3}s / 音频 {:.3}s", video, audio));
+        }
+    }
+
+    println!("{} 分块拼接校验通过: {} 帧", "✅".green(), output_frames);
+    Ok(())
+}
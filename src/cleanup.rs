@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use colored::Colorize;
+
+use crate::staging::deliver;
+use crate::utils::{dir_size, format_size};
+
+// av1目录中的中间文件（转码结果、SDR版本、分块和采样文件）的保留方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntermediatePolicy {
+    // 保留在输出目录中
+    Keep,
+    // DASH打包成功后删除
+    Delete,
+    // 移到单独的目录，输出目录中只留下需要发布的文件
+    Exclude(PathBuf),
+}
+
+impl IntermediatePolicy {
+    pub fn parse(name: &str, archive_root: &Path) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "keep" => Some(IntermediatePolicy::Keep),
+            "delete" => Some(IntermediatePolicy::Delete),
+            "exclude" => Some(IntermediatePolicy::Exclude(archive_root.to_path_buf())),
+            _ => None,
+        }
+    }
+
+    // 按策略处理一个视频的av1目录，返回从输出目录中移走的字节数；path为视频相对输出根目录的路径
+    pub fn apply(&self, av1_dir: &Path, path: &str) -> u64 {
+        if !av1_dir.exists() {
+            return 0;
+        }
+
+        match self {
+            IntermediatePolicy::Keep => 0,
+            IntermediatePolicy::Delete => {
+                let size = dir_size(av1_dir);
+                match fs::remove_dir_all(av1_dir) {
+                    Ok(_) => {
+                        println!("{} 已删除中间文件 {} ({})", "🧹".blue(), av1_dir.display(), format_size(size));
+                        size
+                    }
+                    Err(e) => {
+                        println!("{} 删除中间文件失败 {}: {}", "❌".red(), av1_dir.display(), e);
+                        0
+                    }
+                }
+            }
+            IntermediatePolicy::Exclude(archive_root) => {
+                let size = dir_size(av1_dir);
+                let target = archive_root.join(path);
+                match deliver(av1_dir, &target, false) {
+                    Ok(_) => {
+                        println!("{} 中间文件已移到 {} ({})", "📦".blue(), target.display(), format_size(size));
+                        size
+                    }
+                    Err(e) => {
+                        println!("{} 移出中间文件失败 {}: {}", "❌".red(), av1_dir.display(), e);
+                        0
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::path::Path;
+use std::process::Command;
+use colored::Colorize;
+use serde_json::Value;
+
+use crate::probe::parse_rational;
+
+// 视频的色彩信息，用于在转码和DASH清单中保留HDR/10bit信号
+#[derive(Debug, Clone, Default)]
+pub struct ColorInfo {
+    pub bit_depth: u32,
+    pub primaries: Option<String>,
+    pub transfer: Option<String>,
+    pub matrix: Option<String>,
+    pub range: Option<String>,
+    pub mastering: Option<MasteringDisplay>,
+    pub content_light: Option<(u32, u32)>,
+}
+
+// 母版显示器元数据，色度坐标和亮度均为原始数值
+#[derive(Debug, Clone, Default)]
+pub struct MasteringDisplay {
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+    pub white_point: (f64, f64),
+    pub max_luminance: f64,
+    pub min_luminance: f64,
+}
+
+impl ColorInfo {
+    // PQ(HDR10)或HLG传输特性
+    pub fn is_hdr(&self) -> bool {
+        matches!(self.transfer.as_deref(), Some("smpte2084") | Some("arib-std-b67"))
+    }
+
+    pub fn is_hlg(&self) -> bool {
+        self.transfer.as_deref() == Some("arib-std-b67")
+    }
+
+    // 需要输出10bit：HDR源或本身就是高位深的源
+    pub fn needs_10bit(&self) -> bool {
+        self.is_hdr() || self.bit_depth > 8
+    }
+
+    // 简短描述，用于日志和报告
+    pub fn describe(&self) -> String {
+        let kind = match self.transfer.as_deref() {
+            Some("smpte2084") => "HDR10 (PQ)",
+            Some("arib-std-b67") => "HLG",
+            _ => "SDR",
+        };
+        format!("{} {}bit", kind, self.bit_depth)
+    }
+
+    // QSVEncC64的位深和色彩参数
+    pub fn qsv_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.needs_10bit() {
+            args.push(String::from("--output-depth"));
+            args.push(String::from("10"));
+        }
+        if !self.is_hdr() {
+            return args;
+        }
+
+        for (flag, value) in [
+            ("--colorprim", &self.primaries),
+            ("--transfer", &self.transfer),
+            ("--colormatrix", &self.matrix),
+        ] {
+            if let Some(value) = value {
+                args.push(flag.to_string());
+                args.push(value.clone());
+            }
+        }
+        args.push(String::from("--colorrange"));
+        args.push(String::from(if self.range.as_deref() == Some("pc") { "full" } else { "limited" }));
+
+        if let Some(ref mastering) = self.mastering {
+            args.push(String::from("--master-display"));
+            args.push(mastering.to_x265_string());
+        }
+        if let Some((max_cll, max_fall)) = self.content_light {
+            args.push(String::from("--max-cll"));
+            args.push(format!("{},{}", max_cll, max_fall));
+        }
+        args
+    }
+
+    // ffmpeg(libaom-av1)的位深和色彩参数
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.needs_10bit() {
+            args.push(String::from("-pix_fmt"));
+            args.push(String::from("yuv420p10le"));
+        }
+        if !self.is_hdr() {
+            return args;
+        }
+
+        for (flag, value) in [
+            ("-color_primaries", &self.primaries),
+            ("-color_trc", &self.transfer),
+            ("-colorspace", &self.matrix),
+            ("-color_range", &self.range),
+        ] {
+            if let Some(value) = value {
+                args.push(flag.to_string());
+                args.push(value.clone());
+            }
+        }
+        args
+    }
+
+    // MPD中描述色彩的属性，PQ使用EssentialProperty让不支持HDR的播放器跳过；
+    // HLG可以在SDR播放器上兼容播放，全部使用SupplementalProperty
+    pub fn mpd_properties(&self) -> Vec<String> {
+        if !self.is_hdr() {
+            return Vec::new();
+        }
+
+        let element = if self.is_hlg() { "SupplementalProperty" } else { "EssentialProperty" };
+        let mut properties = Vec::new();
+        for (scheme, code) in [
+            ("ColourPrimaries", self.primaries.as_deref().and_then(cicp_primaries)),
+            ("TransferCharacteristics", self.transfer.as_deref().and_then(cicp_transfer)),
+            ("MatrixCoefficients", self.matrix.as_deref().and_then(cicp_matrix)),
+        ] {
+            if let Some(code) = code {
+                properties.push(format!(
+                    r#"<{} schemeIdUri="urn:mpeg:mpegB:cicp:{}" value="{}"/>"#,
+                    element, scheme, code
+                ));
+            }
+        }
+        properties
+    }
+}
+
+impl MasteringDisplay {
+    // x265/QSVEncC的master-display格式，色度单位0.00002，亮度单位0.0001cd/m²
+    pub fn to_x265_string(&self) -> String {
+        let xy = |(x, y): (f64, f64)| format!("({},{})", (x * 50000.0).round(), (y * 50000.0).round());
+        format!(
+            "G{}B{}R{}WP{}L({},{})",
+            xy(self.green),
+            xy(self.blue),
+            xy(self.red),
+            xy(self.white_point),
+            (self.max_luminance * 10000.0).round(),
+            (self.min_luminance * 10000.0).round()
+        )
+    }
+}
+
+// 读取视频流的色彩信息以及首帧上的HDR元数据
+pub fn probe_color(path: &Path) -> Option<ColorInfo> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v", "error",
+            "-select_streams", "v:0",
+            "-show_streams",
+            "-show_frames",
+            "-read_intervals", "%+#1",
+            "-print_format", "json",
+            path.to_str().unwrap(),
+        ])
+        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            println!("{} 执行ffprobe失败: {}", "❌".red(), e);
+            return None;
+        }
+    };
+
+    let json: Value = serde_json::from_slice(&output.stdout).ok()?;
+    let stream = &json["streams"][0];
+    let text = |key: &str| {
+        stream[key]
+            .as_str()
+            .filter(|v| !v.is_empty() && *v != "unknown")
+            .map(|v| v.to_string())
+    };
+
+    let pix_fmt = text("pix_fmt");
+    let bit_depth = stream["bits_per_raw_sample"]
+        .as_str()
+        .and_then(|b| b.parse::<u32>().ok())
+        .unwrap_or_else(|| match pix_fmt.as_deref() {
+            Some(fmt) if fmt.contains("12") => 12,
+            Some(fmt) if fmt.contains("10") => 10,
+            _ => 8,
+        });
+
+    let mut info = ColorInfo {
+        bit_depth,
+        primaries: text("color_primaries"),
+        transfer: text("color_transfer"),
+        matrix: text("color_space"),
+        range: text("color_range"),
+        ..Default::default()
+    };
+
+    // 流级别和首帧的side data都可能携带HDR元数据
+    let side_data = stream["side_data_list"]
+        .as_array()
+        .into_iter()
+        .chain(json["frames"][0]["side_data_list"].as_array())
+        .flatten();
+    for data in side_data {
+        match data["side_data_type"].as_str() {
+            Some("Mastering display metadata") => {
+                let value = |key: &str| data[key].as_str().map(parse_rational).unwrap_or(0.0);
+                info.mastering = Some(MasteringDisplay {
+                    red: (value("red_x"), value("red_y")),
+                    green: (value("green_x"), value("green_y")),
+                    blue: (value("blue_x"), value("blue_y")),
+                    white_point: (value("white_point_x"), value("white_point_y")),
+                    max_luminance: value("max_luminance"),
+                    min_luminance: value("min_luminance"),
+                });
+            }
+            Some("Content light level metadata") => {
+                info.content_light = Some((
+                    data["max_content"].as_u64().unwrap_or(0) as u32,
+                    data["max_average"].as_u64().unwrap_or(0) as u32,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Some(info)
+}
+
+// ffmpeg色彩名称到ISO/IEC 23091-2 (CICP) 编码的映射
+fn cicp_primaries(name: &str) -> Option<u32> {
+    match name {
+        "bt709" => Some(1),
+        "bt2020" => Some(9),
+        "smpte431" => Some(11),
+        "smpte432" => Some(12),
+        _ => None,
+    }
+}
+
+fn cicp_transfer(name: &str) -> Option<u32> {
+    match name {
+        "bt709" => Some(1),
+        "smpte2084" => Some(16),
+        "arib-std-b67" => Some(18),
+        _ => None,
+    }
+}
+
+fn cicp_matrix(name: &str) -> Option<u32> {
+    match name {
+        "bt709" => Some(1),
+        "bt2020nc" => Some(9),
+        "bt2020c" => Some(10),
+        _ => None,
+    }
+}
@@ -2,13 +2,41 @@ use colored::Colorize;
 use std::fs;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use regex::Regex;
 
+use crate::color::{probe_color, ColorInfo};
+use crate::encryption::{protect_mpd, Encryption};
+use crate::hls::Hls;
+use crate::probe::probe_media;
+use crate::segmenter;
+
+// DASH封装方式：ffmpeg的dash封装，或内置的fMP4分片器
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Packager {
+    Ffmpeg,
+    Native,
+}
+
+impl Packager {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "ffmpeg" => Some(Packager::Ffmpeg),
+            "native" => Some(Packager::Native),
+            _ => None,
+        }
+    }
+}
+
 pub struct DashGenerator {
     video_path: PathBuf,
     dash_dir: PathBuf,
     seg_duration: u32,
+    sdr_path: Option<PathBuf>,
+    packager: Packager,
+    single_file: bool,
+    encryption: Option<Encryption>,
+    hls: Option<Hls>,
 }
 
 impl DashGenerator {
@@ -17,73 +45,95 @@ impl DashGenerator {
             video_path: PathBuf::from(video_path),
             dash_dir: PathBuf::from(dash_dir),
             seg_duration,
+            sdr_path: None,
+            packager: Packager::Ffmpeg,
+            single_file: false,
+            encryption: None,
+            hls: None,
         }
     }
 
+    // 附加一个SDR版本，作为单独的AdaptationSet供不支持HDR的播放器选择
+    pub fn with_sdr_rendition(mut self, sdr_path: &Path) -> Self {
+        self.sdr_path = Some(PathBuf::from(sdr_path));
+        self
+    }
+
+    pub fn with_packager(mut self, packager: Packager) -> Self {
+        self.packager = packager;
+        self
+    }
+
+    // 单文件模式：每个Representation只输出一个带sidx的fMP4，MPD按字节范围播放，
+    // 避免每个视频生成成千上万个分片文件
+    pub fn with_single_file(mut self, single_file: bool) -> Self {
+        self.single_file = single_file;
+        self
+    }
+
+    // CENC加密，由内置分片器加密样本并在MPD中写入ContentProtection
+    pub fn with_encryption(mut self, encryption: &Encryption) -> Self {
+        self.encryption = Some(encryption.clone());
+        self
+    }
+
+    // 同时生成HLS播放列表，与DASH共用分片(加密时HLS使用单独的分片)
+    pub fn with_hls(mut self, hls: &Hls) -> Self {
+        self.hls = Some(hls.clone());
+        self
+    }
+
     // 生成DASH流
     pub fn generate_dash(&self, live_dir: &Path) -> bool {
         let video_name = self.video_path.file_name().unwrap().to_string_lossy();
         println!("{} 为 {} 生成DASH流...", "🔄".yellow(), video_name);
 
-        // 确保live目录存在
-        if !live_dir.exists() {
-            if let Err(e) = fs::create_dir_all(live_dir) {
-                println!("{} 创建live目录失败: {}", "❌".red(), e);
+        if !self.prepare_live_dir(live_dir) {
+            return false;
+        }
+
+        // 内置分片器只能读取MP4容器，其他容器仍交给ffmpeg；
+        // 单文件模式的SegmentBase索引、加密和HLS由内置分片器完成，因此也优先使用内置分片器
+        if self.packager == Packager::Native || self.single_file || self.encryption.is_some() || self.hls.is_some() {
+            if self.native_supported() {
+                return self.generate_native(live_dir);
+            }
+            if self.encryption.is_some() || self.hls.is_some() {
+                println!("{} 加密和HLS需要内置分片器, 只支持MP4/MOV输入", "❌".red());
                 return false;
             }
+            println!("{} 内置分片器只支持MP4/MOV输入, 改用ffmpeg", "⚠️".yellow());
         }
 
-        // MPD文件路径
-        let mpd_path = self.dash_dir.join("main.mpd");
-        let video_path = self.dash_dir.join("live");
-
         println!("{} 使用ffmpeg生成DASH流", "🛠️".blue());
 
-        let output = Command::new("ffmpeg").args(&[
-            "-i",
-            self.video_path.to_str().unwrap(),
-            "-v", 
-            "level+debug",
-            "-c",
-            "copy",
-            "-map",
-            "0",
-            "-f",
-            "dash",
-            "-seg_duration",
-            &self.seg_duration.to_string(),
-            "-use_template",
-            "1",
-            "-use_timeline",
-            "1",
-            "-dash_segment_type",
-            "mp4",
-            "-init_seg_name",
-            &format!(
-                "{}/init_$RepresentationID$.m4s",
-                video_path.to_str().unwrap()
-            ),
-            "-media_seg_name",
-            &format!(
-                "{}/chunk_$RepresentationID$_$Number$.m4s",
-                video_path.to_str().unwrap()
-            ),
-            mpd_path.to_str().unwrap(),
-        ]).output();
+        // 有SDR版本时，HDR视频、SDR视频和音频各自成为一个AdaptationSet
+        let mut inputs = vec![String::from("-i"), self.video_path.to_str().unwrap().to_string()];
+        let mut maps = vec![String::from("-map"), String::from("0")];
+        if let Some(ref sdr_path) = self.sdr_path {
+            let has_audio = probe_media(&self.video_path).is_some_and(|info| info.audio_codec.is_some());
+            inputs.push(String::from("-i"));
+            inputs.push(sdr_path.to_str().unwrap().to_string());
+            maps = vec![
+                String::from("-map"), String::from("0:v:0"),
+                String::from("-map"), String::from("1:v:0"),
+                String::from("-map"), String::from("0:a?"),
+                String::from("-adaptation_sets"),
+                String::from(if has_audio { "id=0,streams=0 id=1,streams=1 id=2,streams=a" } else { "id=0,streams=0 id=1,streams=1" }),
+            ];
+        }
 
+        let output = Command::new("ffmpeg")
+            .args(&inputs)
+            .args(["-v", "level+debug", "-c", "copy"])
+            .args(&maps)
+            .args(self.muxer_args())
+            .output();
 
         match output {
             Ok(output) => {
                 if output.status.success() {
-                    println!("{} DASH流生成成功: {}", "✅".green(), mpd_path.display());
-
-                    // 修复MPD文件中的路径
-                    if self.fix_mpd_paths(&mpd_path) {
-                        true
-                    } else {
-                        println!("{} 修复MPD文件路径失败", "❌".red());
-                        false
-                    }
+                    self.finish()
                 } else {
                     let stderr = String::from_utf8_lossy(&output.stderr);
                     println!("{} DASH流生成失败: {}", "❌".red(), stderr);
@@ -97,6 +147,167 @@ impl DashGenerator {
         }
     }
 
+    // 单遍模式：input_args描述ffmpeg的输入和编码参数，encoder为可选的上游编码器，
+    // 其标准输出通过管道直接进入ffmpeg的DASH封装，不生成中间文件
+    pub fn generate_dash_streaming(&self, live_dir: &Path, encoder: Option<Command>, input_args: &[String]) -> bool {
+        let video_name = self.video_path.file_name().unwrap().to_string_lossy();
+        println!("{} 为 {} 单遍编码并生成DASH流...", "🔄".yellow(), video_name);
+
+        // 清空上一次失败留下的分片
+        if live_dir.exists() {
+            let _ = fs::remove_dir_all(live_dir);
+        }
+        if !self.prepare_live_dir(live_dir) {
+            return false;
+        }
+
+        let mut upstream = match encoder {
+            Some(mut encoder) => match encoder.stdout(Stdio::piped()).spawn() {
+                Ok(child) => Some(child),
+                Err(e) => {
+                    println!("{} 启动编码器失败: {}", "❌".red(), e);
+                    return false;
+                }
+            },
+            None => None,
+        };
+
+        let mut ffmpeg = Command::new("ffmpeg");
+        ffmpeg.args(["-y", "-v", "warning"]).args(input_args).args(self.muxer_args());
+        if let Some(stdout) = upstream.as_mut().and_then(|child| child.stdout.take()) {
+            ffmpeg.stdin(Stdio::from(stdout));
+        }
+        let status = ffmpeg.status();
+
+        let encoder_ok = match upstream {
+            Some(mut child) => match child.wait() {
+                Ok(status) if status.success() => true,
+                Ok(status) => {
+                    println!("{} 编码器失败，退出码: {:?}", "❌".red(), status.code());
+                    false
+                }
+                Err(e) => {
+                    println!("{} 等待编码器失败: {}", "❌".red(), e);
+                    false
+                }
+            },
+            None => true,
+        };
+
+        match status {
+            Ok(status) if status.success() && encoder_ok => self.finish(),
+            Ok(status) => {
+                println!("{} DASH流生成失败，退出码: {:?}", "❌".red(), status.code());
+                false
+            }
+            Err(e) => {
+                println!("{} 执行ffmpeg失败: {}", "❌".red(), e);
+                false
+            }
+        }
+    }
+
+    // 使用内置分片器生成DASH流，MPD中的路径已是相对路径，不需要修复
+    fn generate_native(&self, live_dir: &Path) -> bool {
+        println!("{} 使用内置分片器生成DASH流", "🛠️".blue());
+
+        let mut inputs = vec![self.video_path.clone()];
+        if let Some(ref sdr_path) = self.sdr_path {
+            inputs.push(sdr_path.clone());
+        }
+        if !segmenter::package(
+            &inputs,
+            &self.dash_dir,
+            live_dir,
+            self.seg_duration,
+            self.single_file,
+            self.encryption.as_ref(),
+            self.hls.as_ref(),
+        ) {
+            return false;
+        }
+
+        let mpd_path = self.mpd_path();
+        println!("{} DASH流生成成功: {}", "✅".green(), mpd_path.display());
+        let colored = match probe_color(&self.video_path) {
+            Some(color) if color.is_hdr() => self.add_color_properties(&mpd_path, &color),
+            _ => true,
+        };
+        colored && self.encryption.as_ref().is_none_or(|encryption| protect_mpd(&mpd_path, encryption))
+    }
+
+    fn native_supported(&self) -> bool {
+        std::iter::once(&self.video_path).chain(self.sdr_path.as_ref()).all(|path| {
+            let ext = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+            matches!(ext.as_str(), "mp4" | "m4v" | "mov")
+        })
+    }
+
+    // 确保live目录存在
+    fn prepare_live_dir(&self, live_dir: &Path) -> bool {
+        if !live_dir.exists() {
+            if let Err(e) = fs::create_dir_all(live_dir) {
+                println!("{} 创建live目录失败: {}", "❌".red(), e);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn mpd_path(&self) -> PathBuf {
+        self.dash_dir.join("main.mpd")
+    }
+
+    // ffmpeg dash封装参数，最后一项为MPD文件路径
+    fn muxer_args(&self) -> Vec<String> {
+        let live_path = self.dash_dir.join("live");
+        let mut args = vec![
+            String::from("-f"), String::from("dash"),
+            String::from("-seg_duration"), self.seg_duration.to_string(),
+            String::from("-dash_segment_type"), String::from("mp4"),
+        ];
+        if self.single_file {
+            // ffmpeg的单文件输出在MPD中使用SegmentList的字节范围
+            args.extend([
+                String::from("-use_template"), String::from("0"),
+                String::from("-use_timeline"), String::from("0"),
+                String::from("-single_file"), String::from("1"),
+                String::from("-global_sidx"), String::from("1"),
+                String::from("-single_file_name"),
+                format!("{}/stream_$RepresentationID$.mp4", live_path.to_str().unwrap()),
+            ]);
+        } else {
+            args.extend([
+                String::from("-use_template"), String::from("1"),
+                String::from("-use_timeline"), String::from("1"),
+                String::from("-init_seg_name"),
+                format!("{}/init_$RepresentationID$.m4s", live_path.to_str().unwrap()),
+                String::from("-media_seg_name"),
+                format!("{}/chunk_$RepresentationID$_$Number$.m4s", live_path.to_str().unwrap()),
+            ]);
+        }
+        args.push(self.mpd_path().to_str().unwrap().to_string());
+        args
+    }
+
+    // ffmpeg成功后修复MPD路径并写入HDR色彩属性
+    fn finish(&self) -> bool {
+        let mpd_path = self.mpd_path();
+        println!("{} DASH流生成成功: {}", "✅".green(), mpd_path.display());
+
+        // 修复MPD文件中的路径
+        if !self.fix_mpd_paths(&mpd_path) {
+            println!("{} 修复MPD文件路径失败", "❌".red());
+            return false;
+        }
+
+        // 写入HDR色彩属性
+        match probe_color(&self.video_path) {
+            Some(color) if color.is_hdr() => self.add_color_properties(&mpd_path, &color),
+            _ => true,
+        }
+    }
+
     // 修复MPD文件中的路径
     fn fix_mpd_paths(&self, mpd_path: &Path) -> bool {
         println!("{} 修复MPD文件中的路径...", "🔧".yellow());
@@ -117,8 +328,8 @@ impl DashGenerator {
         }
 
         fn fix_xml_paths(xml: &str) -> String {
-            // 匹配所有包含 ./.../live/ 模式的属性值
-            let re = Regex::new(r#"\./([^/"]+/)?live/([^"]+)"#).unwrap();
+            // 匹配所有包含 ./.../live/ 模式的属性值和BaseURL
+            let re = Regex::new(r#"\./([^/"]+/)?live/([^"<]+)"#).unwrap();
             
             re.replace_all(xml, |caps: &regex::Captures| {
                 format!("live/{}", &caps[2])
@@ -156,4 +367,37 @@ impl DashGenerator {
 
         true
     }
+
+    // 在第一个视频AdaptationSet中写入CICP色彩属性
+    fn add_color_properties(&self, mpd_path: &Path, color: &ColorInfo) -> bool {
+        let content = match fs::read_to_string(mpd_path) {
+            Ok(content) => content,
+            Err(e) => {
+                println!("{} 读取MPD文件失败: {}", "❌".red(), e);
+                return false;
+            }
+        };
+
+        let re = Regex::new(r#"<AdaptationSet[^>]*contentType="video"[^>]*>"#).unwrap();
+        let Some(adaptation_set) = re.find(&content) else {
+            println!("{} MPD中没有视频AdaptationSet", "⚠️".yellow());
+            return true;
+        };
+
+        let properties: String = color
+            .mpd_properties()
+            .iter()
+            .map(|property| format!("\n\t\t\t{}", property))
+            .collect();
+        let mut fixed_content = content.clone();
+        fixed_content.insert_str(adaptation_set.end(), &properties);
+
+        if let Err(e) = fs::write(mpd_path, fixed_content) {
+            println!("{} 写入MPD文件失败: {}", "❌".red(), e);
+            return false;
+        }
+
+        println!("{} MPD已写入{}色彩属性", "🎨".green(), color.describe());
+        true
+    }
 }
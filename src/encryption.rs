@@ -0,0 +1,576 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use base64::Engine;
+use colored::Colorize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::json_generator::timestamp;
+use crate::mp4::clearkey_pssh;
+
+// W3C通用ClearKey的系统ID，写入pssh
+pub const CLEARKEY_SYSTEM_ID: [u8; 16] = [
+    0x10, 0x77, 0xef, 0xec, 0xc0, 0xb2, 0x4d, 0x02, 0xac, 0xe3, 0x3c, 0x1e, 0x52, 0xe2, 0xfb, 0x4b,
+];
+
+// CENC加密方案：cenc为AES-CTR，cbcs为AES-CBC按模式加密(视频每10块加密1块)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    Cenc,
+    Cbcs,
+}
+
+impl Scheme {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "cenc" => Some(Scheme::Cenc),
+            "cbcs" => Some(Scheme::Cbcs),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scheme::Cenc => "cenc",
+            Scheme::Cbcs => "cbcs",
+        }
+    }
+
+    pub fn fourcc(&self) -> &'static [u8; 4] {
+        match self {
+            Scheme::Cenc => b"cenc",
+            Scheme::Cbcs => b"cbcs",
+        }
+    }
+}
+
+// 内容密钥：16字节的KID和AES-128密钥
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentKey {
+    pub kid: [u8; 16],
+    pub key: [u8; 16],
+}
+
+impl ContentKey {
+    pub fn generate() -> Self {
+        ContentKey {
+            kid: random_block(),
+            key: random_block(),
+        }
+    }
+
+    // 解析十六进制的KID和密钥，KID可以带UUID的连字符
+    pub fn parse(kid: &str, key: &str) -> Result<Self, String> {
+        Ok(ContentKey {
+            kid: parse_block(kid).ok_or_else(|| format!("无效的KID: {}", kid))?,
+            key: parse_block(key).ok_or_else(|| format!("无效的密钥: {}", key))?,
+        })
+    }
+
+    // MPD中cenc:default_KID使用的UUID格式
+    pub fn kid_uuid(&self) -> String {
+        let hex = hex::encode(self.kid);
+        format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+    }
+}
+
+pub fn random_block() -> [u8; 16] {
+    let mut block = [0u8; 16];
+    getrandom::getrandom(&mut block).expect("无法获取系统随机数");
+    block
+}
+
+fn parse_block(value: &str) -> Option<[u8; 16]> {
+    hex::decode(value.replace('-', "")).ok()?.try_into().ok()
+}
+
+// 打包时的加密设置：方案、密钥和可选的ClearKey许可证地址
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    pub scheme: Scheme,
+    pub key: ContentKey,
+    pub license_url: Option<String>,
+}
+
+// 命令行指定的加密选项，每个视频的密钥在处理时从密钥库取得
+#[derive(Clone)]
+pub struct EncryptionOptions {
+    pub scheme: Scheme,
+    pub key: Option<ContentKey>,
+    pub license_url: Option<String>,
+    pub keystore: Arc<Mutex<Keystore>>,
+}
+
+impl EncryptionOptions {
+    // 输出路径对应的加密设置，密钥库写入失败时返回None
+    pub fn for_video(&self, path: &str) -> Option<Encryption> {
+        let key = self.keystore.lock().unwrap().key_for(path, self.scheme, self.key)?;
+        Some(Encryption {
+            scheme: self.scheme,
+            key,
+            license_url: self.license_url.clone(),
+        })
+    }
+}
+
+// 一个轨道的样本加密参数，每个轨道使用独立的随机IV
+pub struct Protection {
+    pub scheme: Scheme,
+    pub kid: [u8; 16],
+    // cenc为每个样本IV的起始值(前8字节)，cbcs为固定IV
+    pub iv: [u8; 16],
+    // cbcs的(加密块数, 跳过块数)，(0, 0)表示加密全部完整的块
+    pub pattern: (u8, u8),
+    cipher: Aes128,
+}
+
+impl Protection {
+    pub fn new(encryption: &Encryption, video: bool) -> Self {
+        let mut iv = random_block();
+        if encryption.scheme == Scheme::Cenc {
+            iv[8..].fill(0);
+        }
+        Protection {
+            scheme: encryption.scheme,
+            kid: encryption.key.kid,
+            iv,
+            pattern: if encryption.scheme == Scheme::Cbcs && video { (1, 9) } else { (0, 0) },
+            cipher: Aes128::new(GenericArray::from_slice(&encryption.key.key)),
+        }
+    }
+
+    // cenc每个样本的8字节IV，由起始值加上解码时间得到，同一轨道内不会重复；cbcs使用固定IV
+    pub fn sample_iv(&self, dts: u64) -> Option<[u8; 8]> {
+        match self.scheme {
+            Scheme::Cenc => Some(u64::from_be_bytes(self.iv[..8].try_into().unwrap()).wrapping_add(dts).to_be_bytes()),
+            Scheme::Cbcs => None,
+        }
+    }
+
+    // 原地加密一个样本，subsamples为(明文字节数, 加密字节数)，为空时整个样本加密
+    pub fn encrypt(&self, data: &mut [u8], dts: u64, subsamples: &[(u16, u32)]) {
+        let mut ranges = Vec::new();
+        if subsamples.is_empty() {
+            ranges.push(0..data.len());
+        } else {
+            let mut position = 0;
+            for (clear, protected) in subsamples {
+                position += *clear as usize;
+                ranges.push(position..position + *protected as usize);
+                position += *protected as usize;
+            }
+        }
+
+        match self.scheme {
+            // 计数器在样本内的各个加密区间之间连续
+            Scheme::Cenc => {
+                let mut counter = [0u8; 16];
+                counter[..8].copy_from_slice(&self.sample_iv(dts).unwrap());
+                let mut keystream = [0u8; 16];
+                let mut used = keystream.len();
+                for range in ranges {
+                    for byte in &mut data[range] {
+                        if used == keystream.len() {
+                            keystream = counter;
+                            self.cipher.encrypt_block(GenericArray::from_mut_slice(&mut keystream));
+                            let next = u64::from_be_bytes(counter[8..].try_into().unwrap()).wrapping_add(1);
+                            counter[8..].copy_from_slice(&next.to_be_bytes());
+                            used = 0;
+                        }
+                        *byte ^= keystream[used];
+                        used += 1;
+                    }
+                }
+            }
+            // 每个加密区间从固定IV重新开始CBC，末尾不足一块的部分保持明文
+            Scheme::Cbcs => {
+                let (crypt, skip) = (self.pattern.0 as usize, self.pattern.1 as usize);
+                for range in ranges {
+                    let mut chain = self.iv;
+                    for (index, block) in data[range].chunks_exact_mut(16).enumerate() {
+                        if crypt > 0 && index % (crypt + skip) >= crypt {
+                            continue;
+                        }
+                        for (byte, previous) in block.iter_mut().zip(chain) {
+                            *byte ^= previous;
+                        }
+                        self.cipher.encrypt_block(GenericArray::from_mut_slice(block));
+                        chain.copy_from_slice(block);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// AV1样本的子样本划分：OBU头和长度字段保持明文；帧和tile group OBU的负载从末尾起按16字节整数倍加密，
+// 开头不足一块的部分保持明文；序列头、元数据等其他OBU整体明文
+pub fn av1_subsamples(data: &[u8]) -> Vec<(u16, u32)> {
+    let mut subsamples = Vec::new();
+    let mut clear = 0;
+    let mut position = 0;
+    while position < data.len() {
+        let header = data[position];
+        let obu_type = (header >> 3) & 0x0f;
+        let mut header_len = 1 + ((header >> 2) & 1) as usize;
+        let end = if header & 0x02 != 0 {
+            match read_leb128(data.get(position + header_len..).unwrap_or_default()) {
+                Some((size, len)) => {
+                    header_len += len;
+                    (position + header_len).saturating_add(size as usize).min(data.len())
+                }
+                None => data.len(),
+            }
+        } else {
+            data.len()
+        };
+
+        let payload = end.saturating_sub(position + header_len);
+        let protected = if matches!(obu_type, 4 | 6) { payload / 16 * 16 } else { 0 };
+        clear += end - position - protected;
+        if protected > 0 {
+            push_subsample(&mut subsamples, clear, protected);
+            clear = 0;
+        }
+        position = end;
+    }
+    if clear > 0 {
+        push_subsample(&mut subsamples, clear, 0);
+    }
+    subsamples
+}
+
+fn read_leb128(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+// 子样本的明文长度是16位，过长时拆成多个只有明文的子样本
+fn push_subsample(subsamples: &mut Vec<(u16, u32)>, mut clear: usize, protected: usize) {
+    while clear > u16::MAX as usize {
+        subsamples.push((u16::MAX, 0));
+        clear -= u16::MAX as usize;
+    }
+    subsamples.push((clear as u16, protected as u32));
+}
+
+// 在MPD的每个AdaptationSet中写入ContentProtection：加密方案和默认KID，以及ClearKey的许可证地址和pssh
+pub fn protect_mpd(mpd_path: &Path, encryption: &Encryption) -> bool {
+    let content = match fs::read_to_string(mpd_path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("{} 读取MPD文件失败: {}", "❌".red(), e);
+            return false;
+        }
+    };
+
+    let pssh = base64::engine::general_purpose::STANDARD.encode(clearkey_pssh(&encryption.key.kid));
+    let license = encryption
+        .license_url
+        .as_deref()
+        .map(|url| format!("\n\t\t\t\t<dashif:Laurl>{}</dashif:Laurl>", crate::playlist::escape_xml(url)))
+        .unwrap_or_default();
+    let elements = format!(
+        concat!(
+            "\n\t\t\t<ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" value=\"{}\" cenc:default_KID=\"{}\" />",
+            "\n\t\t\t<ContentProtection schemeIdUri=\"urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e\" value=\"ClearKey1.0\">{}",
+            "\n\t\t\t</ContentProtection>",
+            "\n\t\t\t<ContentProtection schemeIdUri=\"urn:uuid:1077efec-c0b2-4d02-ace3-3c1e52e2fb4b\" value=\"ClearKey1.0\">",
+            "\n\t\t\t\t<cenc:pssh>{}</cenc:pssh>{}",
+            "\n\t\t\t</ContentProtection>",
+        ),
+        encryption.scheme.name(),
+        encryption.key.kid_uuid(),
+        license,
+        pssh,
+        license,
+    );
+
+    let content = Regex::new(r"<AdaptationSet\b[^>]*>")
+        .unwrap()
+        .replace_all(&content, |caps: &regex::Captures| format!("{}{}", &caps[0], elements))
+        .to_string();
+    let content = Regex::new(r"<MPD\b[^>]*?>")
+        .unwrap()
+        .replace(&content, |caps: &regex::Captures| {
+            let mut root = caps[0].trim_end_matches('>').to_string();
+            if !root.contains("xmlns:cenc=") {
+                root.push_str(" xmlns:cenc=\"urn:mpeg:cenc:2013\"");
+            }
+            if !license.is_empty() && !root.contains("xmlns:dashif=") {
+                root.push_str(" xmlns:dashif=\"https://dashif.org/CPS\"");
+            }
+            root + ">"
+        })
+        .to_string();
+
+    if let Err(e) = fs::write(mpd_path, content) {
+        println!("{} 写入MPD文件失败: {}", "❌".red(), e);
+        return false;
+    }
+    println!("{} MPD已写入{}加密信息, KID {}", "🔐".green(), encryption.scheme.name(), encryption.key.kid_uuid());
+    true
+}
+
+// 密钥库中的一条记录，按输出路径保存
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyEntry {
+    path: String,
+    scheme: String,
+    kid: String,
+    key: String,
+    updated_at: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct KeystoreFile {
+    keys: Vec<KeyEntry>,
+}
+
+// 密钥库：记录每个输出目录使用的密钥，再次处理同一个视频时沿用原来的密钥；
+// 应放在发布目录之外
+pub struct Keystore {
+    path: PathBuf,
+    file: KeystoreFile,
+}
+
+impl Keystore {
+    pub fn open(path: &Path) -> Option<Self> {
+        let file = match fs::read_to_string(path) {
+            Ok(content) => match serde_json::from_str::<KeystoreFile>(&content) {
+                Ok(file) => file,
+                Err(e) => {
+                    println!("{} 无法解析密钥库 {}: {}", "❌".red(), path.display(), e);
+                    return None;
+                }
+            },
+            Err(_) => KeystoreFile::default(),
+        };
+        Some(Keystore {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    // 返回输出路径使用的密钥：指定了密钥时使用指定的，否则沿用已有记录或生成新密钥，并立即保存
+    pub fn key_for(&mut self, path: &str, scheme: Scheme, supplied: Option<ContentKey>) -> Option<ContentKey> {
+        let existing = self
+            .file
+            .keys
+            .iter()
+            .find(|entry| entry.path == path)
+            .and_then(|entry| ContentKey::parse(&entry.kid, &entry.key).ok());
+        let key = supplied.or(existing).unwrap_or_else(ContentKey::generate);
+
+        self.file.keys.retain(|entry| entry.path != path);
+        self.file.keys.push(KeyEntry {
+            path: path.to_string(),
+            scheme: scheme.name().to_string(),
+            kid: hex::encode(key.kid),
+            key: hex::encode(key.key),
+            updated_at: timestamp(),
+        });
+        self.file.keys.sort_by(|a, b| a.path.cmp(&b.path));
+
+        if self.save() {
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    // 先写临时文件再替换，避免中断时丢失密钥
+    fn save(&self) -> bool {
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            let _ = fs::create_dir_all(parent);
+        }
+        let temp_path = self.path.with_extension("json.tmp");
+        let result = serde_json::to_string_pretty(&self.file)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&temp_path, json).map_err(|e| e.to_string()))
+            .and_then(|_| fs::rename(&temp_path, &self.path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            println!("{} 写入密钥库失败 {}: {}", "❌".red(), self.path.display(), e);
+            return false;
+        }
+        true
+    }
+
+    // ClearKey许可证文件，位于密钥库旁：EME ClearKey的许可证响应格式(JWK集合)，
+    // 可以由许可证服务器直接返回，也可以作为播放器的clearkeys配置
+    pub fn license_path(&self) -> PathBuf {
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        self.path.with_file_name(format!("{}.clearkey.json", stem))
+    }
+
+    pub fn write_license(&self) -> bool {
+        let encode = |hex_value: &str| {
+            hex::decode(hex_value)
+                .map(|bytes| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+                .unwrap_or_default()
+        };
+        let mut keys: Vec<_> = self.file.keys.iter().map(|entry| (entry.kid.clone(), entry.key.clone())).collect();
+        keys.sort();
+        keys.dedup_by(|a, b| a.0 == b.0);
+
+        let license = json!({
+            "keys": keys
+                .iter()
+                .map(|(kid, key)| json!({ "kty": "oct", "kid": encode(kid), "k": encode(key) }))
+                .collect::<Vec<_>>(),
+            "type": "temporary",
+        });
+        let path = self.license_path();
+        match serde_json::to_string_pretty(&license).map(|json| fs::write(&path, json)) {
+            Ok(Ok(_)) => {
+                println!("{} ClearKey许可证: {} ({}个密钥)", "🔑".green(), path.display(), keys.len());
+                true
+            }
+            Ok(Err(e)) => {
+                println!("{} 写入ClearKey许可证失败: {}", "❌".red(), e);
+                false
+            }
+            Err(e) => {
+                println!("{} ClearKey许可证序列化失败: {}", "❌".red(), e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NIST SP 800-38A F.2.1 / F.5.1 的AES-128密钥和明文块
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const PLAIN_1: &str = "6bc1bee22e409f96e93d7e117393172a";
+    const PLAIN_2: &str = "ae2d8a571e03ac9c9eb76fac45af8e51";
+
+    fn protection(scheme: Scheme, iv: &str, pattern: (u8, u8)) -> Protection {
+        let key: [u8; 16] = hex::decode(KEY).unwrap().try_into().unwrap();
+        let mut iv_block = [0u8; 16];
+        let iv = hex::decode(iv).unwrap();
+        iv_block[..iv.len()].copy_from_slice(&iv);
+        Protection {
+            scheme,
+            kid: [0u8; 16],
+            iv: iv_block,
+            pattern,
+            cipher: Aes128::new(GenericArray::from_slice(&key)),
+        }
+    }
+
+    // 期望值由OpenSSL的AES-128-CTR计算，计数器为 (IV起始值 + dts) || 0^8
+    #[test]
+    fn cenc_whole_sample() {
+        let protection = protection(Scheme::Cenc, "f0f1f2f3f4f5f6f7", (0, 0));
+        assert_eq!(hex::encode(protection.sample_iv(0x10).unwrap()), "f0f1f2f3f4f5f707");
+
+        let mut data: Vec<u8> = (100..140).collect();
+        protection.encrypt(&mut data, 0x10, &[]);
+        assert_eq!(
+            hex::encode(&data),
+            "b1aaad656f4c9fc14c53688f541e0eefdcbf18cf5fc6b7082f81199cb137a901a171910e448fd9ba"
+        );
+    }
+
+    // 计数器在同一样本的各个加密区间之间连续，明文区间不消耗密钥流
+    #[test]
+    fn cenc_subsamples_continue_counter() {
+        let protection = protection(Scheme::Cenc, "f0f1f2f3f4f5f6f7", (0, 0));
+        let mut data: Vec<u8> = (0..58).collect();
+        protection.encrypt(&mut data, 0x10, &[(5, 20), (3, 30)]);
+        assert_eq!(
+            hex::encode(&data),
+            concat!(
+                "0001020304d0c9cc0a0e2ffea62d3009f0357d6f88bddc79a0191a1b3ba2d36c",
+                "73dd45c015930da50ddd3da2e02b7d1e4d4e81801b466680d8ca"
+            )
+        );
+    }
+
+    // 1:9模式：第0块和第10块按CBC加密(链接上一个加密块)，其余块和末尾不足一块的部分保持明文。
+    // 第0、10块的明文取NIST的P1、P2，密文应为F.2.1的C1、C2
+    #[test]
+    fn cbcs_pattern_leaves_skipped_blocks_and_tail_clear() {
+        let protection = protection(Scheme::Cbcs, "000102030405060708090a0b0c0d0e0f", (1, 9));
+        let mut plain = hex::decode(PLAIN_1).unwrap();
+        plain.extend(vec![0x11; 16 * 9]);
+        plain.extend(hex::decode(PLAIN_2).unwrap());
+        plain.extend(vec![0x22; 16 + 7]);
+
+        let mut data = plain.clone();
+        protection.encrypt(&mut data, 0, &[]);
+        assert_eq!(hex::encode(&data[..16]), "7649abac8119b246cee98e9b12e9197d");
+        assert_eq!(&data[16..160], &plain[16..160]);
+        assert_eq!(hex::encode(&data[160..176]), "5086cb9b507219ee95db113a917678b2");
+        assert_eq!(&data[176..], &plain[176..]);
+        assert_eq!(protection.sample_iv(0), None);
+    }
+
+    // 每个加密区间从固定IV重新开始
+    #[test]
+    fn cbcs_restarts_chain_per_subsample() {
+        let protection = protection(Scheme::Cbcs, "000102030405060708090a0b0c0d0e0f", (1, 9));
+        let mut data = vec![0x33; 4];
+        data.extend(hex::decode(PLAIN_1).unwrap());
+        data.extend(vec![0x44; 3]);
+        data.extend(hex::decode(PLAIN_1).unwrap());
+
+        protection.encrypt(&mut data, 0, &[(4, 16), (3, 16)]);
+        assert_eq!(hex::encode(&data[4..20]), "7649abac8119b246cee98e9b12e9197d");
+        assert_eq!(hex::encode(&data[23..39]), "7649abac8119b246cee98e9b12e9197d");
+        assert_eq!(&data[..4], &[0x33; 4]);
+        assert_eq!(&data[20..23], &[0x44; 3]);
+    }
+
+    fn obu(header: u8, payload_len: usize) -> Vec<u8> {
+        let mut data = vec![header];
+        let mut size = payload_len;
+        loop {
+            let byte = (size & 0x7f) as u8;
+            size >>= 7;
+            if size == 0 {
+                data.push(byte);
+                break;
+            }
+            data.push(byte | 0x80);
+        }
+        data.extend(vec![0xaa; payload_len]);
+        data
+    }
+
+    // 时间分隔符、序列头整体明文；帧OBU的负载开头不足一块的部分明文，其余加密
+    #[test]
+    fn av1_frame_obu() {
+        let mut data = obu(0x12, 0);
+        data.extend(obu(0x0a, 10));
+        data.extend(obu(0x32, 100));
+        assert_eq!(av1_subsamples(&data), [(20, 96)]);
+    }
+
+    // 超过65535字节的明文拆成只有明文的子样本，末尾的填充OBU单独作为明文子样本
+    #[test]
+    fn av1_long_clear_run() {
+        let mut data = obu(0x2a, 70000);
+        data.extend(obu(0x32, 33));
+        data.extend(obu(0x7a, 5));
+        let subsamples = av1_subsamples(&data);
+        assert_eq!(subsamples, [(u16::MAX, 0), (4472, 32), (7, 0)]);
+
+        let total: usize = subsamples.iter().map(|(clear, protected)| *clear as usize + *protected as usize).sum();
+        assert_eq!(total, data.len());
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use colored::Colorize;
+
+use crate::encryption::{random_block, ContentKey, Encryption, Protection, Scheme};
+use crate::mp4::TrackKind;
+use crate::segmenter::{Manifest, Representation};
+use crate::utils::uri_encode;
+
+// HLS分片加密方式：AES-128加密整个分片文件，SAMPLE-AES按cbcs加密样本
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HlsMethod {
+    Aes128,
+    SampleAes,
+}
+
+impl HlsMethod {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "aes-128" => Some(HlsMethod::Aes128),
+            "sample-aes" => Some(HlsMethod::SampleAes),
+            _ => None,
+        }
+    }
+
+    // 播放列表中EXT-X-KEY的METHOD
+    pub fn name(&self) -> &'static str {
+        match self {
+            HlsMethod::Aes128 => "AES-128",
+            HlsMethod::SampleAes => "SAMPLE-AES",
+        }
+    }
+}
+
+// HLS加密选项：rotation为每个密钥使用的分片数(0为不轮换)，key_uri为密钥地址模板，
+// 支持{server}(--serve的基础URL)、{path}(视频输出路径)和{key}(密钥文件名)
+#[derive(Debug, Clone)]
+pub struct HlsEncryption {
+    pub method: HlsMethod,
+    pub rotation: usize,
+    pub key_uri: String,
+    pub key_dir: PathBuf,
+}
+
+// 命令行指定的HLS选项
+#[derive(Debug, Clone)]
+pub struct HlsOptions {
+    pub encryption: Option<HlsEncryption>,
+    pub servers: Vec<String>,
+}
+
+impl HlsOptions {
+    pub fn for_video(&self, path: &str) -> Hls {
+        Hls {
+            encryption: self.encryption.clone(),
+            servers: self.servers.clone(),
+            path: path.to_string(),
+        }
+    }
+}
+
+// 一个视频的HLS输出设置，path为输出目录相对输出路径的位置
+#[derive(Debug, Clone)]
+pub struct Hls {
+    pub encryption: Option<HlsEncryption>,
+    pub servers: Vec<String>,
+    pub path: String,
+}
+
+// 一个HLS密钥和它的IV：SAMPLE-AES的IV与初始化分片tenc中的固定IV一致，写入EXT-X-KEY；
+// AES-128不使用这个IV，每个分片以媒体序列号作为IV
+#[derive(Debug, Clone, Copy)]
+pub struct HlsKey {
+    pub key: ContentKey,
+    pub iv: [u8; 16],
+}
+
+// 一个视频的全部HLS密钥，按轮换顺序生成，所有Representation的同一段分片使用同一个密钥
+#[derive(Default)]
+pub struct KeyRing {
+    keys: Vec<HlsKey>,
+}
+
+impl KeyRing {
+    pub fn get(&mut self, index: usize) -> HlsKey {
+        while self.keys.len() <= index {
+            self.keys.push(HlsKey {
+                key: ContentKey::generate(),
+                iv: random_block(),
+            });
+        }
+        self.keys[index]
+    }
+}
+
+impl HlsKey {
+    // SAMPLE-AES的fMP4分片按cbcs加密，固定IV与EXT-X-KEY中的一致
+    pub fn protection(&self, video: bool) -> Protection {
+        let encryption = Encryption {
+            scheme: Scheme::Cbcs,
+            key: self.key,
+            license_url: None,
+        };
+        let mut protection = Protection::new(&encryption, video);
+        protection.iv = self.iv;
+        protection
+    }
+
+    // AES-128：整个分片按CBC加密，PKCS7填充。EXT-X-KEY没有IV属性时播放器以分片的媒体序列号
+    // (128位大端)作为IV，每个分片的IV都不同
+    pub fn encrypt_segment(&self, data: &[u8], sequence: u64) -> Vec<u8> {
+        let cipher = Aes128::new(GenericArray::from_slice(&self.key.key));
+        let padding = 16 - data.len() % 16;
+        let mut encrypted = data.to_vec();
+        encrypted.resize(data.len() + padding, padding as u8);
+
+        let mut chain = (sequence as u128).to_be_bytes();
+        for block in encrypted.chunks_exact_mut(16) {
+            for (byte, previous) in block.iter_mut().zip(chain) {
+                *byte ^= previous;
+            }
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            chain.copy_from_slice(block);
+        }
+        encrypted
+    }
+}
+
+// 加密时HLS使用单独的分片，DASH的分片保持不变
+pub fn init_name(id: usize, encrypted: bool) -> String {
+    if encrypted {
+        format!("hls_init_{}.m4s", id)
+    } else {
+        format!("init_{}.m4s", id)
+    }
+}
+
+pub fn segment_name(id: usize, number: usize, encrypted: bool) -> String {
+    if encrypted {
+        format!("hls_{}_{}.m4s", id, number)
+    } else {
+        format!("chunk_{}_{}.m4s", id, number)
+    }
+}
+
+fn key_name(index: usize) -> String {
+    format!("key_{}.key", index + 1)
+}
+
+impl Hls {
+    // 第index个分片(从0开始)使用的密钥序号
+    pub fn key_index(&self, index: usize) -> usize {
+        match self.encryption {
+            Some(ref encryption) if encryption.rotation > 0 => index / encryption.rotation,
+            _ => 0,
+        }
+    }
+
+    // 每个服务器的密钥地址不同时为每个服务器生成一套播放列表，第一个服务器使用main.m3u8，
+    // 其余使用main_N.m3u8；没有--serve时{server}为空
+    fn variants(&self) -> Vec<(String, String)> {
+        let per_server = self.encryption.as_ref().is_some_and(|e| e.key_uri.contains("{server}"));
+        let servers: Vec<&str> = if per_server && !self.servers.is_empty() {
+            self.servers.iter().map(String::as_str).collect()
+        } else {
+            vec![self.servers.first().map(String::as_str).unwrap_or("")]
+        };
+        servers
+            .into_iter()
+            .enumerate()
+            .map(|(index, server)| {
+                let suffix = if index == 0 { String::new() } else { format!("_{}", index + 1) };
+                (suffix, server.trim_end_matches('/').to_string())
+            })
+            .collect()
+    }
+
+    fn key_uri(&self, template: &str, server: &str, index: usize) -> String {
+        template
+            .replace("{server}", server)
+            .replace("{path}", &uri_encode(&self.path, false))
+            .replace("{key}", &key_name(index))
+    }
+
+    // 密钥写入密钥目录下与输出路径相同的位置，每个文件16字节
+    fn save_keys(&self, encryption: &HlsEncryption, keys: &KeyRing) -> bool {
+        let dir = encryption.key_dir.join(&self.path);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            println!("{} 创建HLS密钥目录失败: {}", "❌".red(), e);
+            return false;
+        }
+        for (index, key) in keys.keys.iter().enumerate() {
+            if let Err(e) = fs::write(dir.join(key_name(index)), key.key.key) {
+                println!("{} 写入HLS密钥失败: {}", "❌".red(), e);
+                return false;
+            }
+        }
+        println!("{} 已写入 {} 个HLS密钥: {}", "🔑".blue(), keys.keys.len(), dir.display());
+        true
+    }
+
+    // 写出主播放列表和每个Representation的媒体播放列表
+    pub fn write_playlists(&self, dash_dir: &Path, live_dir: &Path, manifest: &Manifest, keys: &KeyRing) -> bool {
+        if let Some(ref encryption) = self.encryption {
+            if !self.save_keys(encryption, keys) {
+                return false;
+            }
+        }
+
+        let variants = self.variants();
+        for (suffix, server) in &variants {
+            for representation in &manifest.representations {
+                let path = live_dir.join(format!("stream_{}{}.m3u8", representation.id, suffix));
+                if let Err(e) = fs::write(&path, self.render_media(representation, server, keys)) {
+                    println!("{} 写入HLS播放列表失败: {}", "❌".red(), e);
+                    return false;
+                }
+            }
+            let path = dash_dir.join(format!("main{}.m3u8", suffix));
+            if let Err(e) = fs::write(&path, render_master(manifest, suffix)) {
+                println!("{} 写入HLS播放列表失败: {}", "❌".red(), e);
+                return false;
+            }
+        }
+        println!("{} HLS播放列表已生成: {}套", "✅".green(), variants.len());
+        true
+    }
+
+    fn render_media(&self, representation: &Representation, server: &str, keys: &KeyRing) -> String {
+        let encrypted = self.encryption.is_some();
+        let timescale = representation.timescale as f64;
+        let target = representation.segments.iter().map(|(_, duration)| *duration as f64 / timescale).fold(0.0, f64::max);
+
+        let mut m3u8 = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+        m3u8.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", target.ceil().max(1.0) as u64));
+        m3u8.push_str("#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n");
+        // 初始化分片在第一个EXT-X-KEY之前，不加密
+        m3u8.push_str(&format!("#EXT-X-MAP:URI=\"{}\"\n", init_name(representation.id, encrypted)));
+
+        let mut current_key = None;
+        for (index, (_, duration)) in representation.segments.iter().enumerate() {
+            if let Some(ref encryption) = self.encryption {
+                let key_index = self.key_index(index);
+                if current_key != Some(key_index) {
+                    let mut line = format!(
+                        "#EXT-X-KEY:METHOD={},URI=\"{}\"",
+                        encryption.method.name(),
+                        self.key_uri(&encryption.key_uri, server, key_index)
+                    );
+                    // AES-128省略IV，使用媒体序列号
+                    if encryption.method == HlsMethod::SampleAes {
+                        line.push_str(&format!(",IV=0x{}", hex::encode(keys.keys[key_index].iv)));
+                    }
+                    m3u8.push_str(&line);
+                    m3u8.push('\n');
+                    current_key = Some(key_index);
+                }
+            }
+            m3u8.push_str(&format!("#EXTINF:{:.3},\n", *duration as f64 / timescale));
+            m3u8.push_str(&segment_name(representation.id, index + 1, encrypted));
+            m3u8.push('\n');
+        }
+        m3u8.push_str("#EXT-X-ENDLIST\n");
+        m3u8
+    }
+}
+
+// 主播放列表：音频作为EXT-X-MEDIA，每个视频Representation一个EXT-X-STREAM-INF
+fn render_master(manifest: &Manifest, suffix: &str) -> String {
+    let audio: Vec<&Representation> = manifest.representations.iter().filter(|r| r.kind == TrackKind::Audio).collect();
+    let mut m3u8 = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+
+    for (index, representation) in audio.iter().enumerate() {
+        let language = representation.language.as_deref().unwrap_or("und");
+        let default = if index == 0 { "YES" } else { "NO" };
+        m3u8.push_str(&format!(
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"{}\",LANGUAGE=\"{}\",DEFAULT={},AUTOSELECT=YES,CHANNELS=\"{}\",URI=\"live/stream_{}{}.m3u8\"\n",
+            language, language, default, representation.channels, representation.id, suffix
+        ));
+    }
+
+    let audio_bandwidth = audio.iter().map(|r| r.bandwidth).max().unwrap_or(0);
+    for representation in manifest.representations.iter().filter(|r| r.kind == TrackKind::Video) {
+        let mut codecs = representation.codecs.clone();
+        if let Some(first) = audio.first() {
+            codecs.push(',');
+            codecs.push_str(&first.codecs);
+        }
+        let mut attributes = format!(
+            "BANDWIDTH={},CODECS=\"{}\",RESOLUTION={}x{}",
+            representation.bandwidth + audio_bandwidth,
+            codecs,
+            representation.width,
+            representation.height
+        );
+        if let Some(frame_rate) = representation.frame_rate.as_deref().and_then(parse_frame_rate) {
+            attributes.push_str(&format!(",FRAME-RATE={:.3}", frame_rate));
+        }
+        if !audio.is_empty() {
+            attributes.push_str(",AUDIO=\"audio\"");
+        }
+        m3u8.push_str(&format!("#EXT-X-STREAM-INF:{}\nlive/stream_{}{}.m3u8\n", attributes, representation.id, suffix));
+    }
+    m3u8
+}
+
+// MPD中的帧率如 24000/1001
+fn parse_frame_rate(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => Some(numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?),
+        None => value.parse().ok(),
+    }
+}
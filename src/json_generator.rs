@@ -1,54 +1,548 @@
-use std::path::PathBuf;
-use std::fs::File;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
 use std::io::Write;
 use colored::Colorize;
-use serde_json::json;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
 
-// 生成服务器JSON文件
-pub fn generate_server_json(processed_videos: &[(PathBuf, PathBuf)], servers: &[String]) {
-    if servers.is_empty() || processed_videos.is_empty() {
-        return;
+use crate::chapters::{read_vtt, Chapter, CHAPTERS_FILE};
+use crate::probe::probe_media;
+use crate::utils::natural_cmp;
+
+// 服务器JSON的结构版本，旧版本是只含title和url的数组
+pub const SCHEMA_VERSION: u32 = 2;
+
+// 处理完成的视频记录，在输出目录移动前收集
+#[derive(Debug, Clone)]
+pub struct VideoRecord {
+    pub id: String,
+    pub title: String,
+    pub folder: String,
+    pub path: String,
+    pub duration: f64,
+    pub width: u32,
+    pub height: u32,
+    pub codecs: Vec<String>,
+    pub size: u64,
+    pub poster: Option<String>,
+    pub sprite: Option<String>,
+    pub hls: Option<String>,
+    // HLS密钥地址按服务器区分时，第N个服务器(N>1)使用的 main_N.m3u8
+    pub server_hls: Vec<usize>,
+    pub subtitles: Vec<SubtitleFile>,
+    pub chapter_track: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+// 服务器配置：基础URL和可选的路径前缀，如 /courses
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerConfig {
+    pub url: String,
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+// --serve中的每一项可以是URL字符串，也可以是 {"url": ..., "prefix": ...}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ServerSpec {
+    Url(String),
+    Config(ServerConfig),
+}
+
+// 索引生成选项
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    pub input_root: PathBuf,
+    pub output_root: Option<PathBuf>,
+    pub prune: bool,
+    pub index_dir: PathBuf,
+    pub index_name: String,
+    pub catalog_name: String,
+}
+
+impl From<ServerSpec> for ServerConfig {
+    fn from(spec: ServerSpec) -> Self {
+        match spec {
+            ServerSpec::Url(url) => ServerConfig { url, prefix: None },
+            ServerSpec::Config(config) => config,
+        }
+    }
+}
+
+impl ServerConfig {
+    // 视频所在的基础URL，服务器URL加上路径前缀
+    pub fn base_url(&self) -> String {
+        let url = self.url.trim_end_matches('/');
+        match self.prefix.as_deref().map(|p| p.trim_matches('/')) {
+            Some(prefix) if !prefix.is_empty() => format!("{}/{}", url, prefix),
+            _ => url.to_string(),
+        }
+    }
+
+    // 从URL中取出主机名，如 https://cdn.example.com:8443/x -> cdn.example.com
+    pub fn host(&self) -> String {
+        let without_scheme = self.url.split("://").last().unwrap_or(&self.url);
+        let authority = without_scheme.split('/').next().unwrap_or_default();
+        let host = authority.rsplit('@').next().unwrap_or_default();
+        host.split(':').next().unwrap_or_default().to_string()
+    }
+
+    // 文件名模板中{host}的取值：主机名加上非默认端口和路径前缀，
+    // 同一主机的不同前缀各自对应一个文件，如 cdn.example.com_8443_courses
+    pub fn file_key(&self) -> String {
+        let base_url = self.base_url();
+        let without_scheme = base_url.split("://").last().unwrap_or(&base_url);
+        let mut segments = without_scheme.split('/');
+        let authority = segments.next().unwrap_or_default();
+        let authority = authority.rsplit('@').next().unwrap_or_default();
+        let parts: Vec<&str> = authority
+            .split(':')
+            .chain(segments)
+            .filter(|part| !part.is_empty())
+            .collect();
+        parts.join("_")
+    }
+}
+
+impl IndexOptions {
+    // 根据文件名模板生成索引文件路径，支持 {num}(服务器序号) 和 {host}(服务器主机名和路径前缀)
+    pub fn index_path(&self, template: &str, server_num: usize, server: &ServerConfig) -> PathBuf {
+        let filename = template
+            .replace("{num}", &server_num.to_string())
+            .replace("{host}", &server.file_key());
+        self.index_dir.join(filename)
+    }
+
+    // 不同服务器生成相同的文件名时会互相覆盖，写入前检查模板
+    pub fn check_templates(&self, servers: &[ServerConfig]) -> bool {
+        for template in [&self.index_name, &self.catalog_name] {
+            let mut seen = HashSet::new();
+            for (i, server) in servers.iter().enumerate() {
+                let path = self.index_path(template, i + 1, server);
+                if !seen.insert(path.clone()) {
+                    println!(
+                        "{} 多个服务器生成了相同的文件 {}，请在模板 {} 中加入{{num}}",
+                        "❌".red(),
+                        path.display(),
+                        template
+                    );
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // 条目对应的DASH输出目录
+    pub fn dash_dir(&self, entry: &VideoEntry) -> PathBuf {
+        match self.output_root {
+            _ if !entry.path.is_empty() => self.output_root.as_ref().unwrap_or(&self.input_root).join(&entry.path),
+            // 旧版条目没有path，按原来的布局查找
+            Some(ref output_root) => output_root.join(&entry.title),
+            None => self.input_root.join(&entry.folder).join(&entry.title),
+        }
+    }
+
+    // 条目对应的DASH输出是否仍然存在
+    pub fn output_exists(&self, entry: &VideoEntry) -> bool {
+        self.dash_dir(entry).join("main.mpd").is_file()
+    }
+}
+
+// 输出目录中的字幕文件
+#[derive(Debug, Clone)]
+pub struct SubtitleFile {
+    pub lang: String,
+    pub path: String,
+}
+
+// 服务器JSON文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerIndex {
+    pub schema_version: u32,
+    pub generated_at: String,
+    pub server: String,
+    pub videos: Vec<VideoEntry>,
+}
+
+// 服务器JSON中的单个视频
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoEntry {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hls_url: Option<String>,
+    pub folder: String,
+    // 相对输出根目录的路径，旧版索引中没有
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    pub duration: f64,
+    pub resolution: Resolution,
+    pub codecs: Vec<String>,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poster: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sprite: Option<String>,
+    #[serde(default)]
+    pub subtitles: Vec<SubtitleTrack>,
+    // WebVTT章节轨道和进度条上的章节标记
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chapters_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<ChapterMarker>,
+}
+
+// 旧版服务器JSON中的条目
+#[derive(Debug, Clone, Deserialize)]
+struct LegacyEntry {
+    title: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtitleTrack {
+    pub lang: String,
+    pub url: String,
+}
+
+// 字段名与artplayer的highlight选项一致，可以直接传入
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChapterMarker {
+    pub time: f64,
+    pub text: String,
+}
+
+impl VideoRecord {
+    // 从输出目录和处理后的视频收集元数据
+    pub fn collect(source: &Path, dash_dir: &Path, processed_path: &Path, input_root: &Path, path: &str) -> Self {
+        let title = source.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let folder = source
+            .parent()
+            .and_then(|parent| parent.strip_prefix(input_root).ok())
+            .map(to_url_path)
+            .unwrap_or_default();
+        let relative_source = source
+            .strip_prefix(input_root)
+            .map(to_url_path)
+            .unwrap_or_else(|_| to_url_path(source));
+
+        let info = probe_media(processed_path).unwrap_or_default();
+        // 单遍模式下探测的是源文件，分辨率以MPD中实际输出的为准
+        let (width, height) = read_mpd_resolution(&dash_dir.join("main.mpd")).unwrap_or((info.width, info.height));
+        let mut codecs = read_mpd_codecs(&dash_dir.join("main.mpd"));
+        if codecs.is_empty() {
+            codecs = info.video_codec.iter().chain(info.audio_codec.iter()).cloned().collect();
+        }
+
+        let existing = |name: &str| {
+            if dash_dir.join(name).is_file() {
+                Some(name.to_string())
+            } else {
+                None
+            }
+        };
+
+        VideoRecord {
+            id: video_id(&relative_source),
+            title,
+            folder,
+            path: path.to_string(),
+            duration: info.duration,
+            width,
+            height,
+            codecs,
+            size: published_size(dash_dir),
+            poster: existing("poster.jpg"),
+            sprite: existing("sprite.jpg"),
+            hls: existing("main.m3u8"),
+            server_hls: find_server_playlists(dash_dir),
+            subtitles: find_subtitles(dash_dir),
+            chapter_track: existing(CHAPTERS_FILE),
+            chapters: read_vtt(&dash_dir.join(CHAPTERS_FILE)),
+        }
+    }
+
+    // 生成第server_num个服务器上的视频条目
+    pub fn to_entry(&self, server: &ServerConfig, server_num: usize) -> VideoEntry {
+        let base = format!("{}/{}", server.base_url(), self.path);
+        let url = |path: &str| format!("{}/{}", base, path);
+        let hls = if self.server_hls.contains(&server_num) {
+            Some(format!("main_{}.m3u8", server_num))
+        } else {
+            self.hls.clone()
+        };
+
+        VideoEntry {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            url: url("main.mpd"),
+            hls_url: hls.as_deref().map(url),
+            folder: self.folder.clone(),
+            path: self.path.clone(),
+            duration: self.duration,
+            resolution: Resolution {
+                width: self.width,
+                height: self.height,
+            },
+            codecs: self.codecs.clone(),
+            size: self.size,
+            poster: self.poster.as_deref().map(url),
+            sprite: self.sprite.as_deref().map(url),
+            subtitles: self
+                .subtitles
+                .iter()
+                .map(|subtitle| SubtitleTrack {
+                    lang: subtitle.lang.clone(),
+                    url: url(&subtitle.path),
+                })
+                .collect(),
+            chapters_url: self.chapter_track.as_deref().map(url),
+            chapters: self
+                .chapters
+                .iter()
+                .map(|chapter| ChapterMarker {
+                    time: (chapter.start * 1000.0).round() / 1000.0,
+                    text: chapter.title.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// 生成服务器JSON文件，与已有文件按视频ID合并，返回合并后的索引
+pub fn generate_server_json(processed_videos: &[VideoRecord], servers: &[ServerConfig], options: &IndexOptions) -> Vec<ServerIndex> {
+    if servers.is_empty() || (processed_videos.is_empty() && !options.prune) {
+        return Vec::new();
     }
-    
+
     println!("{} 生成服务器JSON文件...", "📝".blue());
-    
-    for (i, server_url) in servers.iter().enumerate() {
+
+    if let Err(e) = fs::create_dir_all(&options.index_dir) {
+        println!("{} 创建索引目录失败: {}", "❌".red(), e);
+        return Vec::new();
+    }
+
+    let generated_at = timestamp();
+    let mut indexes = Vec::new();
+
+    for (i, server) in servers.iter().enumerate() {
         let server_num = i + 1;
-        let filename = format!("server_{}.json", server_num);
-        
-        // 创建JSON数组
-        let mut json_array = Vec::new();
-        
-        for (_video_path, dash_dir) in processed_videos {
-            let video_name = dash_dir.file_name().unwrap_or_default().to_string_lossy();
-            let mpd_url = format!("{}/{}/main.mpd", server_url, video_name);
-            
-            let video_entry = json!({
-                "title": video_name,
-                "url": mpd_url
-            });
-            
-            json_array.push(video_entry);
-        }
-        
-        // 写入JSON文件
-        let json_str = serde_json::to_string_pretty(&json_array).unwrap_or_else(|e| {
-            println!("{} JSON序列化失败: {}", "❌".red(), e);
-            String::from("[]")
-        });
-        
-        let mut file = match File::create(&filename) {
-            Ok(file) => file,
-            Err(e) => {
-                println!("{} 创建JSON文件失败: {}", "❌".red(), e);
-                continue;
-            }
+        let index_path = options.index_path(&options.index_name, server_num, server);
+
+        let existing = load_existing(&index_path);
+        let videos = merge_entries(existing, processed_videos, server, server_num, options);
+
+        let index = ServerIndex {
+            schema_version: SCHEMA_VERSION,
+            generated_at: generated_at.clone(),
+            server: server.base_url(),
+            videos,
         };
-        
-        if let Err(e) = file.write_all(json_str.as_bytes()) {
-            println!("{} 写入JSON文件失败: {}", "❌".red(), e);
+
+        write_json(&index_path, &index);
+        indexes.push(index);
+    }
+
+    indexes
+}
+
+// 读取已有的服务器JSON，兼容旧版的数组格式
+fn load_existing(path: &Path) -> Vec<VideoEntry> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+
+    if let Ok(index) = serde_json::from_str::<ServerIndex>(&content) {
+        return index.videos;
+    }
+
+    match serde_json::from_str::<Vec<LegacyEntry>>(&content) {
+        Ok(entries) => {
+            println!("{} {} 是旧版格式, 将升级到版本 {}", "ℹ️".blue(), path.display(), SCHEMA_VERSION);
+            entries
+                .into_iter()
+                .map(|entry| VideoEntry {
+                    id: format!("legacy:{}", entry.title),
+                    title: entry.title,
+                    url: entry.url,
+                    hls_url: None,
+                    folder: String::new(),
+                    path: String::new(),
+                    duration: 0.0,
+                    resolution: Resolution::default(),
+                    codecs: Vec::new(),
+                    size: 0,
+                    poster: None,
+                    sprite: None,
+                    subtitles: Vec::new(),
+                    chapters_url: None,
+                    chapters: Vec::new(),
+                })
+                .collect()
+        }
+        Err(e) => {
+            println!("{} 无法解析已有的 {}, 将重新生成: {}", "⚠️".yellow(), path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+// 合并已有条目和本次处理的视频：更新同ID条目，保留未处理的条目，可选删除输出已不存在的条目
+fn merge_entries(
+    existing: Vec<VideoEntry>,
+    processed_videos: &[VideoRecord],
+    server: &ServerConfig,
+    server_num: usize,
+    options: &IndexOptions,
+) -> Vec<VideoEntry> {
+    let mut videos: Vec<VideoEntry> = processed_videos.iter().map(|video| video.to_entry(server, server_num)).collect();
+    let (mut updated, mut kept, mut pruned) = (0, 0, 0);
+
+    for entry in existing {
+        // 旧版条目没有ID，按标题匹配
+        if videos.iter().any(|video| video.id == entry.id || (entry.id.starts_with("legacy:") && video.title == entry.title)) {
+            updated += 1;
+        } else if options.prune && !options.output_exists(&entry) {
+            println!("{} 输出已不存在, 从索引中移除: {}", "🗑️".yellow(), entry.title);
+            pruned += 1;
         } else {
-            println!("{} 成功创建JSON文件: {}", "✅".green(), filename);
+            videos.push(entry);
+            kept += 1;
+        }
+    }
+
+    println!(
+        "{} 索引合并: 新增 {} / 更新 {} / 保留 {} / 移除 {}",
+        "📝".blue(),
+        processed_videos.len().saturating_sub(updated),
+        updated,
+        kept,
+        pruned
+    );
+
+    videos.sort_by(|a, b| natural_cmp(&a.folder, &b.folder).then_with(|| natural_cmp(&a.title, &b.title)));
+    videos
+}
+
+// 序列化并写入JSON文件
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> bool {
+    let json_str = match serde_json::to_string_pretty(value) {
+        Ok(json_str) => json_str,
+        Err(e) => {
+            println!("{} JSON序列化失败: {}", "❌".red(), e);
+            return false;
+        }
+    };
+
+    let mut file = match File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("{} 创建JSON文件失败: {}", "❌".red(), e);
+            return false;
+        }
+    };
+
+    if let Err(e) = file.write_all(json_str.as_bytes()) {
+        println!("{} 写入JSON文件失败: {}", "❌".red(), e);
+        false
+    } else {
+        println!("{} 成功创建JSON文件: {}", "✅".green(), path.display());
+        true
+    }
+}
+
+// 生成时间，RFC 3339格式
+pub fn timestamp() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+// 基于源文件相对路径的稳定ID (FNV-1a 64位)
+pub fn video_id(relative_source: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in relative_source.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+// 路径统一使用POSIX风格
+fn to_url_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+// 从MPD读取各Representation的codecs属性
+fn read_mpd_codecs(mpd_path: &Path) -> Vec<String> {
+    let content = fs::read_to_string(mpd_path).unwrap_or_default();
+    let re = Regex::new(r#"codecs="([^"]+)""#).unwrap();
+    let mut codecs: Vec<String> = Vec::new();
+    for caps in re.captures_iter(&content) {
+        if !codecs.contains(&caps[1].to_string()) {
+            codecs.push(caps[1].to_string());
         }
     }
-}
\ No newline at end of file
+    codecs
+}
+
+// 从MPD读取第一个视频Representation的分辨率
+fn read_mpd_resolution(mpd_path: &Path) -> Option<(u32, u32)> {
+    let content = fs::read_to_string(mpd_path).ok()?;
+    let re = Regex::new(r#"<Representation[^>]*\swidth="(\d+)"[^>]*\sheight="(\d+)""#).unwrap();
+    let caps = re.captures(&content)?;
+    Some((caps[1].parse().ok()?, caps[2].parse().ok()?))
+}
+
+// 需要发布的文件总大小（不含AV1中间文件）
+fn published_size(dash_dir: &Path) -> u64 {
+    WalkDir::new(dash_dir)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != "av1")
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+// 输出目录subtitles文件夹下的WebVTT字幕，文件名即语言，如 zh.vtt
+fn find_subtitles(dash_dir: &Path) -> Vec<SubtitleFile> {
+    let subtitle_dir: PathBuf = dash_dir.join("subtitles");
+    let mut subtitles: Vec<SubtitleFile> = fs::read_dir(&subtitle_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "vtt"))
+        .map(|path| SubtitleFile {
+            lang: path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+            path: format!("subtitles/{}", path.file_name().unwrap_or_default().to_string_lossy()),
+        })
+        .collect();
+    subtitles.sort_by(|a, b| a.lang.cmp(&b.lang));
+    subtitles
+}
+
+// 输出目录中按服务器区分的HLS主播放列表 main_N.m3u8，返回N
+fn find_server_playlists(dash_dir: &Path) -> Vec<usize> {
+    let name_re = Regex::new(r"^main_(\d+)\.m3u8$").unwrap();
+    let mut servers: Vec<usize> = fs::read_dir(dash_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| name_re.captures(&entry.file_name().to_string_lossy()).and_then(|caps| caps[1].parse().ok()))
+        .collect();
+    servers.sort_unstable();
+    servers
+}
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use colored::Colorize;
+
+use crate::json_generator::video_id;
+use crate::report::JobReport;
+
+// 输出目录冲突（不同源文件映射到同一个输出目录）时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollisionPolicy {
+    Suffix,
+    Skip,
+    Overwrite,
+    Fail,
+}
+
+impl CollisionPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "suffix" => Some(CollisionPolicy::Suffix),
+            "skip" => Some(CollisionPolicy::Skip),
+            "overwrite" => Some(CollisionPolicy::Overwrite),
+            "fail" => Some(CollisionPolicy::Fail),
+            _ => None,
+        }
+    }
+}
+
+// 输出布局：输出根目录加上路径模板，
+// 模板支持 {out}(输出根目录) {relpath}(源文件相对输入路径的目录) {stem} {ext} {id}(源文件相对路径的哈希)
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+    pub input_root: PathBuf,
+    pub output_root: PathBuf,
+    pub template: String,
+    pub collision: CollisionPolicy,
+}
+
+// 单个视频的输出位置，path为相对输出根目录的路径（使用/分隔），也是发布和URL中使用的路径
+#[derive(Debug, Clone)]
+pub struct OutputPlan {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub path: String,
+}
+
+impl OutputLayout {
+    // 按模板生成相对输出根目录的路径，去掉空段和 . / ..
+    pub fn render(&self, source: &Path) -> String {
+        let relative = source.strip_prefix(&self.input_root).unwrap_or(source);
+        let relpath = relative
+            .parent()
+            .map(|parent| parent.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = source.extension().unwrap_or_default().to_string_lossy();
+        let id = video_id(&relative.to_string_lossy().replace('\\', "/"));
+
+        self.template
+            .replace("{out}", "")
+            .replace("{relpath}", &relpath)
+            .replace("{stem}", &stem)
+            .replace("{ext}", &ext)
+            .replace("{id}", &id)
+            .split(['/', '\\'])
+            .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    // 为所有视频分配输出目录并处理冲突，策略为fail时遇到冲突返回错误
+    pub fn plan(&self, videos: &[PathBuf]) -> Result<Vec<OutputPlan>, String> {
+        let mut claimed: HashSet<String> = HashSet::new();
+        let mut plans = Vec::new();
+
+        for source in videos {
+            let path = self.render(source);
+            if path.is_empty() {
+                return Err(format!("输出路径模板 {} 对 {} 生成了空路径", self.template, source.display()));
+            }
+            if self.is_free(&path, source, &claimed) {
+                claimed.insert(path.clone());
+                plans.push(self.plan_for(source, path));
+                continue;
+            }
+
+            match self.collision {
+                CollisionPolicy::Suffix => {
+                    let path = (2..)
+                        .map(|n| format!("{}-{}", path, n))
+                        .find(|candidate| self.is_free(candidate, source, &claimed))
+                        .unwrap();
+                    println!("{} {} 的输出目录已被占用, 改为 {}", "⚠️".yellow(), source.display(), path);
+                    claimed.insert(path.clone());
+                    plans.push(self.plan_for(source, path));
+                }
+                CollisionPolicy::Skip => {
+                    println!("{} {} 的输出目录 {} 已被占用, 跳过", "⚠️".yellow(), source.display(), path);
+                }
+                // 覆盖只针对以前运行留下的输出，同一次运行中的两个视频不能写同一个目录
+                CollisionPolicy::Overwrite if !claimed.contains(&path) => {
+                    println!("{} {} 将覆盖已有的输出目录 {}", "⚠️".yellow(), source.display(), path);
+                    claimed.insert(path.clone());
+                    plans.push(self.plan_for(source, path));
+                }
+                CollisionPolicy::Overwrite => {
+                    println!("{} {} 与本次运行中的其他视频输出到同一目录 {}, 跳过", "⚠️".yellow(), source.display(), path);
+                }
+                CollisionPolicy::Fail => {
+                    return Err(format!("{} 的输出目录 {} 已被占用", source.display(), path));
+                }
+            }
+        }
+        Ok(plans)
+    }
+
+    fn plan_for(&self, source: &Path, path: String) -> OutputPlan {
+        OutputPlan {
+            source: source.to_path_buf(),
+            target: self.output_root.join(&path),
+            path,
+        }
+    }
+
+    // 路径没有被本次运行占用，且磁盘上不存在或是同一个源文件以前的输出
+    fn is_free(&self, path: &str, source: &Path, claimed: &HashSet<String>) -> bool {
+        if claimed.contains(path) {
+            return false;
+        }
+        let target = self.output_root.join(path);
+        !target.exists() || JobReport::load_source(&target).as_deref() == Some(&*source.to_string_lossy())
+    }
+}
+
+// 视频处理时使用的工作目录，与最终输出目录在同一文件系统上，完成后整体重命名
+pub fn prepare_work_dir(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let work = target.with_file_name(format!(".{}.dash-work", name));
+    if work.exists() {
+        println!("{} 清理上次中断留下的工作目录: {}", "🧹".yellow(), work.display());
+        let _ = fs::remove_dir_all(&work);
+    }
+    work
+}
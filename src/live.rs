@@ -0,0 +1,453 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use colored::Colorize;
+use regex::{Captures, Regex};
+
+use crate::mp4::{read_default_duration, read_fragment_timing};
+use crate::segmenter::{iso_duration, render_timeline};
+
+// 低延迟模式下每个CMAF块的时长（秒）
+const LOW_LATENCY_FRAGMENT: f64 = 0.5;
+
+// 直播输入源
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveSource {
+    // rtmp/srt/udp等网络地址，listen为true时由本机监听推流
+    Url { url: String, listen: bool },
+    // 本地文件按实时速度循环播放，用于测试
+    Loop(PathBuf),
+    // 采集设备，如 device:dshow:video=USB Camera
+    Device { format: String, spec: String },
+}
+
+impl LiveSource {
+    pub fn parse(spec: &str) -> Self {
+        if let Some(rest) = spec.strip_prefix("device:") {
+            let (format, spec) = rest.split_once(':').unwrap_or((rest, ""));
+            return LiveSource::Device {
+                format: format.to_string(),
+                spec: spec.to_string(),
+            };
+        }
+        let scheme = spec.split("://").next().unwrap_or("").to_lowercase();
+        match scheme.as_str() {
+            // rtmp需要显式监听；srt/udp通过地址参数(mode=listener、0.0.0.0)决定
+            "rtmp" | "rtmps" => LiveSource::Url {
+                url: spec.to_string(),
+                listen: spec.contains("://0.0.0.0"),
+            },
+            "srt" | "udp" | "rtp" | "tcp" | "http" | "https" => LiveSource::Url {
+                url: spec.to_string(),
+                listen: false,
+            },
+            _ => LiveSource::Loop(PathBuf::from(spec.strip_prefix("file:").unwrap_or(spec))),
+        }
+    }
+
+    fn input_args(&self) -> Vec<String> {
+        match self {
+            LiveSource::Url { url, listen } => {
+                let mut args = Vec::new();
+                if *listen {
+                    args.extend([String::from("-listen"), String::from("1")]);
+                }
+                args.extend([String::from("-i"), url.clone()]);
+                args
+            }
+            LiveSource::Loop(path) => vec![
+                String::from("-re"),
+                String::from("-stream_loop"), String::from("-1"),
+                String::from("-i"), path.to_string_lossy().to_string(),
+            ],
+            LiveSource::Device { format, spec } => vec![
+                String::from("-f"), format.clone(),
+                String::from("-i"), spec.clone(),
+            ],
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            LiveSource::Url { url, listen: true } => format!("{} (监听)", url),
+            LiveSource::Url { url, .. } => url.clone(),
+            LiveSource::Loop(path) => format!("{} (循环)", path.display()),
+            LiveSource::Device { format, spec } => format!("{}设备 {}", format, spec),
+        }
+    }
+}
+
+// 直播转DASH的选项
+#[derive(Debug, Clone)]
+pub struct LiveOptions {
+    pub source: LiveSource,
+    pub seg_duration: u32,
+    // MPD中保留的分片数，0表示保留全部
+    pub window: u32,
+    pub encoder: String,
+    pub bitrate: String,
+    pub low_latency: bool,
+    pub utc_timing: Option<String>,
+}
+
+impl LiveOptions {
+    // ffmpeg参数：输入、实时编码和动态MPD的DASH封装
+    fn ffmpeg_args(&self, mpd_path: &Path) -> Vec<String> {
+        let mut args = vec![String::from("-y"), String::from("-v"), String::from("warning"), String::from("-stats")];
+        args.extend(self.source.input_args());
+
+        // 关键帧按分片时长强制插入，音频统一转为AAC
+        args.extend([
+            String::from("-map"), String::from("0:v:0"),
+            String::from("-map"), String::from("0:a:0?"),
+            String::from("-c:v"), self.encoder.clone(),
+            String::from("-b:v"), self.bitrate.clone(),
+            String::from("-force_key_frames"), format!("expr:gte(t,n_forced*{})", self.seg_duration),
+            String::from("-c:a"), String::from("aac"),
+            String::from("-b:a"), String::from("128k"),
+            String::from("-ar"), String::from("48000"),
+        ]);
+
+        // 分片路径相对MPD所在目录，ffmpeg运行期间会不断重写MPD，不能事后修复路径
+        args.extend([
+            String::from("-f"), String::from("dash"),
+            String::from("-seg_duration"), self.seg_duration.to_string(),
+            String::from("-dash_segment_type"), String::from("mp4"),
+            String::from("-use_template"), String::from("1"),
+            String::from("-window_size"), self.window.to_string(),
+            String::from("-extra_window_size"), self.window.min(5).to_string(),
+            String::from("-remove_at_exit"), String::from("0"),
+            String::from("-init_seg_name"), String::from("live/init_$RepresentationID$.m4s"),
+            String::from("-media_seg_name"), String::from("live/chunk_$RepresentationID$_$Number$.m4s"),
+        ]);
+        if self.low_latency {
+            // 分块CMAF：分片生成过程中就按块写出，播放器可以边下载边播放
+            args.extend([
+                String::from("-use_timeline"), String::from("0"),
+                String::from("-streaming"), String::from("1"),
+                String::from("-ldash"), String::from("1"),
+                String::from("-frag_type"), String::from("duration"),
+                String::from("-frag_duration"), LOW_LATENCY_FRAGMENT.to_string(),
+                String::from("-target_latency"), (LOW_LATENCY_FRAGMENT * 6.0).to_string(),
+                String::from("-write_prft"), String::from("1"),
+            ]);
+        } else {
+            args.extend([String::from("-use_timeline"), String::from("1")]);
+        }
+        if let Some(ref url) = self.utc_timing {
+            args.extend([String::from("-utc_timing_url"), url.clone()]);
+        }
+        args.push(mpd_path.to_string_lossy().to_string());
+        args
+    }
+}
+
+// 运行直播转换直到输入结束或按下Ctrl+C，输出目录结构与批量转换相同(main.mpd + live/)
+pub fn run(options: &LiveOptions, dash_dir: &Path) -> bool {
+    let live_dir = dash_dir.join("live");
+    if let Err(e) = fs::create_dir_all(&live_dir) {
+        println!("{} 创建live目录失败: {}", "❌".red(), e);
+        return false;
+    }
+    if let LiveSource::Loop(ref path) = options.source {
+        if !path.exists() {
+            println!("{} 输入文件不存在: {}", "❌".red(), path.display());
+            return false;
+        }
+    }
+
+    // Ctrl+C同时发给ffmpeg，ffmpeg会写完最后的分片；这里只记录是主动停止
+    let stopping = Arc::new(AtomicBool::new(false));
+    let flag = stopping.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        flag.store(true, Ordering::SeqCst);
+    }) {
+        println!("{} 注册Ctrl+C处理失败: {}", "⚠️".yellow(), e);
+    }
+
+    let mpd_path = dash_dir.join("main.mpd");
+    println!("{} 直播输入: {}", "📡".blue(), options.source.describe());
+    println!("{} 动态MPD: {}", "📄".blue(), mpd_path.display());
+    if options.low_latency {
+        println!("{} 低延迟模式: 分块CMAF, 每块{}秒", "⚡".blue(), LOW_LATENCY_FRAGMENT);
+    }
+    println!("{} 按Ctrl+C结束直播", "ℹ️".blue());
+
+    let status = Command::new("ffmpeg").args(options.ffmpeg_args(&mpd_path)).status();
+    match status {
+        Ok(_) if stopping.load(Ordering::SeqCst) => {
+            println!("{} 直播已停止", "✅".green());
+            true
+        }
+        Ok(status) if status.success() => {
+            println!("{} 直播输入已结束", "✅".green());
+            true
+        }
+        Ok(status) => {
+            println!("{} 直播转换中断，退出码: {:?}", "❌".red(), status.code());
+            false
+        }
+        Err(e) => {
+            println!("{} 执行ffmpeg失败: {}", "❌".red(), e);
+            false
+        }
+    }
+}
+
+// 直播结束后把动态MPD改写为静态点播MPD：按保留下来的分片重建SegmentTimeline，
+// 写入mediaPresentationDuration，去掉只对直播有意义的属性和元素，分片本身不重新编码；返回点播时长(秒)
+pub fn finalize(dash_dir: &Path) -> Option<f64> {
+    let mpd_path = dash_dir.join("main.mpd");
+    let content = match fs::read_to_string(&mpd_path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("{} 读取MPD文件失败: {}", "❌".red(), e);
+            return None;
+        }
+    };
+    println!("{} 将直播MPD转换为点播: {}", "🔄".yellow(), mpd_path.display());
+
+    let mut duration: f64 = 0.0;
+    let mut failed = None;
+    let representation = Regex::new(r"(?s)<Representation\b([^>]*)>(.*?)</Representation>").unwrap();
+    let template = Regex::new(r"(?s)<SegmentTemplate\b([^>]*?)(/>|>.*?</SegmentTemplate>)").unwrap();
+    let content = representation.replace_all(&content, |caps: &Captures| {
+        let id = attribute(&caps[1], "id").unwrap_or_default();
+        let inner = template.replace(&caps[2], |template: &Captures| {
+            match rebuild_template(dash_dir, &id, &template[1]) {
+                Ok((xml, seconds)) => {
+                    duration = duration.max(seconds);
+                    xml
+                }
+                Err(e) => {
+                    failed.get_or_insert(format!("Representation {}: {}", id, e));
+                    template[0].to_string()
+                }
+            }
+        });
+        format!("<Representation{}>{}</Representation>", &caps[1], inner)
+    });
+    if let Some(e) = failed {
+        println!("{} 重建分片列表失败 {}", "❌".red(), e);
+        return None;
+    }
+
+    // MPD根元素改为static并写入总时长
+    let root = Regex::new(r"(?s)<MPD\b[^>]*>").unwrap();
+    let live_attributes = Regex::new(
+        r#"\s+(availabilityStartTime|publishTime|minimumUpdatePeriod|timeShiftBufferDepth|suggestedPresentationDelay|mediaPresentationDuration)="[^"]*""#,
+    )
+    .unwrap();
+    let content = root.replace(&content, |caps: &Captures| {
+        live_attributes
+            .replace_all(&caps[0], "")
+            .replacen(
+                r#"type="dynamic""#,
+                &format!(r#"type="static" mediaPresentationDuration="{}""#, iso_duration(duration)),
+                1,
+            )
+    });
+
+    // 去掉时钟同步、低延迟服务描述和分块可用时间
+    let mut content = content.to_string();
+    for pattern in [
+        r"(?s)\s*<UTCTiming\b[^>]*?(/>|>.*?</UTCTiming>)",
+        r"(?s)\s*<ServiceDescription\b.*?</ServiceDescription>",
+        r"(?s)\s*<ProducerReferenceTime\b[^>]*?(/>|>.*?</ProducerReferenceTime>)",
+        r#"\s+availabilityTime(Offset|Complete)="[^"]*""#,
+    ] {
+        content = Regex::new(pattern).unwrap().replace_all(&content, "").to_string();
+    }
+    if !content.contains(r#"type="static""#) {
+        println!("{} MPD缺少type属性, 无法转换", "❌".red());
+        return None;
+    }
+
+    // 先写临时文件再替换，避免中断时留下半个MPD
+    let temp_path = dash_dir.join("main.mpd.tmp");
+    if let Err(e) = fs::write(&temp_path, &content).and_then(|_| fs::rename(&temp_path, &mpd_path)) {
+        println!("{} 写入MPD文件失败: {}", "❌".red(), e);
+        return None;
+    }
+    println!("{} 点播MPD已生成, 时长 {:.1}秒", "✅".green(), duration);
+    Some(duration)
+}
+
+fn attribute(element: &str, name: &str) -> Option<String> {
+    Regex::new(&format!(r#"\b{}="([^"]*)""#, name))
+        .unwrap()
+        .captures(element)
+        .map(|caps| caps[1].to_string())
+}
+
+// 按磁盘上保留的分片重建一个Representation的SegmentTemplate，返回新的XML和时长（秒）
+fn rebuild_template(dash_dir: &Path, id: &str, attributes: &str) -> Result<(String, f64), String> {
+    let timescale = attribute(attributes, "timescale")
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .ok_or("缺少timescale")?;
+    let initialization = attribute(attributes, "initialization").ok_or("缺少initialization")?;
+    let media = attribute(attributes, "media").ok_or("缺少media")?;
+
+    let init = fs::read(dash_dir.join(initialization.replace("$RepresentationID$", id)))
+        .map_err(|e| format!("读取初始化分片失败: {}", e))?;
+    let default_duration = read_default_duration(&init);
+
+    // 按media模板匹配分片文件，取编号连续的一段（窗口之前的分片已被删除）
+    let media_path = media.replace("$RepresentationID$", id);
+    let (directory, name) = media_path.rsplit_once('/').unwrap_or(("", &media_path));
+    let pattern = Regex::new(&format!(
+        "^{}$",
+        Regex::new(r"\\\$Number(%0\d+d)?\\\$").unwrap().replace_all(&regex::escape(name), r"(\d+)")
+    ))
+    .map_err(|e| e.to_string())?;
+    let mut numbers: Vec<u64> = fs::read_dir(dash_dir.join(directory))
+        .map_err(|e| format!("读取分片目录失败: {}", e))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            pattern.captures(&file_name).and_then(|caps| caps[1].parse::<u64>().ok())
+        })
+        .collect();
+    numbers.sort_unstable();
+    let first = *numbers.first().ok_or("没有保留的分片")?;
+
+    let number_field = Regex::new(r"\$Number(%0(\d+)d)?\$").unwrap();
+    let mut segments = Vec::new();
+    for (expected, number) in (first..).zip(&numbers) {
+        if *number != expected {
+            break;
+        }
+        let file_name = number_field
+            .replace(name, |caps: &Captures| {
+                let width = caps.get(2).and_then(|w| w.as_str().parse::<usize>().ok()).unwrap_or(0);
+                format!("{:0width$}", number, width = width)
+            })
+            .to_string();
+        let data = fs::read(dash_dir.join(directory).join(&file_name)).map_err(|e| format!("{}: {}", file_name, e))?;
+        // 直播被强行中断时最后一个分片可能不完整，丢弃
+        match read_fragment_timing(&data, default_duration) {
+            Some(timing) => segments.push(timing),
+            None => {
+                println!("{} 分片 {} 不完整, 已丢弃", "⚠️".yellow(), file_name);
+                break;
+            }
+        }
+    }
+    let start = segments.first().map(|(start, _)| *start).ok_or("没有完整的分片")?;
+    let total: u64 = segments.iter().map(|(_, duration)| duration).sum();
+
+    let mut xml = format!(
+        "<SegmentTemplate timescale=\"{}\" initialization=\"{}\" media=\"{}\" startNumber=\"{}\" presentationTimeOffset=\"{}\">\n",
+        timescale, initialization, media, first, start
+    );
+    xml.push_str("\t\t\t\t\t<SegmentTimeline>\n");
+    xml.push_str(&render_timeline(&segments));
+    xml.push_str("\t\t\t\t\t</SegmentTimeline>\n");
+    xml.push_str("\t\t\t\t</SegmentTemplate>");
+    Ok((xml, total as f64 / timescale as f64))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATTRIBUTES: &str = r#" timescale="1000" initialization="init_$RepresentationID$.m4s" media="chunk_$RepresentationID$_$Number%05d$.m4s""#;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    // 初始化分片只需要trex中的默认样本时长
+    fn init_segment() -> Vec<u8> {
+        let mut trex = vec![0u8; 4];
+        for value in [1u32, 1, 2000, 0, 0] {
+            trex.extend_from_slice(&value.to_be_bytes());
+        }
+        mp4_box(b"moov", &mp4_box(b"mvex", &mp4_box(b"trex", &trex)))
+    }
+
+    // 一个moof：tfdt为起始时间，trun只有样本数，使用trex的默认时长
+    fn media_segment(dts: u64, samples: u32) -> Vec<u8> {
+        let mut tfhd = 0x0002_0000u32.to_be_bytes().to_vec();
+        tfhd.extend_from_slice(&1u32.to_be_bytes());
+        let mut tfdt = 0x0100_0000u32.to_be_bytes().to_vec();
+        tfdt.extend_from_slice(&dts.to_be_bytes());
+        let mut trun = 0u32.to_be_bytes().to_vec();
+        trun.extend_from_slice(&samples.to_be_bytes());
+
+        let mut traf = mp4_box(b"tfhd", &tfhd);
+        traf.extend(mp4_box(b"tfdt", &tfdt));
+        traf.extend(mp4_box(b"trun", &trun));
+        let mut data = mp4_box(b"moof", &mp4_box(b"traf", &traf));
+        data.extend(mp4_box(b"mdat", &[0u8; 4]));
+        data
+    }
+
+    fn live_dir(name: &str, files: &[(&str, Vec<u8>)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dash_live_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, data) in files {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        dir
+    }
+
+    // 窗口之前的分片已删除，编号从3开始；6缺失时在5处停止，7及其他Representation的分片不计入
+    #[test]
+    fn rebuild_template_stops_at_first_missing_number() {
+        let dir = live_dir("gap", &[
+            ("init_0.m4s", init_segment()),
+            ("chunk_0_00003.m4s", media_segment(4000, 1)),
+            ("chunk_0_00004.m4s", media_segment(6000, 1)),
+            ("chunk_0_00005.m4s", media_segment(8000, 1)),
+            ("chunk_0_00007.m4s", media_segment(12000, 1)),
+            ("chunk_1_00001.m4s", media_segment(0, 1)),
+            ("chunk_0_3.m4s.tmp", Vec::new()),
+        ]);
+        let rebuilt = rebuild_template(&dir, "0", ATTRIBUTES);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (xml, seconds) = rebuilt.unwrap();
+        assert_eq!(seconds, 6.0);
+        assert!(xml.contains(r#"startNumber="3" presentationTimeOffset="4000""#));
+        assert!(xml.contains(r#"media="chunk_$RepresentationID$_$Number%05d$.m4s""#));
+        assert!(xml.contains(r#"<S t="4000" d="2000" r="2" />"#));
+        assert_eq!(xml.matches("<S ").count(), 1);
+    }
+
+    // 被中断写到一半的分片和它之后的分片都丢弃
+    #[test]
+    fn rebuild_template_drops_incomplete_segment() {
+        let mut partial = media_segment(2000, 1);
+        partial.truncate(30);
+        let dir = live_dir("partial", &[
+            ("init_0.m4s", init_segment()),
+            ("chunk_0_00001.m4s", media_segment(0, 1)),
+            ("chunk_0_00002.m4s", partial),
+            ("chunk_0_00003.m4s", media_segment(4000, 1)),
+        ]);
+        let rebuilt = rebuild_template(&dir, "0", ATTRIBUTES);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (xml, seconds) = rebuilt.unwrap();
+        assert_eq!(seconds, 2.0);
+        assert!(xml.contains(r#"startNumber="1" presentationTimeOffset="0""#));
+        assert!(xml.contains(r#"<S t="0" d="2000" />"#));
+    }
+
+    #[test]
+    fn rebuild_template_without_segments() {
+        let dir = live_dir("empty", &[("init_0.m4s", init_segment())]);
+        let rebuilt = rebuild_template(&dir, "0", ATTRIBUTES);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(rebuilt.is_err());
+    }
+}
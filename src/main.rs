@@ -1,20 +1,58 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use colored::Colorize;
 use std::path::{Path, PathBuf};
-use std::fs;
 use std::sync::{Arc, Mutex};
 use threadpool::ThreadPool;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 mod video_processor;
 mod dash_generator;
+mod encryption;
+mod hls;
 mod utils;
 mod json_generator;
+mod layout;
+mod live;
+mod mp4;
+mod periods;
+mod playlist;
+mod alignment;
+mod analysis;
+mod catalog;
+mod chapters;
+mod cleanup;
+mod chunked;
+mod color;
+mod probe;
+mod quality;
+mod report;
+mod publish;
+mod s3;
+mod segmenter;
+mod staging;
 
-use video_processor::VideoProcessor;
-use dash_generator::DashGenerator;
-use utils::{get_video_files, setup_output_dirs};
-use json_generator::generate_server_json;
+use video_processor::{EncodeOptions, VideoProcessor};
+use alignment::Alignment;
+use dash_generator::{DashGenerator, Packager};
+use encryption::{ContentKey, Encryption, EncryptionOptions, Keystore, Scheme};
+use hls::{Hls, HlsEncryption, HlsMethod, HlsOptions};
+use utils::{format_size, get_video_files, setup_output_dirs};
+use json_generator::{generate_server_json, IndexOptions, ServerConfig, ServerSpec, VideoRecord};
+use layout::{prepare_work_dir, CollisionPolicy, OutputLayout};
+use live::{LiveOptions, LiveSource};
+use periods::GroupMode;
+use playlist::{generate_playlists, PlaylistFormat};
+use analysis::Deinterlacer;
+use catalog::generate_catalog_json;
+use chapters::Chapter;
+use cleanup::IntermediatePolicy;
+use chunked::ChunkOptions;
+use quality::{QualityMetric, QualityOptions, TargetQuality};
+use probe::{probe_chapters, probe_media};
+use report::JobReport;
+use publish::{parse_target, Publisher};
+use s3::{S3Config, S3Uploader};
+use staging::deliver;
 
 fn main() {
     let matches = App::new("视频DASH流转换工具")
@@ -27,7 +65,7 @@ fn main() {
                 .long("input")
                 .value_name("路径")
                 .help("要处理的视频文件夹路径")
-                .required(true)
+                .required_unless_one(&["live", "finalize"])
         )
         .arg(
             Arg::with_name("time")
@@ -37,6 +75,13 @@ fn main() {
                 .help("DASH分片的时间间隔（秒）")
                 .default_value("10")
         )
+        .arg(
+            Arg::with_name("segment-tolerance")
+                .long("segment-tolerance")
+                .value_name("百分比")
+                .help("分片实际时长超过--time的百分比上限，直接复制的AV1源超出时按分片时长重新编码")
+                .default_value("50")
+        )
         .arg(
             Arg::with_name("parallel")
                 .short("p")
@@ -63,44 +108,652 @@ fn main() {
             Arg::with_name("copy")
                 .long("copy")
                 .value_name("是否复制")
-                .help("是否复制而不是移动文件到输出路径")
+                .help("已移除：输出直接按--layout写入输出路径，不再先生成在源文件旁")
+                .takes_value(true)
+                .possible_values(&["true", "false"])
+                .hidden(true)
+        )
+        .arg(
+            Arg::with_name("layout")
+                .long("layout")
+                .value_name("模板")
+                .help("输出目录模板，支持{out} {relpath} {stem} {ext} {id}，{out}为--output或输入路径")
+                .default_value("{out}/{relpath}/{stem}")
+        )
+        .arg(
+            Arg::with_name("on-collision")
+                .long("on-collision")
+                .value_name("策略")
+                .help("不同视频输出到同一目录时的处理方式")
+                .possible_values(&["suffix", "skip", "overwrite", "fail"])
+                .default_value("suffix")
+        )
+        .arg(
+            Arg::with_name("intermediate")
+                .long("intermediate")
+                .value_name("策略")
+                .help("av1中间文件的处理方式：keep保留，delete打包成功后删除，exclude移到--intermediate-dir")
+                .possible_values(&["keep", "delete", "exclude"])
+                .default_value("keep")
+        )
+        .arg(
+            Arg::with_name("intermediate-dir")
+                .long("intermediate-dir")
+                .value_name("路径")
+                .help("exclude策略下中间文件的存放目录，默认为输出路径下的.dash-intermediate")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("quality")
+                .long("quality")
+                .value_name("指标")
+                .help("转码后使用指定指标校验质量 (vmaf/ssim/psnr)")
+                .takes_value(true)
+                .possible_values(&["vmaf", "ssim", "psnr"])
+        )
+        .arg(
+            Arg::with_name("quality-min")
+                .long("quality-min")
+                .value_name("分数")
+                .help("质量合格线，默认vmaf为93、ssim为0.97、psnr为40")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("quality-retry")
+                .long("quality-retry")
+                .value_name("次数")
+                .help("质量不达标时提高质量重新编码的次数")
+                .default_value("1")
+        )
+        .arg(
+            Arg::with_name("quality-samples")
+                .long("quality-samples")
+                .value_name("片段数")
+                .help("质量评估的采样片段数")
+                .default_value("3")
+        )
+        .arg(
+            Arg::with_name("target-vmaf")
+                .long("target-vmaf")
+                .value_name("分数")
+                .help("目标质量模式：为每个视频搜索达到该VMAF分数的CRF")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("chunked")
+                .long("chunked")
+                .help("在场景切换处切分单个视频，使用全部线程并行编码各分块")
+        )
+        .arg(
+            Arg::with_name("chunk-min")
+                .long("chunk-min")
+                .value_name("秒数")
+                .help("分块编码时每个分块的最短时长（秒）")
+                .default_value("60")
+        )
+        .arg(
+            Arg::with_name("single-pass")
+                .long("single-pass")
+                .help("单遍模式：转码输出直接封装为DASH，不生成完整的AV1中间文件（不支持--quality、--chunked、--sdr-rendition）")
+        )
+        .arg(
+            Arg::with_name("packager")
+                .long("packager")
+                .value_name("封装器")
+                .help("DASH封装方式：ffmpeg 或 native（内置fMP4分片器，按关键帧切分并自行生成MPD）")
+                .possible_values(&["ffmpeg", "native"])
+                .default_value("ffmpeg")
+        )
+        .arg(
+            Arg::with_name("single-file")
+                .long("single-file")
+                .help("每个Representation只输出一个带sidx索引的fMP4文件，MPD使用SegmentBase按字节范围播放")
+        )
+        .arg(
+            Arg::with_name("encrypt")
+                .long("encrypt")
+                .value_name("方案")
+                .help("CENC加密分片：cenc(AES-CTR) 或 cbcs(AES-CBC模式加密)，使用内置分片器，只支持AV1视频")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("key-id")
+                .long("key-id")
+                .value_name("KID")
+                .help("加密使用的KID(32位十六进制)，与--key一起指定时所有视频使用这个密钥，否则每个视频生成独立的密钥")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .value_name("密钥")
+                .help("加密使用的AES-128密钥(32位十六进制)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("keystore")
+                .long("keystore")
+                .value_name("文件")
+                .help("密钥库JSON文件，记录每个视频的KID和密钥，旁边生成ClearKey许可证；应放在发布目录之外")
+                .default_value("keystore.json")
+        )
+        .arg(
+            Arg::with_name("license-url")
+                .long("license-url")
+                .value_name("URL")
+                .help("写入MPD的ClearKey许可证服务器地址")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("hls")
+                .long("hls")
+                .help("同时生成HLS播放列表(fMP4)，与DASH共用分片，使用内置分片器")
+        )
+        .arg(
+            Arg::with_name("hls-encrypt")
+                .long("hls-encrypt")
+                .value_name("方式")
+                .help("加密HLS分片：aes-128(加密整个分片) 或 sample-aes(cbcs加密样本，只支持AV1视频)，HLS使用单独的分片")
+                .possible_values(&["aes-128", "sample-aes"])
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("hls-key-uri")
+                .long("hls-key-uri")
+                .value_name("模板")
+                .help("HLS密钥地址模板，支持{server}、{path}和{key}；包含{server}时每个服务器生成一套播放列表")
+                .default_value("{server}/keys/{path}/{key}")
+        )
+        .arg(
+            Arg::with_name("hls-key-rotation")
+                .long("hls-key-rotation")
+                .value_name("分片数")
+                .help("每N个分片更换一次HLS密钥，0为每个视频只用一个密钥，只支持aes-128")
+                .default_value("0")
+        )
+        .arg(
+            Arg::with_name("hls-key-dir")
+                .long("hls-key-dir")
+                .value_name("路径")
+                .help("HLS密钥的存放目录，按视频输出路径分目录；应放在发布目录之外")
+                .default_value("hls-keys")
+        )
+        .arg(
+            Arg::with_name("multi-period")
+                .long("multi-period")
+                .value_name("分组")
+                .help("把一组视频合并为一个多Period的MPD，每个文件一个Period：folder表示每个文件夹一组，也可以是文件名通配符，如 part*.mp4")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("sdr-rendition")
+                .long("sdr-rendition")
+                .help("为HDR视频额外生成色调映射的SDR版本")
+        )
+        .arg(
+            Arg::with_name("detect-filters")
+                .long("detect-filters")
+                .help("转码前检测黑边和隔行扫描，结果可信时自动裁剪和反交错")
+        )
+        .arg(
+            Arg::with_name("deinterlacer")
+                .long("deinterlacer")
+                .value_name("滤镜")
+                .help("反交错滤镜，QSVEncC64使用bwdif时改用硬件反交错")
+                .possible_values(&["yadif", "bwdif"])
+                .default_value("bwdif")
+        )
+        .arg(
+            Arg::with_name("catalog")
+                .long("catalog")
+                .help("额外生成按文件夹结构分组的目录JSON (catalog_N.json)")
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .help("合并服务器JSON时移除输出目录已不存在的视频")
+        )
+        .arg(
+            Arg::with_name("index-dir")
+                .long("index-dir")
+                .value_name("目录")
+                .help("服务器JSON和目录JSON的输出目录")
+                .default_value(".")
+        )
+        .arg(
+            Arg::with_name("index-name")
+                .long("index-name")
+                .value_name("模板")
+                .help("服务器JSON文件名模板，支持{num}(服务器序号)和{host}(服务器主机名和路径前缀)")
+                .default_value("server_{num}.json")
+        )
+        .arg(
+            Arg::with_name("catalog-name")
+                .long("catalog-name")
+                .value_name("模板")
+                .help("目录JSON文件名模板，支持{num}和{host}")
+                .default_value("catalog_{num}.json")
+        )
+        .arg(
+            Arg::with_name("playlist")
+                .long("playlist")
+                .value_name("格式")
+                .help("为每个服务器额外生成播放列表，多个格式用逗号分隔 (m3u,xspf,rss)")
+                .takes_value(true)
+                .use_delimiter(true)
+                .require_delimiter(true)
+                .multiple(true)
+                .possible_values(&["m3u", "xspf", "rss"])
+        )
+        .arg(
+            Arg::with_name("s3-endpoint")
+                .long("s3-endpoint")
+                .value_name("URL")
+                .help("上传到S3兼容对象存储的地址，如 http://127.0.0.1:9000，密钥从AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY读取")
+                .takes_value(true)
+                .requires("s3-bucket")
+        )
+        .arg(
+            Arg::with_name("s3-bucket")
+                .long("s3-bucket")
+                .value_name("存储桶")
+                .help("上传的目标存储桶")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("s3-region")
+                .long("s3-region")
+                .value_name("区域")
+                .help("S3签名使用的区域")
+                .default_value("us-east-1")
+        )
+        .arg(
+            Arg::with_name("s3-prefix")
+                .long("s3-prefix")
+                .value_name("前缀")
+                .help("上传对象键的前缀")
+                .default_value("")
+        )
+        .arg(
+            Arg::with_name("s3-workers")
+                .long("s3-workers")
+                .value_name("线程数")
+                .help("每个视频并行上传的线程数")
+                .default_value("4")
+        )
+        .arg(
+            Arg::with_name("publish")
+                .long("publish")
+                .value_name("目标")
+                .help("额外发布到的位置，可多次指定：本地目录、webdav:URL 或 rclone:远程:路径，WebDAV账号从WEBDAV_USER/WEBDAV_PASSWORD读取")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("publish-retries")
+                .long("publish-retries")
+                .value_name("次数")
+                .help("发布失败时每个文件的重试次数")
+                .default_value("3")
+        )
+        .arg(
+            Arg::with_name("publish-workers")
+                .long("publish-workers")
+                .value_name("线程数")
+                .help("每个视频并行发布到WebDAV的线程数")
+                .default_value("4")
+        )
+        .arg(
+            Arg::with_name("live")
+                .long("live")
+                .value_name("输入源")
+                .help("直播模式：rtmp://、srt://、udp://地址，device:格式:设备名，或循环播放的本地文件，生成动态MPD")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("live-name")
+                .long("live-name")
+                .value_name("名称")
+                .help("直播输出目录名，位于--output(默认为当前目录)下")
+                .default_value("live-stream")
+        )
+        .arg(
+            Arg::with_name("live-window")
+                .long("live-window")
+                .value_name("分片数")
+                .help("直播MPD中保留的分片数，0表示保留全部分片")
+                .default_value("10")
+        )
+        .arg(
+            Arg::with_name("live-encoder")
+                .long("live-encoder")
+                .value_name("编码器")
+                .help("直播使用的ffmpeg AV1编码器，如 av1_qsv、libsvtav1")
+                .default_value("av1_qsv")
+        )
+        .arg(
+            Arg::with_name("live-bitrate")
+                .long("live-bitrate")
+                .value_name("码率")
+                .help("直播视频码率")
+                .default_value("4M")
+        )
+        .arg(
+            Arg::with_name("low-latency")
+                .long("low-latency")
+                .help("低延迟直播：输出分块CMAF(LL-DASH)，播放器可在分片完成前开始下载")
+        )
+        .arg(
+            Arg::with_name("live-vod")
+                .long("live-vod")
+                .help("直播结束后把动态MPD转为点播MPD，复用保留的分片，并写入服务器JSON")
+        )
+        .arg(
+            Arg::with_name("finalize")
+                .long("finalize")
+                .value_name("直播目录")
+                .help("把已录制的直播输出目录转为点播MPD并写入服务器JSON，不处理其他视频")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("utc-timing")
+                .long("utc-timing")
+                .value_name("URL")
+                .help("写入MPD的UTCTiming时间源，低延迟播放器用它同步时钟")
                 .takes_value(true)
-                .default_value("true")
         )
         .get_matches();
 
     // 获取参数
-    let input_path = matches.value_of("path").unwrap();
     let seg_duration = matches.value_of("time").unwrap().parse::<u32>().unwrap_or(10);
+
+    // 直播模式：持续转换一个直播输入，不处理文件夹
+    if let Some(source) = matches.value_of("live") {
+        let options = LiveOptions {
+            source: LiveSource::parse(source),
+            seg_duration,
+            window: matches.value_of("live-window").unwrap().parse::<u32>().unwrap_or(10),
+            encoder: matches.value_of("live-encoder").unwrap().to_string(),
+            bitrate: matches.value_of("live-bitrate").unwrap().to_string(),
+            low_latency: matches.is_present("low-latency"),
+            utc_timing: matches.value_of("utc-timing").map(String::from),
+        };
+        let dash_dir = matches
+            .value_of("output")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(matches.value_of("live-name").unwrap());
+
+        println!("{}", "🚀 视频DASH流转换工具启动中(直播模式)...".green().bold());
+        println!("{} {}秒", "⏱️ 分片时间:".blue(), seg_duration);
+        let ok = live::run(&options, &dash_dir);
+        // 即使直播异常中断，已经写出的分片也可以转为点播
+        if matches.is_present("live-vod") && !finalize_live(&matches, &dash_dir) {
+            std::process::exit(1);
+        }
+        if !ok {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // 把以前录制的直播输出目录转为点播
+    if let Some(dir) = matches.value_of("finalize") {
+        if !finalize_live(&matches, &PathBuf::from(dir)) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // --copy 控制生成在源文件旁的输出是复制还是移动到输出路径，现在输出直接写入输出路径，
+    // false(移动)与新行为一致；true要求源文件旁另留一份，已无对应行为
+    match matches.value_of("copy") {
+        Some("true") => {
+            println!(
+                "{} --copy 已移除：输出直接按 --layout 写入输出路径；需要另存一份时请使用 --publish <目录>",
+                "❌".red()
+            );
+            std::process::exit(1);
+        }
+        Some(_) => println!("{} --copy 已移除，输出直接按 --layout 写入输出路径", "⚠️".yellow()),
+        None => {}
+    }
+
+    let input_path = matches.value_of("path").unwrap();
+    let segment_tolerance = matches.value_of("segment-tolerance").unwrap().parse::<f64>().unwrap_or(50.0) / 100.0;
     let thread_count = matches.value_of("parallel").unwrap().parse::<usize>().unwrap_or(2);
-    let output_path = matches.value_of("output").map(|p| PathBuf::from(p));
-    let is_copy = matches.value_of("copy").unwrap_or("true") == "true";
-    
-    // 解析服务器URLs
-    let servers = match matches.value_of("serve") {
-        Some(servers_str) => {
-            serde_json::from_str::<Vec<String>>(servers_str).unwrap_or_else(|_| {
-                println!("{}", "❌ 服务器URLs格式错误，应为JSON数组".red());
-                vec![]
+    let output_path = matches.value_of("output").map(PathBuf::from);
+    let layout = OutputLayout {
+        input_root: PathBuf::from(input_path),
+        output_root: output_path.clone().unwrap_or_else(|| PathBuf::from(input_path)),
+        template: matches.value_of("layout").unwrap().to_string(),
+        collision: matches.value_of("on-collision").and_then(CollisionPolicy::parse).unwrap_or(CollisionPolicy::Suffix),
+    };
+    let intermediate_dir = matches
+        .value_of("intermediate-dir")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| layout.output_root.join(".dash-intermediate"));
+    let intermediate = matches
+        .value_of("intermediate")
+        .and_then(|name| IntermediatePolicy::parse(name, &intermediate_dir))
+        .unwrap_or(IntermediatePolicy::Keep);
+    let quality_samples = matches.value_of("quality-samples").unwrap().parse::<u32>().unwrap_or(3);
+    let quality = matches.value_of("quality").and_then(QualityMetric::parse).map(|metric| QualityOptions {
+        metric,
+        threshold: matches.value_of("quality-min")
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or_else(|| metric.default_threshold()),
+        retries: matches.value_of("quality-retry").unwrap().parse::<u32>().unwrap_or(1),
+        samples: quality_samples,
+    });
+    let target = matches.value_of("target-vmaf").and_then(|v| v.parse::<f64>().ok()).map(|vmaf| TargetQuality {
+        vmaf,
+        samples: quality_samples,
+    });
+    let chunked = if matches.is_present("chunked") {
+        Some(ChunkOptions {
+            min_seconds: matches.value_of("chunk-min").unwrap().parse::<f64>().unwrap_or(60.0),
+            workers: thread_count,
+        })
+    } else {
+        None
+    };
+    let encode_options = EncodeOptions {
+        quality,
+        target,
+        chunked,
+        sdr_rendition: matches.is_present("sdr-rendition"),
+        detect_filters: matches.is_present("detect-filters"),
+        deinterlacer: matches.value_of("deinterlacer").and_then(Deinterlacer::parse).unwrap_or_default(),
+        single_pass: matches.is_present("single-pass"),
+        keyframe_seconds: seg_duration,
+    };
+    // 解析服务器URLs，每一项可以是URL字符串或带路径前缀的对象
+    let servers = parse_servers(&matches);
+
+    let encryption = match matches.value_of("encrypt") {
+        Some(name) => {
+            let Some(scheme) = Scheme::parse(name) else {
+                println!("{} 无效的加密方案: {}, 可选 cenc 或 cbcs", "❌".red(), name);
+                return;
+            };
+            let key = match (matches.value_of("key-id"), matches.value_of("key")) {
+                (Some(kid), Some(key)) => match ContentKey::parse(kid, key) {
+                    Ok(key) => Some(key),
+                    Err(e) => {
+                        println!("{} {}", "❌".red(), e);
+                        return;
+                    }
+                },
+                (None, None) => None,
+                _ => {
+                    println!("{} --key-id 和 --key 需要同时指定", "❌".red());
+                    return;
+                }
+            };
+            let keystore_path = PathBuf::from(matches.value_of("keystore").unwrap());
+            let Some(keystore) = Keystore::open(&keystore_path) else {
+                return;
+            };
+            if matches.is_present("single-pass") {
+                println!("{} 加密由内置分片器完成, 不能与 --single-pass 一起使用", "❌".red());
+                return;
+            }
+            Some(EncryptionOptions {
+                scheme,
+                key,
+                license_url: matches.value_of("license-url").map(String::from),
+                keystore: Arc::new(Mutex::new(keystore)),
             })
-        },
-        None => vec![],
+        }
+        None => None,
+    };
+    // 指定--hls-encrypt时也生成HLS
+    let hls = if matches.is_present("hls") || matches.is_present("hls-encrypt") {
+        if matches.is_present("single-pass") || matches.is_present("single-file") {
+            println!("{} HLS由内置分片器按分片生成, 不能与 --single-pass 或 --single-file 一起使用", "❌".red());
+            return;
+        }
+        let encryption = matches.value_of("hls-encrypt").and_then(HlsMethod::parse).map(|method| HlsEncryption {
+            method,
+            rotation: matches.value_of("hls-key-rotation").unwrap().parse::<usize>().unwrap_or(0),
+            key_uri: matches.value_of("hls-key-uri").unwrap().to_string(),
+            key_dir: PathBuf::from(matches.value_of("hls-key-dir").unwrap()),
+        });
+        // SAMPLE-AES的KID和IV写在初始化分片的tenc中，每个Representation只有一个EXT-X-MAP，无法轮换密钥
+        if encryption.as_ref().is_some_and(|e| e.method == HlsMethod::SampleAes && e.rotation > 0) {
+            println!("{} --hls-key-rotation 只能用于 aes-128, sample-aes 的分片共用一个初始化分片, 无法轮换密钥", "❌".red());
+            return;
+        }
+        // CENC加密的分片不能直接用于HLS
+        if encryption.is_none() && matches.is_present("encrypt") {
+            println!("{} --encrypt 的分片无法用于HLS, 请同时指定 --hls-encrypt", "❌".red());
+            return;
+        }
+        Some(HlsOptions {
+            encryption,
+            servers: servers.iter().map(|server| server.base_url()).collect(),
+        })
+    } else {
+        None
+    };
+    let settings = PackageSettings {
+        encode_options,
+        seg_duration,
+        segment_tolerance,
+        packager: matches.value_of("packager").and_then(Packager::parse).unwrap_or(Packager::Ffmpeg),
+        single_file: matches.is_present("single-file"),
+        encryption,
+        hls,
     };
+    let group_mode = match matches.value_of("multi-period").map(GroupMode::parse) {
+        Some(None) => {
+            println!("{} 无效的--multi-period分组: {}", "❌".red(), matches.value_of("multi-period").unwrap());
+            return;
+        }
+        Some(mode) => mode,
+        None => None,
+    };
+
+    // 发布目标：--publish 指定的位置和S3
+    let publish_retries = matches.value_of("publish-retries").unwrap().parse::<u32>().unwrap_or(3);
+    let publish_workers = matches.value_of("publish-workers").unwrap().parse::<usize>().unwrap_or(4);
+    let mut publishers: Vec<Box<dyn Publisher>> = matches
+        .values_of("publish")
+        .map(|values| values.map(|spec| parse_target(spec, publish_retries, publish_workers)).collect())
+        .unwrap_or_default();
+    if let (Some(endpoint), Some(bucket)) = (matches.value_of("s3-endpoint"), matches.value_of("s3-bucket")) {
+        let workers = matches.value_of("s3-workers").unwrap().parse::<usize>().unwrap_or(4);
+        match S3Config::from_env(
+            endpoint,
+            bucket,
+            matches.value_of("s3-region").unwrap(),
+            matches.value_of("s3-prefix").unwrap(),
+            workers,
+            publish_retries,
+        ) {
+            Some(config) => publishers.push(Box::new(S3Uploader::new(config))),
+            None => {
+                println!("{}", "❌ 未设置AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY环境变量".red());
+                return;
+            }
+        }
+    }
+    let publishers = Arc::new(publishers);
 
     println!("{}", "🚀 视频DASH流转换工具启动中...".green().bold());
     println!("{} {}", "📂 输入路径:".blue(), input_path);
     println!("{} {}秒", "⏱️ 分片时间:".blue(), seg_duration);
     println!("{} {}", "🧵 并行线程数:".blue(), thread_count);
+    if settings.encode_options.single_pass {
+        println!("{} 单遍编码", "🌊 模式:".blue());
+        if settings.encode_options.quality.is_some() || settings.encode_options.chunked.is_some() || settings.encode_options.sdr_rendition {
+            println!("{} 单遍模式下忽略 --quality、--chunked 和 --sdr-rendition", "⚠️".yellow());
+        }
+    }
+    if settings.packager == Packager::Native {
+        println!("{} 内置fMP4分片器", "📦 封装:".blue());
+    }
+    if settings.single_file {
+        println!("{} 每个Representation一个文件(SegmentBase)", "📄 单文件:".blue());
+    }
+    if let Some(ref encryption) = settings.encryption {
+        let keystore = PathBuf::from(matches.value_of("keystore").unwrap());
+        println!("{} {}, 密钥库 {}", "🔐 加密:".blue(), encryption.scheme.name(), keystore.display());
+        // 密钥库在输出路径下时可能随输出一起被托管
+        let keystore_dir = keystore.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if let (Ok(keystore_dir), Ok(output_root)) = (keystore_dir.canonicalize(), layout.output_root.canonicalize()) {
+            if keystore_dir.starts_with(&output_root) {
+                println!("{} 密钥库位于输出路径 {} 下, 请确认它不会被发布", "⚠️".yellow(), output_root.display());
+            }
+        }
+    }
+    if let Some(ref hls) = settings.hls {
+        match hls.encryption {
+            Some(ref encryption) => {
+                let rotation = match encryption.rotation {
+                    0 => String::from("不轮换"),
+                    n => format!("每{}个分片轮换", n),
+                };
+                println!("{} {}, {}, 密钥目录 {}", "🍎 HLS:".blue(), encryption.method.name(), rotation, encryption.key_dir.display());
+                if let (Ok(key_dir), Ok(output_root)) = (encryption.key_dir.canonicalize(), layout.output_root.canonicalize()) {
+                    if key_dir.starts_with(&output_root) {
+                        println!("{} HLS密钥目录位于输出路径 {} 下, 请确认它不会被发布", "⚠️".yellow(), output_root.display());
+                    }
+                }
+            }
+            None => println!("{} 生成HLS播放列表", "🍎 HLS:".blue()),
+        }
+        if group_mode.is_some() {
+            println!("{} 多Period组不生成HLS播放列表", "⚠️".yellow());
+        }
+    }
+    if let Some(GroupMode::Glob(_)) = group_mode {
+        println!("{} 同一文件夹中匹配 {} 的文件合并为一个MPD", "🎞️ 多Period:".blue(), matches.value_of("multi-period").unwrap());
+    } else if group_mode.is_some() {
+        println!("{} 每个文件夹合并为一个MPD", "🎞️ 多Period:".blue());
+    }
+    if let Some(ref chunked) = settings.encode_options.chunked {
+        println!("{} 最短 {}秒, {}个线程编码分块, 每次处理一个视频", "🧩 分块编码:".blue(), chunked.min_seconds, chunked.workers);
+    }
+    if let Some(ref target) = settings.encode_options.target {
+        println!("{} VMAF {}", "🎯 目标质量:".blue(), target.vmaf);
+    }
+    if let Some(ref quality) = settings.encode_options.quality {
+        println!("{} {} >= {}", "📏 质量校验:".blue(), quality.metric.name().to_uppercase(), quality.threshold);
+    }
     
     if !servers.is_empty() {
         println!("{}", "🌐 服务器URLs:".blue());
         for (i, server) in servers.iter().enumerate() {
-            println!("   {}. {}", i+1, server);
+            println!("   {}. {}", i+1, server.base_url());
         }
     }
 
     // 获取视频文件列表
-    let video_files = get_video_files(input_path);
+    let mut video_files = get_video_files(input_path);
+    video_files.sort();
     if video_files.is_empty() {
         println!("{}", "❌ 未找到视频文件！".red().bold());
         return;
@@ -108,66 +761,146 @@ fn main() {
 
     println!("{} {} 个视频文件", "🎬 找到:".green(), video_files.len());
 
-    // 创建线程池
-    let pool = ThreadPool::new(thread_count);
+    // 多Period模式下同组的视频作为一个条目处理
+    let (sources, groups) = match group_mode {
+        Some(ref mode) => periods::group(video_files, mode),
+        None => (video_files, Default::default()),
+    };
+    if !groups.is_empty() {
+        println!("{} {} 组多Period视频", "🎞️ 找到:".green(), groups.len());
+    }
+    let groups = Arc::new(groups);
+
+    // 分配每个视频的输出目录
+    let plans = match layout.plan(&sources) {
+        Ok(plans) => plans,
+        Err(e) => {
+            println!("{} {}", "❌".red(), e);
+            return;
+        }
+    };
+
+    // 创建线程池；分块编码时并行线程用于编码同一个视频的分块，视频逐个处理，避免同时运行的编码器成倍增加
+    let video_threads = if settings.encode_options.chunked.is_some() { 1 } else { thread_count };
+    let pool = ThreadPool::new(video_threads);
     let counter = Arc::new(AtomicUsize::new(0));
     let processed_videos = Arc::new(Mutex::new(Vec::new()));
+    let flagged_videos = Arc::new(Mutex::new(Vec::new()));
+    let reclaimed = Arc::new(AtomicU64::new(0));
     
     // 处理每个视频文件
-    for video_path in video_files {
+    for plan in plans {
         let counter = counter.clone();
         let processed_videos = processed_videos.clone();
+        let flagged_videos = flagged_videos.clone();
+        let settings = settings.clone();
+        let groups = groups.clone();
         // let servers = servers.clone();
-        let output_path = output_path.clone();
-        let is_copy = is_copy;
-        let seg_duration = seg_duration;
+        let input_root = PathBuf::from(input_path);
+        let publishers = publishers.clone();
+        let intermediate = intermediate.clone();
+        let reclaimed = reclaimed.clone();
         
         pool.execute(move || {
-            let video_processor = VideoProcessor::new(&video_path);
-            let file_name = video_processor.get_file_name();
+            let video_path = plan.source;
+            let file_name = video_path.file_name().unwrap_or_default().to_string_lossy().to_string();
             let thread_id = counter.fetch_add(1, Ordering::SeqCst) + 1;
             
-            println!("{} [线程 {}] 开始处理: {}", "🔄".yellow(), thread_id, file_name);
-            
-            // 设置输出目录
-            let (dash_dir, av1_dir, live_dir) = setup_output_dirs(&video_path);
-            
-            // 处理视频
-            if let Some(processed_path) = video_processor.process(&av1_dir) {
-                // 生成DASH流
-                let dash_generator = DashGenerator::new(&processed_path, &dash_dir, seg_duration);
-                if dash_generator.generate_dash(&live_dir) {
-                    println!("{} [线程 {}] {} 处理完成", "✅".green(), thread_id, file_name);
-                    
-                    // 记录处理成功的视频
-                    let mut videos = processed_videos.lock().unwrap();
-                    videos.push((video_path.clone(), dash_dir.clone()));
-                    
-                    // 如果指定了输出路径，复制或移动文件
-                    if let Some(ref out_path) = output_path {
-                        let target_dir = out_path.join(Path::new(&dash_dir).file_name().unwrap());
-                        
-                        if is_copy {
-                            println!("{} [线程 {}] 正在复制 {} 到 {}", "📋".blue(), thread_id, 
-                                dash_dir.display(), target_dir.display());
-                            match fs_extra::dir::copy(&dash_dir, out_path, &fs_extra::dir::CopyOptions::new()) {
-                                Ok(_) => println!("{} [线程 {}] 复制成功", "✅".green(), thread_id),
-                                Err(e) => println!("{} [线程 {}] 复制失败: {}", "❌".red(), thread_id, e),
+            // 在输出位置旁的工作目录中处理，完成后整体重命名为输出目录
+            let dash_dir = prepare_work_dir(&plan.target);
+
+            // 加密时整个输出(包括多Period组的所有成员)使用同一个密钥
+            let encryption = match settings.encryption {
+                Some(ref options) => match options.for_video(&plan.path) {
+                    Some(encryption) => Some(encryption),
+                    None => {
+                        println!("{} [线程 {}] {} 无法取得加密密钥", "❌".red(), thread_id, file_name);
+                        return;
+                    }
+                },
+                None => None,
+            };
+            let hls = settings.hls.as_ref().map(|options| options.for_video(&plan.path));
+
+            // 多Period组的每个成员处理到各自的 period_N 子目录，再合并MPD
+            let members = groups.get(&video_path);
+            let packaged = match members {
+                Some(members) => {
+                    println!("{} [线程 {}] 开始处理多Period组: {} ({}个文件)", "🔄".yellow(), thread_id, file_name, members.len());
+                    let mut first = None;
+                    for (index, member) in members.iter().enumerate() {
+                        match package_video(member, &dash_dir.join(periods::period_dir(index)), &settings, encryption.as_ref(), None, thread_id, &flagged_videos) {
+                            Some(processed_path) => {
+                                first.get_or_insert(processed_path);
+                            }
+                            None => {
+                                first = None;
+                                break;
                             }
-                        } else {
-                            println!("{} [线程 {}] 正在移动 {} 到 {}", "🚚".blue(), thread_id, 
-                                dash_dir.display(), target_dir.display());
-                            match fs::rename(&dash_dir, &target_dir) {
-                                Ok(_) => println!("{} [线程 {}] 移动成功", "✅".green(), thread_id),
-                                Err(e) => println!("{} [线程 {}] 移动失败: {}", "❌".red(), thread_id, e),
+                        }
+                    }
+                    let merged = first.and_then(|processed_path| {
+                        periods::merge(&dash_dir, members).map(|merged| (processed_path, Some(merged)))
+                    });
+                    if merged.is_some() {
+                        JobReport::new(&video_path).write(&dash_dir);
+                    }
+                    merged
+                }
+                None => {
+                    println!("{} [线程 {}] 开始处理: {}", "🔄".yellow(), thread_id, file_name);
+                    package_video(&video_path, &dash_dir, &settings, encryption.as_ref(), hls.as_ref(), thread_id, &flagged_videos)
+                        .map(|processed_path| (processed_path, None))
+                }
+            };
+
+            if let Some((processed_path, merged)) = packaged {
+                println!("{} [线程 {}] {} 处理完成", "✅".green(), thread_id, file_name);
+
+                write_chapters(&video_path, &dash_dir, &processed_path, merged.as_ref());
+
+                // 记录处理成功的视频，多Period组的时长为各成员之和
+                let mut record = VideoRecord::collect(&video_path, &dash_dir, &processed_path, &input_root, &plan.path);
+                if let Some((duration, _)) = merged {
+                    record.duration = duration;
+                }
+
+                // 处理av1中间文件
+                let av1_dirs: Vec<(PathBuf, String)> = match members {
+                    Some(members) => (0..members.len())
+                        .map(|index| {
+                            let period = periods::period_dir(index);
+                            (dash_dir.join(&period).join("av1"), format!("{}/{}", plan.path, period))
+                        })
+                        .collect(),
+                    None => vec![(dash_dir.join("av1"), plan.path.clone())],
+                };
+                for (av1_dir, path) in av1_dirs {
+                    reclaimed.fetch_add(intermediate.apply(&av1_dir, &path), Ordering::SeqCst);
+                }
+
+                // 工作目录就位为最终输出目录，成功后才写入索引并从最终目录发布；
+                // 失败时输出目录不存在，不记录该视频，工作目录下次运行也会被清理
+                match deliver(&dash_dir, &plan.target, false) {
+                    Ok(_) => {
+                        println!("{} [线程 {}] 输出到 {}", "📁".blue(), thread_id, plan.target.display());
+                        processed_videos.lock().unwrap().push(record);
+
+                        // 发布到各个目标
+                        for publisher in publishers.iter() {
+                            if !publisher.publish(&plan.target, &plan.path) {
+                                println!("{} [线程 {}] {} 发布到 {} 失败", "❌".red(), thread_id, file_name,
+                                    publisher.describe(&plan.path));
                             }
                         }
                     }
-                } else {
-                    println!("{} [线程 {}] {} DASH生成失败", "❌".red(), thread_id, file_name);
+                    Err(e) => {
+                        println!("{} [线程 {}] 输出失败: {}", "❌".red(), thread_id, e);
+                        let _ = std::fs::remove_dir_all(&dash_dir);
+                    }
                 }
-            } else {
-                println!("{} [线程 {}] {} 处理失败", "❌".red(), thread_id, file_name);
+            } else if members.is_some() {
+                println!("{} [线程 {}] 多Period组 {} 处理失败", "❌".red(), thread_id, file_name);
             }
         });
     }
@@ -175,11 +908,221 @@ fn main() {
     // 等待所有任务完成
     pool.join();
 
+    // 根据密钥库生成ClearKey许可证
+    if let Some(ref encryption) = settings.encryption {
+        encryption.keystore.lock().unwrap().write_license();
+    }
+
     // 生成服务器JSON文件
-    if !servers.is_empty() {
-        let processed_videos = processed_videos.lock().unwrap();
-        generate_server_json(&processed_videos, &servers);
+    write_indexes(&matches, &processed_videos.lock().unwrap(), &servers, PathBuf::from(input_path), output_path.clone());
+
+    let flagged_videos = flagged_videos.lock().unwrap();
+    if !flagged_videos.is_empty() {
+        println!("{} {} 个视频质量未达标:", "⚠️".yellow(), flagged_videos.len());
+        for name in flagged_videos.iter() {
+            println!("   - {}", name);
+        }
+    }
+
+    let reclaimed = reclaimed.load(Ordering::SeqCst);
+    if reclaimed > 0 {
+        let action = if intermediate == IntermediatePolicy::Delete { "释放" } else { "移出" };
+        println!("{} 中间文件共{} {}", "🧹".blue(), action, format_size(reclaimed));
     }
 
     println!("{}", "🎉 所有视频处理完成！".green().bold());
-}
\ No newline at end of file
+}
+
+// 解析服务器URLs，每一项可以是URL字符串或带路径前缀的对象
+fn parse_servers(matches: &ArgMatches) -> Vec<ServerConfig> {
+    match matches.value_of("serve") {
+        Some(servers_str) => {
+            serde_json::from_str::<Vec<ServerSpec>>(servers_str)
+                .map(|specs| specs.into_iter().map(ServerConfig::from).collect())
+                .unwrap_or_else(|_| {
+                    println!("{}", "❌ 服务器URLs格式错误，应为JSON数组".red());
+                    vec![]
+                })
+        },
+        None => vec![],
+    }
+}
+
+// 合并写入服务器JSON，并按需生成目录JSON和播放列表
+fn write_indexes(matches: &ArgMatches, records: &[VideoRecord], servers: &[ServerConfig], input_root: PathBuf, output_root: Option<PathBuf>) {
+    if servers.is_empty() {
+        return;
+    }
+    let index_options = IndexOptions {
+        input_root,
+        output_root,
+        prune: matches.is_present("prune"),
+        index_dir: PathBuf::from(matches.value_of("index-dir").unwrap()),
+        index_name: matches.value_of("index-name").unwrap().to_string(),
+        catalog_name: matches.value_of("catalog-name").unwrap().to_string(),
+    };
+    if !index_options.check_templates(servers) {
+        return;
+    }
+    let indexes = generate_server_json(records, servers, &index_options);
+    if matches.is_present("catalog") {
+        generate_catalog_json(&indexes, servers, &index_options);
+    }
+
+    let playlist_formats: Vec<PlaylistFormat> = matches
+        .values_of("playlist")
+        .map(|values| values.filter_map(PlaylistFormat::parse).collect())
+        .unwrap_or_default();
+    generate_playlists(&indexes, servers, &index_options, &playlist_formats);
+}
+
+// 直播输出转为点播后像批量转换的视频一样写入服务器JSON，目录名作为标题和路径
+fn finalize_live(matches: &ArgMatches, dash_dir: &Path) -> bool {
+    let Some(duration) = live::finalize(dash_dir) else {
+        return false;
+    };
+    let servers = parse_servers(matches);
+    if servers.is_empty() {
+        return true;
+    }
+    let output_root = dash_dir.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let path = dash_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+    // 时长以重建的分片列表为准，不依赖ffprobe读取MPD
+    let mut record = VideoRecord::collect(dash_dir, dash_dir, &dash_dir.join("main.mpd"), &output_root, &path);
+    record.duration = duration;
+    write_indexes(matches, &[record], &servers, output_root.clone(), Some(output_root));
+    true
+}
+
+
+// 转码和封装设置，所有工作线程共用
+#[derive(Clone)]
+struct PackageSettings {
+    encode_options: EncodeOptions,
+    seg_duration: u32,
+    segment_tolerance: f64,
+    packager: Packager,
+    single_file: bool,
+    encryption: Option<EncryptionOptions>,
+    hls: Option<HlsOptions>,
+}
+
+// 转码一个视频并在dash_dir中生成DASH流，成功时返回用于读取元数据的视频路径
+fn package_video(
+    video_path: &Path,
+    dash_dir: &Path,
+    settings: &PackageSettings,
+    encryption: Option<&Encryption>,
+    hls: Option<&Hls>,
+    thread_id: usize,
+    flagged_videos: &Mutex<Vec<String>>,
+) -> Option<PathBuf> {
+    let video_processor = VideoProcessor::new(video_path, settings.encode_options.clone());
+    let file_name = video_processor.get_file_name();
+    let seg_duration = settings.seg_duration;
+    let (dash_dir, av1_dir, live_dir) = setup_output_dirs(dash_dir);
+
+    let mut report = JobReport::new(video_path);
+    if settings.encode_options.single_pass {
+        let dash_generator = DashGenerator::new(video_path, &dash_dir, seg_duration)
+            .with_packager(settings.packager)
+            .with_single_file(settings.single_file);
+        let ok = video_processor.process_streaming(&av1_dir, &dash_generator, &live_dir, &mut report);
+        if ok {
+            if let Alignment::Drift(stats) | Alignment::Exceeded(stats) = alignment::check(&dash_dir, seg_duration, settings.segment_tolerance) {
+                report.note(format!("分片时长与目标不一致: {}", stats.describe()));
+            }
+        }
+        report.write(&dash_dir);
+        if ok {
+            Some(video_path.to_path_buf())
+        } else {
+            println!("{} [线程 {}] {} 处理失败", "❌".red(), thread_id, file_name);
+            None
+        }
+    } else if let Some(processed_path) = video_processor.process(&av1_dir, &mut report) {
+        if report.flagged {
+            flagged_videos.lock().unwrap().push(file_name.clone());
+        }
+
+        // HDR视频可选生成SDR版本
+        let sdr_path = if settings.encode_options.sdr_rendition {
+            video_processor.tonemap_sdr(&av1_dir, &mut report)
+        } else {
+            None
+        };
+        report.write(&dash_dir);
+
+        // 生成DASH流并检查分片时长；直接复制的AV1源GOP过长导致分片超出容差时，按分片时长重新编码一次
+        let mut processed_path = processed_path;
+        let packaged = loop {
+            let mut dash_generator = DashGenerator::new(&processed_path, &dash_dir, seg_duration)
+                .with_packager(settings.packager)
+                .with_single_file(settings.single_file);
+            if let Some(ref sdr_path) = sdr_path {
+                dash_generator = dash_generator.with_sdr_rendition(sdr_path);
+            }
+            if let Some(encryption) = encryption {
+                dash_generator = dash_generator.with_encryption(encryption);
+            }
+            if let Some(hls) = hls {
+                dash_generator = dash_generator.with_hls(hls);
+            }
+            if !dash_generator.generate_dash(&live_dir) {
+                break false;
+            }
+
+            match alignment::check(&dash_dir, seg_duration, settings.segment_tolerance) {
+                Alignment::Exceeded(stats) if report.encoder.as_deref() == Some("copy") => {
+                    report.note(format!("源视频关键帧间隔过长({})，已按分片时长重新编码", stats.describe()));
+                    match video_processor.realign(&av1_dir, &mut report) {
+                        Some(path) => {
+                            processed_path = path;
+                            let _ = std::fs::remove_dir_all(&live_dir);
+                        }
+                        None => break true,
+                    }
+                }
+                Alignment::Exceeded(stats) | Alignment::Drift(stats) => {
+                    report.note(format!("分片时长与目标不一致: {}", stats.describe()));
+                    break true;
+                }
+                Alignment::Aligned | Alignment::Unknown => break true,
+            }
+        };
+        report.write(&dash_dir);
+
+        if packaged {
+            Some(processed_path)
+        } else {
+            println!("{} [线程 {}] {} DASH生成失败", "❌".red(), thread_id, file_name);
+            None
+        }
+    } else {
+        println!("{} [线程 {}] {} 处理失败", "❌".red(), thread_id, file_name);
+        None
+    }
+}
+
+// 在输出目录写出WebVTT章节：优先使用章节文件，其次是源文件中的章节，多Period组默认每个文件一章
+fn write_chapters(source: &Path, dash_dir: &Path, processed_path: &Path, merged: Option<&(f64, Vec<Chapter>)>) {
+    let chapters = match chapters::find_sidecar(source, merged.is_some()) {
+        Some(path) => {
+            println!("{} 使用章节文件: {}", "📑".blue(), path.display());
+            chapters::read_sidecar(&path)
+        }
+        None => match merged {
+            Some((_, chapters)) => chapters.clone(),
+            None => probe_chapters(source),
+        },
+    };
+    if chapters.is_empty() {
+        return;
+    }
+
+    let duration = match merged {
+        Some((duration, _)) => *duration,
+        None => probe_media(processed_path).map(|info| info.duration).unwrap_or(0.0),
+    };
+    chapters::write_vtt(dash_dir, &chapters, duration);
+}
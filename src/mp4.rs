@@ -0,0 +1,1218 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::encryption::{av1_subsamples, Protection, Scheme, CLEARKEY_SYSTEM_ID};
+
+// ISO-BMFF（MP4）的读取和分片写入，只处理VideoProcessor生成的普通MP4：
+// 读取moov中的样本表，按样本的文件偏移从mdat中取数据，写出fMP4的初始化分片和媒体分片
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+}
+
+// 单个样本（视频帧或音频帧），时间以轨道的timescale为单位
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub offset: u64,
+    pub size: u32,
+    pub dts: u64,
+    pub duration: u32,
+    pub cts_offset: i32,
+    pub sync: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub id: u32,
+    pub kind: TrackKind,
+    pub timescale: u32,
+    pub language: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub codec: String,
+    // 编辑列表中第一段的起始媒体时间，对应MPD中的presentationTimeOffset
+    pub media_time: u64,
+    pub samples: Vec<Sample>,
+    tkhd: Vec<u8>,
+    mdhd: Vec<u8>,
+    hdlr: Vec<u8>,
+    // minf中除stbl外的子box，如vmhd/smhd/dinf
+    minf_children: Vec<([u8; 4], Vec<u8>)>,
+    stsd: Vec<u8>,
+}
+
+impl Track {
+    // 轨道总时长（秒）
+    pub fn duration(&self) -> f64 {
+        match self.samples.last() {
+            Some(last) => (last.dts + last.duration as u64) as f64 / self.timescale as f64,
+            None => 0.0,
+        }
+    }
+}
+
+// 读取MP4文件中的视频和音频轨道
+pub fn read_tracks(path: &Path) -> Result<Vec<Track>, String> {
+    let mut file = File::open(path).map_err(|e| format!("打开 {} 失败: {}", path.display(), e))?;
+    let file_size = file.metadata().map_err(|e| e.to_string())?.len();
+
+    // 在顶层box中查找moov，mdat不读入内存
+    let mut position = 0;
+    let moov = loop {
+        if position + 8 > file_size {
+            return Err(String::from("没有找到moov"));
+        }
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(position)).map_err(|e| e.to_string())?;
+        file.read_exact(&mut header[..8]).map_err(|e| e.to_string())?;
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            file.read_exact(&mut header[8..16]).map_err(|e| e.to_string())?;
+            size = u64::from_be_bytes(header[8..16].try_into().unwrap());
+            header_len = 16;
+        } else if size == 0 {
+            size = file_size - position;
+        }
+        if size < header_len {
+            return Err(String::from("box大小无效"));
+        }
+
+        if &header[4..8] == b"moov" {
+            let mut payload = vec![0u8; (size - header_len) as usize];
+            file.read_exact(&mut payload).map_err(|e| e.to_string())?;
+            break payload;
+        }
+        position += size;
+    };
+
+    let mut tracks = Vec::new();
+    for (kind, payload) in children(&moov) {
+        if &kind == b"trak" {
+            if let Some(track) = parse_track(payload) {
+                tracks.push(track);
+            }
+        }
+    }
+    if tracks.is_empty() {
+        return Err(String::from("没有可用的视频或音频轨道"));
+    }
+    Ok(tracks)
+}
+
+// 大端读取的游标，越界时返回None
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8).map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos.min(self.data.len())..]
+    }
+}
+
+// 列出一段数据中的子box，返回(类型, 内容)
+fn children(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let mut size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let mut header_len = 8;
+        if size == 1 {
+            if pos + 16 > data.len() {
+                break;
+            }
+            size = u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()) as usize;
+            header_len = 16;
+        } else if size == 0 {
+            size = data.len() - pos;
+        }
+        if size < header_len || pos + size > data.len() {
+            break;
+        }
+        boxes.push((kind, &data[pos + header_len..pos + size]));
+        pos += size;
+    }
+    boxes
+}
+
+fn find<'a>(boxes: &[([u8; 4], &'a [u8])], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes.iter().find(|(k, _)| k == kind).map(|(_, payload)| *payload)
+}
+
+fn parse_track(trak: &[u8]) -> Option<Track> {
+    let trak_boxes = children(trak);
+    let tkhd = find(&trak_boxes, b"tkhd")?;
+    let mdia_boxes = children(find(&trak_boxes, b"mdia")?);
+    let mdhd = find(&mdia_boxes, b"mdhd")?;
+    let hdlr = find(&mdia_boxes, b"hdlr")?;
+    let minf_boxes = children(find(&mdia_boxes, b"minf")?);
+    let stbl_boxes = children(find(&minf_boxes, b"stbl")?);
+    let stsd = find(&stbl_boxes, b"stsd")?;
+
+    let kind = match hdlr.get(8..12)? {
+        b"vide" => TrackKind::Video,
+        b"soun" => TrackKind::Audio,
+        _ => return None,
+    };
+
+    // tkhd: 轨道ID
+    let mut reader = Reader::new(tkhd);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    reader.skip(if version == 1 { 16 } else { 8 })?;
+    let id = reader.u32()?;
+
+    // mdhd: timescale和语言
+    let mut reader = Reader::new(mdhd);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    reader.skip(if version == 1 { 16 } else { 8 })?;
+    let timescale = reader.u32()?;
+    reader.skip(if version == 1 { 8 } else { 4 })?;
+    let language = decode_language(reader.u16()?);
+
+    let media_time = find(&trak_boxes, b"edts")
+        .and_then(|edts| find(&children(edts), b"elst"))
+        .and_then(parse_edit_list)
+        .unwrap_or(0);
+
+    let mut track = Track {
+        id,
+        kind,
+        timescale,
+        language,
+        width: 0,
+        height: 0,
+        channels: 0,
+        sample_rate: 0,
+        codec: String::new(),
+        media_time,
+        samples: parse_samples(&stbl_boxes)?,
+        tkhd: tkhd.to_vec(),
+        mdhd: mdhd.to_vec(),
+        hdlr: hdlr.to_vec(),
+        minf_children: minf_boxes
+            .iter()
+            .filter(|(kind, _)| kind != b"stbl")
+            .map(|(kind, payload)| (*kind, payload.to_vec()))
+            .collect(),
+        stsd: stsd.to_vec(),
+    };
+    parse_sample_entry(&mut track, stsd)?;
+    if track.samples.is_empty() || timescale == 0 {
+        return None;
+    }
+    Some(track)
+}
+
+// mdhd中的语言为3个5位字符，und表示未指定
+fn decode_language(packed: u16) -> Option<String> {
+    let language: String = [(packed >> 10) & 0x1f, (packed >> 5) & 0x1f, packed & 0x1f]
+        .iter()
+        .map(|c| (*c as u8 + 0x60) as char)
+        .collect();
+    if language == "und" || !language.chars().all(|c| c.is_ascii_lowercase()) {
+        None
+    } else {
+        Some(language)
+    }
+}
+
+// 取编辑列表中第一个非空编辑的起始媒体时间
+fn parse_edit_list(elst: &[u8]) -> Option<u64> {
+    let mut reader = Reader::new(elst);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let media_time = if version == 1 {
+            reader.skip(8)?;
+            reader.u64()? as i64
+        } else {
+            reader.skip(4)?;
+            reader.u32()? as i32 as i64
+        };
+        reader.skip(4)?;
+        if media_time >= 0 {
+            return Some(media_time as u64);
+        }
+    }
+    None
+}
+
+// 从样本描述中读取编码、分辨率和声道信息，生成MPD中的codecs字符串
+fn parse_sample_entry(track: &mut Track, stsd: &[u8]) -> Option<()> {
+    let entries = children(stsd.get(8..)?);
+    let (format, entry) = entries.first()?;
+    let fourcc = String::from_utf8_lossy(format).to_string();
+
+    match track.kind {
+        TrackKind::Video => {
+            let mut reader = Reader::new(entry);
+            reader.skip(24)?;
+            track.width = reader.u16()? as u32;
+            track.height = reader.u16()? as u32;
+            let boxes = children(entry.get(78..)?);
+            track.codec = match format {
+                b"av01" => find(&boxes, b"av1C").and_then(av1_codec).unwrap_or(fourcc),
+                _ => fourcc,
+            };
+        }
+        TrackKind::Audio => {
+            let mut reader = Reader::new(entry);
+            reader.skip(16)?;
+            track.channels = reader.u16()?;
+            reader.skip(6)?;
+            track.sample_rate = reader.u32()? >> 16;
+            let boxes = children(entry.get(28..)?);
+            track.codec = match format {
+                b"mp4a" => find(&boxes, b"esds").and_then(mp4a_codec).unwrap_or(fourcc),
+                b"Opus" => String::from("opus"),
+                b"fLaC" => String::from("flac"),
+                _ => fourcc,
+            };
+            // Opus的输出声道数以dOps为准
+            if let Some(dops) = find(&boxes, b"dOps") {
+                track.channels = *dops.get(1)? as u16;
+            }
+        }
+    }
+    Some(())
+}
+
+// av1C -> av01.P.LLT.DD
+fn av1_codec(av1c: &[u8]) -> Option<String> {
+    let profile = av1c.get(1)? >> 5;
+    let level = av1c.get(1)? & 0x1f;
+    let flags = *av1c.get(2)?;
+    let tier = if flags & 0x80 != 0 { 'H' } else { 'M' };
+    let bit_depth = match (flags & 0x40 != 0, flags & 0x20 != 0) {
+        (true, true) => 12,
+        (true, false) => 10,
+        _ => 8,
+    };
+    Some(format!("av01.{}.{:02}{}.{:02}", profile, level, tier, bit_depth))
+}
+
+// esds -> mp4a.40.2 之类
+fn mp4a_codec(esds: &[u8]) -> Option<String> {
+    let mut reader = Reader::new(esds.get(4..)?);
+    let (tag, _) = read_descriptor(&mut reader)?;
+    if tag != 0x03 {
+        return None;
+    }
+    reader.skip(2)?;
+    let flags = reader.u8()?;
+    if flags & 0x80 != 0 {
+        reader.skip(2)?;
+    }
+    if flags & 0x40 != 0 {
+        let len = reader.u8()? as usize;
+        reader.skip(len)?;
+    }
+    if flags & 0x20 != 0 {
+        reader.skip(2)?;
+    }
+
+    let (tag, _) = read_descriptor(&mut reader)?;
+    if tag != 0x04 {
+        return None;
+    }
+    let object_type = reader.u8()?;
+    reader.skip(12)?;
+
+    match read_descriptor(&mut reader) {
+        Some((0x05, len)) if len > 0 => {
+            let info = reader.bytes(len.min(2))?;
+            let mut audio_object_type = info[0] >> 3;
+            if audio_object_type == 31 && info.len() > 1 {
+                audio_object_type = 32 + (((info[0] & 0x07) << 3) | (info[1] >> 5));
+            }
+            Some(format!("mp4a.{:x}.{}", object_type, audio_object_type))
+        }
+        _ => Some(format!("mp4a.{:x}", object_type)),
+    }
+}
+
+// MPEG-4描述符头：标签和变长长度
+fn read_descriptor(reader: &mut Reader) -> Option<(u8, usize)> {
+    let tag = reader.u8()?;
+    let mut len = 0usize;
+    for _ in 0..4 {
+        let byte = reader.u8()?;
+        len = (len << 7) | (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Some((tag, len))
+}
+
+// 根据stts/ctts/stsc/stsz/stco/stss展开每个样本的位置和时间
+fn parse_samples(stbl: &[([u8; 4], &[u8])]) -> Option<Vec<Sample>> {
+    let sizes = match find(stbl, b"stsz") {
+        Some(stsz) => {
+            let mut reader = Reader::new(stsz);
+            reader.skip(4)?;
+            let sample_size = reader.u32()?;
+            let count = reader.u32()? as usize;
+            if sample_size != 0 {
+                vec![sample_size; count]
+            } else {
+                (0..count).map(|_| reader.u32()).collect::<Option<Vec<_>>>()?
+            }
+        }
+        None => {
+            let stz2 = find(stbl, b"stz2")?;
+            let mut reader = Reader::new(stz2);
+            reader.skip(7)?;
+            let field_size = reader.u8()?;
+            let count = reader.u32()? as usize;
+            let data = reader.rest();
+            (0..count)
+                .map(|i| match field_size {
+                    4 => data.get(i / 2).map(|b| if i % 2 == 0 { (b >> 4) as u32 } else { (b & 0x0f) as u32 }),
+                    8 => data.get(i).map(|b| *b as u32),
+                    16 => data.get(i * 2..i * 2 + 2).map(|b| u16::from_be_bytes(b.try_into().unwrap()) as u32),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?
+        }
+    };
+    let count = sizes.len();
+
+    // 解码时间
+    let mut durations = Vec::with_capacity(count);
+    let mut reader = Reader::new(find(stbl, b"stts")?);
+    reader.skip(4)?;
+    // 游程按stsz的样本数截断，损坏的表不会展开出超出样本数的条目
+    for _ in 0..reader.u32()? {
+        let run = reader.u32()?;
+        let delta = reader.u32()?;
+        durations.extend(std::iter::repeat_n(delta, (run as usize).min(count - durations.len())));
+    }
+    durations.resize(count, durations.last().copied().unwrap_or(0));
+
+    // 显示时间偏移，版本0也按有符号处理
+    let mut cts_offsets = Vec::with_capacity(count);
+    if let Some(ctts) = find(stbl, b"ctts") {
+        let mut reader = Reader::new(ctts);
+        reader.skip(4)?;
+        for _ in 0..reader.u32()? {
+            let run = reader.u32()?;
+            let offset = reader.u32()? as i32;
+            cts_offsets.extend(std::iter::repeat_n(offset, (run as usize).min(count - cts_offsets.len())));
+        }
+    }
+    cts_offsets.resize(count, 0);
+
+    // 块偏移
+    let chunk_offsets: Vec<u64> = match find(stbl, b"stco") {
+        Some(stco) => {
+            let mut reader = Reader::new(stco);
+            reader.skip(4)?;
+            let n = reader.u32()?;
+            (0..n).map(|_| reader.u32().map(|o| o as u64)).collect::<Option<Vec<_>>>()?
+        }
+        None => {
+            let mut reader = Reader::new(find(stbl, b"co64")?);
+            reader.skip(4)?;
+            let n = reader.u32()?;
+            (0..n).map(|_| reader.u64()).collect::<Option<Vec<_>>>()?
+        }
+    };
+
+    // 每个块中的样本数
+    let mut reader = Reader::new(find(stbl, b"stsc")?);
+    reader.skip(4)?;
+    let stsc: Vec<(u32, u32)> = (0..reader.u32()?)
+        .map(|_| {
+            let first_chunk = reader.u32()?;
+            let samples_per_chunk = reader.u32()?;
+            reader.skip(4)?;
+            Some((first_chunk, samples_per_chunk))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut offsets = Vec::with_capacity(count);
+    for (chunk_index, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk_number = chunk_index as u32 + 1;
+        let samples_per_chunk = stsc
+            .iter()
+            .rev()
+            .find(|(first_chunk, _)| *first_chunk <= chunk_number)
+            .map(|(_, n)| *n)
+            .unwrap_or(0);
+        let mut offset = *chunk_offset;
+        for _ in 0..samples_per_chunk {
+            let Some(size) = sizes.get(offsets.len()) else {
+                break;
+            };
+            offsets.push(offset);
+            offset += *size as u64;
+        }
+    }
+    if offsets.len() < count {
+        return None;
+    }
+
+    // 关键帧，没有stss时所有样本都是关键帧
+    let sync: Option<Vec<bool>> = find(stbl, b"stss").map(|stss| {
+        let mut flags = vec![false; count];
+        let mut reader = Reader::new(stss);
+        if reader.skip(4).is_some() {
+            let n = reader.u32().unwrap_or(0);
+            for _ in 0..n {
+                match reader.u32() {
+                    Some(number) if number >= 1 && (number as usize) <= count => flags[number as usize - 1] = true,
+                    _ => break,
+                }
+            }
+        }
+        flags
+    });
+
+    let mut dts = 0u64;
+    let mut samples = Vec::with_capacity(count);
+    for i in 0..count {
+        samples.push(Sample {
+            offset: offsets[i],
+            size: sizes[i],
+            dts,
+            duration: durations[i],
+            cts_offset: cts_offsets[i],
+            sync: sync.as_ref().map(|flags| flags[i]).unwrap_or(true),
+        });
+        dts += durations[i] as u64;
+    }
+    Some(samples)
+}
+
+// 读取sidx盒子（含盒子头），返回timescale和每个引用的时长
+pub fn read_segment_index(data: &[u8]) -> Option<(u32, Vec<u64>)> {
+    let (kind, payload) = children(data).into_iter().next()?;
+    if &kind != b"sidx" {
+        return None;
+    }
+    let mut reader = Reader::new(payload);
+    let version = reader.u8()?;
+    reader.skip(7)?;
+    let timescale = reader.u32()?;
+    reader.skip(if version == 1 { 16 } else { 8 })?;
+    reader.skip(2)?;
+    let count = reader.u16()?;
+    let durations = (0..count)
+        .map(|_| {
+            reader.skip(4)?;
+            let duration = reader.u32()? as u64;
+            reader.skip(4)?;
+            Some(duration)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((timescale, durations))
+}
+
+// 初始化分片中trex的默认样本时长，媒体分片的tfhd/trun没有写时长时使用
+pub fn read_default_duration(init: &[u8]) -> Option<u32> {
+    let moov = children(init).into_iter().find(|(kind, _)| kind == b"moov")?.1;
+    let trex = find(&children(find(&children(moov), b"mvex")?), b"trex")?;
+    let mut reader = Reader::new(trex);
+    reader.skip(12)?;
+    reader.u32()
+}
+
+// 读取媒体分片中所有moof的最早呈现时间和总时长（分块CMAF的一个分片包含多个moof）
+pub fn read_fragment_timing(data: &[u8], default_duration: Option<u32>) -> Option<(u64, u64)> {
+    let mut earliest: Option<i64> = None;
+    let mut total = 0u64;
+    for (kind, moof) in children(data) {
+        if &kind != b"moof" {
+            continue;
+        }
+        let traf = children(find(&children(moof), b"traf")?);
+
+        let mut reader = Reader::new(find(&traf, b"tfhd")?);
+        let flags = reader.u32()? & 0x00ff_ffff;
+        reader.skip(4)?;
+        if flags & 0x01 != 0 {
+            reader.skip(8)?;
+        }
+        if flags & 0x02 != 0 {
+            reader.skip(4)?;
+        }
+        let fragment_default = if flags & 0x08 != 0 { Some(reader.u32()?) } else { default_duration };
+
+        let mut reader = Reader::new(find(&traf, b"tfdt")?);
+        let version = reader.u8()?;
+        reader.skip(3)?;
+        let mut dts = if version == 1 { reader.u64()? } else { reader.u32()? as u64 } as i64;
+
+        for (kind, trun) in &traf {
+            if kind != b"trun" {
+                continue;
+            }
+            let mut reader = Reader::new(trun);
+            let flags = reader.u32()? & 0x00ff_ffff;
+            let count = reader.u32()?;
+            if flags & 0x01 != 0 {
+                reader.skip(4)?;
+            }
+            if flags & 0x04 != 0 {
+                reader.skip(4)?;
+            }
+            for _ in 0..count {
+                let duration = if flags & 0x100 != 0 { reader.u32()? } else { fragment_default? };
+                if flags & 0x200 != 0 {
+                    reader.skip(4)?;
+                }
+                if flags & 0x400 != 0 {
+                    reader.skip(4)?;
+                }
+                let cts_offset = if flags & 0x800 != 0 { reader.u32()? as i32 } else { 0 };
+                let pts = dts + cts_offset as i64;
+                earliest = Some(earliest.map_or(pts, |e| e.min(pts)));
+                dts += duration as i64;
+                total += duration as u64;
+            }
+        }
+    }
+    Some((earliest?.max(0) as u64, total))
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(payload.len() + 8);
+    data.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    data.extend_from_slice(kind);
+    data.extend_from_slice(payload);
+    data
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(payload.len() + 4);
+    data.extend_from_slice(&((version as u32) << 24 | flags).to_be_bytes());
+    data.extend_from_slice(payload);
+    mp4_box(kind, &data)
+}
+
+fn file_type(major: &[u8; 4], compatible: &[&[u8; 4]], kind: &[u8; 4]) -> Vec<u8> {
+    let mut payload = major.to_vec();
+    payload.extend_from_slice(&0u32.to_be_bytes());
+    for brand in compatible {
+        payload.extend_from_slice(*brand);
+    }
+    mp4_box(kind, &payload)
+}
+
+// 把tkhd/mdhd中的时长清零，分片文件的时长由各个分片决定
+fn clear_duration(payload: &[u8], v0_offset: usize, v1_offset: usize) -> Vec<u8> {
+    let mut payload = payload.to_vec();
+    let (offset, len) = if payload.first() == Some(&1) { (v1_offset, 8) } else { (v0_offset, 4) };
+    if let Some(field) = payload.get_mut(offset..offset + len) {
+        field.fill(0);
+    }
+    payload
+}
+
+// 加密轨道的样本描述：格式改为encv/enca，原格式写入sinf的frma，tenc记录KID和IV
+fn protected_stsd(track: &Track, protection: &Protection) -> Vec<u8> {
+    let mut tenc = vec![0u8, 0, 1, 0];
+    let version = match protection.scheme {
+        Scheme::Cenc => {
+            tenc[3] = 8;
+            tenc.extend_from_slice(&protection.kid);
+            0
+        }
+        Scheme::Cbcs => {
+            tenc[1] = protection.pattern.0 << 4 | protection.pattern.1;
+            tenc.extend_from_slice(&protection.kid);
+            tenc.push(16);
+            tenc.extend_from_slice(&protection.iv);
+            1
+        }
+    };
+    let mut schm = protection.scheme.fourcc().to_vec();
+    schm.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+
+    let mut stsd = track.stsd[..8.min(track.stsd.len())].to_vec();
+    for (format, entry) in children(track.stsd.get(8..).unwrap_or_default()) {
+        let mut sinf = mp4_box(b"frma", &format);
+        sinf.extend(full_box(b"schm", 0, 0, &schm));
+        sinf.extend(mp4_box(b"schi", &full_box(b"tenc", version, 0, &tenc)));
+
+        let mut payload = entry.to_vec();
+        payload.extend(mp4_box(b"sinf", &sinf));
+        let kind = match track.kind {
+            TrackKind::Video => b"encv",
+            TrackKind::Audio => b"enca",
+        };
+        stsd.extend(mp4_box(kind, &payload));
+    }
+    stsd
+}
+
+// ClearKey的pssh（版本1，只列出KID，没有系统数据）
+pub fn clearkey_pssh(kid: &[u8; 16]) -> Vec<u8> {
+    let mut pssh = CLEARKEY_SYSTEM_ID.to_vec();
+    pssh.extend_from_slice(&1u32.to_be_bytes());
+    pssh.extend_from_slice(kid);
+    pssh.extend_from_slice(&0u32.to_be_bytes());
+    full_box(b"pssh", 1, 0, &pssh)
+}
+
+// 初始化分片：ftyp + 只含这一个轨道的moov（样本表为空，带mvex）；加密时带sinf和pssh
+pub fn init_segment(track: &Track, protection: Option<&Protection>) -> Vec<u8> {
+    // mvhd版本0：创建/修改时间、timescale、时长、速率、音量、保留、矩阵、预定义、下一个轨道ID
+    let mut mvhd = Vec::new();
+    mvhd.extend_from_slice(&[0u8; 8]);
+    mvhd.extend_from_slice(&1000u32.to_be_bytes());
+    mvhd.extend_from_slice(&0u32.to_be_bytes());
+    mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    mvhd.extend_from_slice(&0x0100u16.to_be_bytes());
+    mvhd.extend_from_slice(&[0u8; 10]);
+    for value in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        mvhd.extend_from_slice(&value.to_be_bytes());
+    }
+    mvhd.extend_from_slice(&[0u8; 24]);
+    mvhd.extend_from_slice(&(track.id + 1).to_be_bytes());
+
+    let empty_table = 0u32.to_be_bytes();
+    let mut stbl = match protection {
+        Some(protection) => mp4_box(b"stsd", &protected_stsd(track, protection)),
+        None => mp4_box(b"stsd", &track.stsd),
+    };
+    stbl.extend(full_box(b"stts", 0, 0, &empty_table));
+    stbl.extend(full_box(b"stsc", 0, 0, &empty_table));
+    stbl.extend(full_box(b"stsz", 0, 0, &[0u8; 8]));
+    stbl.extend(full_box(b"stco", 0, 0, &empty_table));
+
+    let mut minf = Vec::new();
+    for (kind, payload) in &track.minf_children {
+        minf.extend(mp4_box(kind, payload));
+    }
+    minf.extend(mp4_box(b"stbl", &stbl));
+
+    let mut mdia = mp4_box(b"mdhd", &clear_duration(&track.mdhd, 16, 24));
+    mdia.extend(mp4_box(b"hdlr", &track.hdlr));
+    mdia.extend(mp4_box(b"minf", &minf));
+
+    let mut trak = mp4_box(b"tkhd", &clear_duration(&track.tkhd, 20, 28));
+    trak.extend(mp4_box(b"mdia", &mdia));
+
+    let mut trex = Vec::new();
+    trex.extend_from_slice(&track.id.to_be_bytes());
+    trex.extend_from_slice(&1u32.to_be_bytes());
+    trex.extend_from_slice(&[0u8; 12]);
+
+    let mut moov = full_box(b"mvhd", 0, 0, &mvhd);
+    moov.extend(mp4_box(b"trak", &trak));
+    moov.extend(mp4_box(b"mvex", &full_box(b"trex", 0, 0, &trex)));
+    if let Some(protection) = protection {
+        moov.extend(clearkey_pssh(&protection.kid));
+    }
+
+    let mut data = file_type(b"iso6", &[b"iso6", b"cmfc", b"dash", b"mp41"], b"ftyp");
+    data.extend(mp4_box(b"moov", &moov));
+    data
+}
+
+// 一组样本中最早的呈现时间（dts加上显示时间偏移）
+pub fn earliest_presentation(samples: &[Sample]) -> u64 {
+    samples
+        .iter()
+        .map(|s| (s.dts as i64 + s.cts_offset as i64).max(0) as u64)
+        .min()
+        .unwrap_or(0)
+}
+
+// 关键帧不依赖其他帧；非关键帧依赖其他帧且标记为非同步样本
+fn sample_flags(sample: &Sample) -> u32 {
+    if sample.sync {
+        0x0200_0000
+    } else {
+        0x0101_0000
+    }
+}
+
+// 样本的加密信息：加密参数和每个样本的子样本(音频为空，整个样本加密)
+type SampleEncryption<'a> = (&'a Protection, &'a [Vec<(u16, u32)>]);
+
+// senc记录每个样本的IV和子样本，saiz/saio指向senc中的数据
+fn sample_encryption_boxes(protection: &Protection, subsamples: &[Vec<(u16, u32)>], samples: &[Sample], offset: usize) -> Vec<u8> {
+    let use_subsamples = subsamples.iter().any(|entries| !entries.is_empty());
+    let mut senc = (samples.len() as u32).to_be_bytes().to_vec();
+    let mut sizes = Vec::new();
+    for (sample, entries) in samples.iter().zip(subsamples) {
+        let start = senc.len();
+        if let Some(iv) = protection.sample_iv(sample.dts) {
+            senc.extend_from_slice(&iv);
+        }
+        if use_subsamples {
+            senc.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+            for (clear, protected) in entries {
+                senc.extend_from_slice(&clear.to_be_bytes());
+                senc.extend_from_slice(&protected.to_be_bytes());
+            }
+        }
+        sizes.push((senc.len() - start) as u8);
+    }
+    // cbcs整样本加密时没有需要记录的信息
+    if sizes.iter().all(|size| *size == 0) {
+        return Vec::new();
+    }
+
+    let mut saiz = Vec::new();
+    if sizes.iter().all(|size| *size == sizes[0]) {
+        saiz.push(sizes[0]);
+        saiz.extend_from_slice(&(sizes.len() as u32).to_be_bytes());
+    } else {
+        saiz.push(0);
+        saiz.extend_from_slice(&(sizes.len() as u32).to_be_bytes());
+        saiz.extend_from_slice(&sizes);
+    }
+    let mut boxes = full_box(b"saiz", 0, 0, &saiz);
+
+    // saio的偏移相对moof开始(default-base-is-moof)，指向senc中第一个样本的数据：
+    // offset为saiz之前的长度，saio固定20字节，senc头部16字节
+    let senc_data = (offset + boxes.len() + 20 + 16) as u32;
+    let mut saio = 1u32.to_be_bytes().to_vec();
+    saio.extend_from_slice(&senc_data.to_be_bytes());
+    boxes.extend(full_box(b"saio", 0, 0, &saio));
+    boxes.extend(full_box(b"senc", 0, if use_subsamples { 0x02 } else { 0 }, &senc));
+    boxes
+}
+
+fn movie_fragment(track: &Track, samples: &[Sample], sequence: u32, data_offset: u32, encryption: Option<SampleEncryption>) -> Vec<u8> {
+    let mut tfdt = Vec::new();
+    tfdt.extend_from_slice(&samples[0].dts.to_be_bytes());
+
+    let mut trun = Vec::new();
+    trun.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    trun.extend_from_slice(&data_offset.to_be_bytes());
+    for sample in samples {
+        trun.extend_from_slice(&sample.duration.to_be_bytes());
+        trun.extend_from_slice(&sample.size.to_be_bytes());
+        trun.extend_from_slice(&sample_flags(sample).to_be_bytes());
+        trun.extend_from_slice(&sample.cts_offset.to_be_bytes());
+    }
+
+    // tfhd: default-base-is-moof；trun: data-offset/duration/size/flags/cts
+    let mut traf = full_box(b"tfhd", 0, 0x02_0000, &track.id.to_be_bytes());
+    traf.extend(full_box(b"tfdt", 1, 0, &tfdt));
+    traf.extend(full_box(b"trun", 1, 0x0f01, &trun));
+
+    let mut moof = full_box(b"mfhd", 0, 0, &sequence.to_be_bytes());
+    if let Some((protection, subsamples)) = encryption {
+        // moof头 + mfhd + traf头
+        let offset = 8 + moof.len() + 8 + traf.len();
+        traf.extend(sample_encryption_boxes(protection, subsamples, samples, offset));
+    }
+    moof.extend(mp4_box(b"traf", &traf));
+    mp4_box(b"moof", &moof)
+}
+
+// 一个分片（moof + mdat头）的数据，样本数据在写出时从源文件复制（加密时逐个样本加密后写出）
+struct Fragment {
+    moof: Vec<u8>,
+    mdat_header: Vec<u8>,
+    data_size: u64,
+    earliest: u64,
+    duration: u64,
+    subsamples: Vec<Vec<(u16, u32)>>,
+}
+
+impl Fragment {
+    fn new(source: &mut File, track: &Track, samples: &[Sample], sequence: u32, protection: Option<&Protection>) -> io::Result<Self> {
+        let data_size: u64 = samples.iter().map(|s| s.size as u64).sum();
+        let mut mdat_header = Vec::new();
+        if data_size + 8 > u32::MAX as u64 {
+            mdat_header.extend_from_slice(&1u32.to_be_bytes());
+            mdat_header.extend_from_slice(b"mdat");
+            mdat_header.extend_from_slice(&(data_size + 16).to_be_bytes());
+        } else {
+            mdat_header.extend_from_slice(&(data_size as u32 + 8).to_be_bytes());
+            mdat_header.extend_from_slice(b"mdat");
+        }
+
+        // 视频按OBU划分子样本，需要读取样本数据；音频整个样本加密
+        let mut subsamples = vec![Vec::new(); samples.len()];
+        if protection.is_some() && track.kind == TrackKind::Video {
+            for (sample, entries) in samples.iter().zip(subsamples.iter_mut()) {
+                *entries = av1_subsamples(&read_sample(source, sample)?);
+            }
+        }
+
+        let encryption = protection.map(|protection| (protection, subsamples.as_slice()));
+        let moof_len = movie_fragment(track, samples, sequence, 0, encryption).len() as u32;
+        let moof = movie_fragment(track, samples, sequence, moof_len + mdat_header.len() as u32, encryption);
+        Ok(Fragment {
+            moof,
+            mdat_header,
+            data_size,
+            earliest: earliest_presentation(samples),
+            duration: samples.iter().map(|s| s.duration as u64).sum(),
+            subsamples,
+        })
+    }
+
+    fn size(&self) -> u64 {
+        self.moof.len() as u64 + self.mdat_header.len() as u64 + self.data_size
+    }
+
+    fn write(&self, source: &mut File, output: &mut impl Write, samples: &[Sample], protection: Option<&Protection>) -> io::Result<()> {
+        output.write_all(&self.moof)?;
+        output.write_all(&self.mdat_header)?;
+        let Some(protection) = protection else {
+            return copy_samples(source, output, samples);
+        };
+        for (sample, subsamples) in samples.iter().zip(&self.subsamples) {
+            let mut data = read_sample(source, sample)?;
+            protection.encrypt(&mut data, sample.dts, subsamples);
+            output.write_all(&data)?;
+        }
+        Ok(())
+    }
+}
+
+// sidx：每个分片一个引用，所有分片都以关键帧(SAP type 1)开始。
+// 引用大小只有31位、时长32位、引用数16位，超出时报错而不是截断
+fn segment_index(track: &Track, fragments: &[Fragment]) -> io::Result<Vec<u8>> {
+    let count = u16::try_from(fragments.len()).map_err(|_| io::Error::other(format!("分片数 {} 超出sidx上限", fragments.len())))?;
+    let mut sidx = Vec::new();
+    sidx.extend_from_slice(&track.id.to_be_bytes());
+    sidx.extend_from_slice(&track.timescale.to_be_bytes());
+    sidx.extend_from_slice(&fragments.first().map(|f| f.earliest).unwrap_or(0).to_be_bytes());
+    sidx.extend_from_slice(&0u64.to_be_bytes());
+    sidx.extend_from_slice(&0u16.to_be_bytes());
+    sidx.extend_from_slice(&count.to_be_bytes());
+    for fragment in fragments {
+        let size = fragment.size();
+        if size >= 1 << 31 {
+            return Err(io::Error::other(format!("分片大小 {} 字节超出sidx的31位上限", size)));
+        }
+        let duration = u32::try_from(fragment.duration).map_err(|_| io::Error::other(format!("分片时长 {} 超出sidx上限", fragment.duration)))?;
+        sidx.extend_from_slice(&(size as u32).to_be_bytes());
+        sidx.extend_from_slice(&duration.to_be_bytes());
+        sidx.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+    }
+    Ok(full_box(b"sidx", 1, 0, &sidx))
+}
+
+fn read_sample(source: &mut File, sample: &Sample) -> io::Result<Vec<u8>> {
+    let mut data = vec![0u8; sample.size as usize];
+    source.seek(SeekFrom::Start(sample.offset))?;
+    source.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// 按样本在源文件中的位置复制数据，合并连续的样本以减少读取次数
+fn copy_samples(source: &mut File, output: &mut impl Write, samples: &[Sample]) -> io::Result<()> {
+    let mut index = 0;
+    while index < samples.len() {
+        let start = samples[index].offset;
+        let mut end = start + samples[index].size as u64;
+        index += 1;
+        while index < samples.len() && samples[index].offset == end {
+            end += samples[index].size as u64;
+            index += 1;
+        }
+        source.seek(SeekFrom::Start(start))?;
+        let copied = io::copy(&mut (&mut *source).take(end - start), output)?;
+        if copied != end - start {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "样本数据不完整"));
+        }
+    }
+    Ok(())
+}
+
+// 写出一个媒体分片：styp + sidx + moof + mdat，返回分片大小
+pub fn write_media_segment(
+    source: &mut File,
+    path: &Path,
+    track: &Track,
+    samples: &[Sample],
+    sequence: u32,
+    protection: Option<&Protection>,
+) -> io::Result<u64> {
+    let fragment = Fragment::new(source, track, samples, sequence, protection)?;
+    let styp = file_type(b"msdh", &[b"msdh", b"msix"], b"styp");
+    let sidx = segment_index(track, std::slice::from_ref(&fragment))?;
+
+    let mut output = BufWriter::new(File::create(path)?);
+    output.write_all(&styp)?;
+    output.write_all(&sidx)?;
+    fragment.write(source, &mut output, samples, protection)?;
+    output.flush()?;
+
+    Ok(styp.len() as u64 + sidx.len() as u64 + fragment.size())
+}
+
+// 单文件中各部分的字节范围（闭区间），用于MPD的SegmentBase
+#[derive(Debug, Clone, Copy)]
+pub struct SingleFileIndex {
+    pub init_end: u64,
+    pub index_start: u64,
+    pub index_end: u64,
+}
+
+// 写出单文件fMP4：初始化部分 + 覆盖全部分片的sidx + 所有moof/mdat，
+// 返回字节范围和每个分片的大小
+pub fn write_single_file(
+    source: &mut File,
+    path: &Path,
+    track: &Track,
+    segments: &[&[Sample]],
+    protection: Option<&Protection>,
+) -> io::Result<(SingleFileIndex, Vec<u64>)> {
+    let init = init_segment(track, protection);
+    let fragments = segments
+        .iter()
+        .enumerate()
+        .map(|(i, samples)| Fragment::new(source, track, samples, i as u32 + 1, protection))
+        .collect::<io::Result<Vec<Fragment>>>()?;
+    let sidx = segment_index(track, &fragments)?;
+
+    let mut output = BufWriter::new(File::create(path)?);
+    output.write_all(&init)?;
+    output.write_all(&sidx)?;
+    for (fragment, samples) in fragments.iter().zip(segments) {
+        fragment.write(source, &mut output, samples, protection)?;
+    }
+    output.flush()?;
+
+    let index = SingleFileIndex {
+        init_end: init.len() as u64 - 1,
+        index_start: init.len() as u64,
+        index_end: (init.len() + sidx.len()) as u64 - 1,
+    };
+    Ok((index, fragments.iter().map(|f| f.size()).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMESCALE: u32 = 12800;
+    const SAMPLES: [&[u8]; 3] = [b"key frame data", b"delta 1", b"delta two"];
+
+    // 构造只有一个AV1视频轨道的普通MP4：ftyp + mdat + moov
+    fn sample_table(stts_run: u32) -> Vec<u8> {
+        let mut entry = vec![0u8; 78];
+        entry[7] = 1;
+        entry[24..26].copy_from_slice(&640u16.to_be_bytes());
+        entry[26..28].copy_from_slice(&360u16.to_be_bytes());
+        entry.extend(mp4_box(b"av1C", &[0x81, 0x08, 0x00, 0x00]));
+        let mut stsd = 1u32.to_be_bytes().to_vec();
+        stsd.extend(mp4_box(b"av01", &entry));
+
+        let mut stts = 1u32.to_be_bytes().to_vec();
+        stts.extend_from_slice(&stts_run.to_be_bytes());
+        stts.extend_from_slice(&512u32.to_be_bytes());
+        let mut stsz = 0u32.to_be_bytes().to_vec();
+        stsz.extend_from_slice(&(SAMPLES.len() as u32).to_be_bytes());
+        for sample in SAMPLES {
+            stsz.extend_from_slice(&(sample.len() as u32).to_be_bytes());
+        }
+        let mut stsc = 1u32.to_be_bytes().to_vec();
+        for value in [1u32, SAMPLES.len() as u32, 1] {
+            stsc.extend_from_slice(&value.to_be_bytes());
+        }
+        // ftyp(16) + mdat头(8)之后就是样本数据
+        let mut stco = 1u32.to_be_bytes().to_vec();
+        stco.extend_from_slice(&24u32.to_be_bytes());
+        let mut stss = 1u32.to_be_bytes().to_vec();
+        stss.extend_from_slice(&1u32.to_be_bytes());
+
+        let mut stbl = full_box(b"stsd", 0, 0, &stsd);
+        stbl.extend(full_box(b"stts", 0, 0, &stts));
+        stbl.extend(full_box(b"stsz", 0, 0, &stsz));
+        stbl.extend(full_box(b"stsc", 0, 0, &stsc));
+        stbl.extend(full_box(b"stco", 0, 0, &stco));
+        stbl.extend(full_box(b"stss", 0, 0, &stss));
+        stbl
+    }
+
+    fn source_file(stts_run: u32) -> Vec<u8> {
+        let mut tkhd = vec![0u8; 80];
+        tkhd[8..12].copy_from_slice(&1u32.to_be_bytes());
+        let mut mdhd = vec![0u8; 20];
+        mdhd[8..12].copy_from_slice(&TIMESCALE.to_be_bytes());
+        mdhd[16..18].copy_from_slice(&0x15c7u16.to_be_bytes());
+        let mut hdlr = vec![0u8; 4];
+        hdlr.extend_from_slice(b"vide");
+        hdlr.extend_from_slice(&[0u8; 13]);
+
+        let mut minf = full_box(b"vmhd", 0, 1, &[0u8; 8]);
+        minf.extend(mp4_box(b"stbl", &sample_table(stts_run)));
+        let mut mdia = full_box(b"mdhd", 0, 0, &mdhd);
+        mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+        mdia.extend(mp4_box(b"minf", &minf));
+        let mut trak = full_box(b"tkhd", 0, 3, &tkhd);
+        trak.extend(mp4_box(b"mdia", &mdia));
+
+        let mut data = file_type(b"isom", &[], b"ftyp");
+        assert_eq!(data.len(), 16);
+        data.extend(mp4_box(b"mdat", &SAMPLES.concat()));
+        data.extend(mp4_box(b"moov", &mp4_box(b"trak", &trak)));
+        data
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("dash_mp4_{}_{}", std::process::id(), name))
+    }
+
+    fn read_source(name: &str, stts_run: u32) -> Track {
+        let path = temp_path(name);
+        std::fs::write(&path, source_file(stts_run)).unwrap();
+        let mut tracks = read_tracks(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(tracks.len(), 1);
+        tracks.remove(0)
+    }
+
+    // 递归检查容器box的子box恰好填满容器，并按规范检查固定长度box的大小（含box头）
+    fn check_box_sizes(data: &[u8], seen: &mut Vec<[u8; 4]>) {
+        let boxes = children(data);
+        let total: usize = boxes.iter().map(|(_, payload)| payload.len() + 8).sum();
+        assert_eq!(total, data.len());
+        for (kind, payload) in boxes {
+            seen.push(kind);
+            let expected = match &kind {
+                b"mvhd" => Some(108),
+                b"tkhd" => Some(92),
+                b"mdhd" => Some(32),
+                b"vmhd" => Some(20),
+                b"stts" | b"stsc" | b"stco" => Some(16),
+                b"stsz" => Some(20),
+                b"trex" => Some(32),
+                _ => None,
+            };
+            if let Some(expected) = expected {
+                assert_eq!(payload.len() + 8, expected, "{}", String::from_utf8_lossy(&kind));
+            }
+            if matches!(&kind, b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" | b"mvex") {
+                check_box_sizes(payload, seen);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_sample_table() {
+        let track = read_source("read.mp4", SAMPLES.len() as u32);
+        assert_eq!(track.kind, TrackKind::Video);
+        assert_eq!(track.codec, "av01.0.08M.08");
+        assert_eq!((track.width, track.height), (640, 360));
+        assert_eq!(track.timescale, TIMESCALE);
+        assert_eq!(track.language.as_deref(), Some("eng"));
+
+        let offsets: Vec<u64> = track.samples.iter().map(|s| s.offset).collect();
+        assert_eq!(offsets, [24, 38, 45]);
+        let dts: Vec<u64> = track.samples.iter().map(|s| s.dts).collect();
+        assert_eq!(dts, [0, 512, 1024]);
+        let sync: Vec<bool> = track.samples.iter().map(|s| s.sync).collect();
+        assert_eq!(sync, [true, false, false]);
+    }
+
+    #[test]
+    fn init_segment_box_sizes_match_spec() {
+        let track = read_source("init.mp4", SAMPLES.len() as u32);
+        let init = init_segment(&track, None);
+
+        let mut seen = Vec::new();
+        check_box_sizes(&init, &mut seen);
+        for kind in [b"ftyp", b"moov", b"mvhd", b"trak", b"tkhd", b"mdhd", b"hdlr", b"stsd", b"mvex", b"trex"] {
+            assert!(seen.contains(kind), "缺少 {}", String::from_utf8_lossy(kind));
+        }
+
+        let moov = children(&init).into_iter().find(|(kind, _)| kind == b"moov").unwrap().1;
+        let mvhd = find(&children(moov), b"mvhd").unwrap();
+        let mut reader = Reader::new(mvhd);
+        assert_eq!(reader.u32(), Some(0));
+        reader.skip(8).unwrap();
+        assert_eq!(reader.u32(), Some(1000));
+        assert_eq!(read_default_duration(&init), Some(0));
+    }
+
+    #[test]
+    fn media_segment_round_trip() {
+        let track = read_source("media.mp4", SAMPLES.len() as u32);
+        let source_path = temp_path("media_source.mp4");
+        std::fs::write(&source_path, source_file(SAMPLES.len() as u32)).unwrap();
+        let segment_path = temp_path("media_segment.m4s");
+
+        let mut source = File::open(&source_path).unwrap();
+        let size = write_media_segment(&mut source, &segment_path, &track, &track.samples, 1, None).unwrap();
+        let data = std::fs::read(&segment_path).unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+        std::fs::remove_file(&segment_path).unwrap();
+        assert_eq!(size, data.len() as u64);
+
+        let boxes = children(&data);
+        let kinds: Vec<&[u8; 4]> = boxes.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"styp", b"sidx", b"moof", b"mdat"]);
+        assert_eq!(boxes[3].1, SAMPLES.concat().as_slice());
+
+        let sidx_start = boxes[0].1.len() + 8;
+        assert_eq!(read_segment_index(&data[sidx_start..]), Some((TIMESCALE, vec![1536])));
+        assert_eq!(read_fragment_timing(&data, None), Some((0, 1536)));
+    }
+
+    #[test]
+    fn sample_runs_are_bounded_by_sample_count() {
+        let track = read_source("runs.mp4", u32::MAX);
+        assert_eq!(track.samples.len(), SAMPLES.len());
+        assert_eq!(track.samples.last().map(|s| s.dts), Some(1024));
+    }
+
+    #[test]
+    fn segment_index_rejects_oversized_fragments() {
+        let track = read_source("sidx.mp4", SAMPLES.len() as u32);
+        let fragment = |data_size: u64| Fragment {
+            moof: Vec::new(),
+            mdat_header: vec![0u8; 8],
+            data_size,
+            earliest: 0,
+            duration: 1536,
+            subsamples: Vec::new(),
+        };
+        assert!(segment_index(&track, &[fragment((1 << 31) - 9)]).is_ok());
+        assert!(segment_index(&track, &[fragment((1 << 31) - 8)]).is_err());
+    }
+}
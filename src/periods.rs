@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use colored::Colorize;
+use regex::{Captures, Regex};
+
+use crate::chapters::Chapter;
+use crate::playlist::escape_xml;
+use crate::segmenter::iso_duration;
+use crate::utils::natural_cmp;
+
+// 多Period分组方式：每个文件夹一组，或同一文件夹中文件名匹配通配符的文件为一组
+#[derive(Debug, Clone)]
+pub enum GroupMode {
+    Folder,
+    Glob(Regex),
+}
+
+impl GroupMode {
+    pub fn parse(spec: &str) -> Option<Self> {
+        if spec == "folder" {
+            return Some(GroupMode::Folder);
+        }
+        // 通配符只支持 * 和 ?，匹配完整文件名
+        let pattern = regex::escape(spec).replace(r"\*", ".*").replace(r"\?", ".");
+        Regex::new(&format!("^{}$", pattern)).ok().map(GroupMode::Glob)
+    }
+
+    fn matches(&self, video: &Path) -> bool {
+        match self {
+            GroupMode::Folder => true,
+            GroupMode::Glob(re) => re.is_match(&video.file_name().unwrap_or_default().to_string_lossy()),
+        }
+    }
+}
+
+// 成员在组输出目录中的子目录
+pub fn period_dir(index: usize) -> String {
+    format!("period_{}", index + 1)
+}
+
+// 组在文件夹中以文件夹名作为虚拟源文件，如 第1章/第1章，输出目录和标题与单个视频一样由它生成
+fn group_source(folder: &Path) -> PathBuf {
+    let name = folder
+        .file_name()
+        .map(|name| name.to_os_string())
+        .or_else(|| folder.canonicalize().ok()?.file_name().map(|name| name.to_os_string()))
+        .unwrap_or_else(|| "main".into());
+    folder.join(name)
+}
+
+// 把视频分组，返回需要处理的条目(单个视频和组的虚拟源文件)以及每个组按文件名自然排序的成员；
+// 只有一个文件的组按普通视频处理
+pub fn group(videos: Vec<PathBuf>, mode: &GroupMode) -> (Vec<PathBuf>, BTreeMap<PathBuf, Vec<PathBuf>>) {
+    let mut groups: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let mut sources = Vec::new();
+    for video in videos {
+        match video.parent() {
+            Some(folder) if mode.matches(&video) => groups.entry(group_source(folder)).or_default().push(video),
+            _ => sources.push(video),
+        }
+    }
+
+    groups.retain(|_, members| {
+        if members.len() < 2 {
+            sources.append(members);
+            return false;
+        }
+        members.sort_by(|a, b| {
+            natural_cmp(&a.file_name().unwrap_or_default().to_string_lossy(), &b.file_name().unwrap_or_default().to_string_lossy())
+        });
+        true
+    });
+    sources.extend(groups.keys().cloned());
+    sources.sort();
+    (sources, groups)
+}
+
+// 解析MPD中的ISO 8601时长，如 PT1H2M3.5S
+pub fn parse_iso_duration(value: &str) -> Option<f64> {
+    let caps = Regex::new(r"^P(?:(\d+)D)?(?:T(?:([\d.]+)H)?(?:([\d.]+)M)?(?:([\d.]+)S)?)?$").unwrap().captures(value.trim())?;
+    let part = |index: usize| caps.get(index).and_then(|m| m.as_str().parse::<f64>().ok()).unwrap_or(0.0);
+    Some(part(1) * 86400.0 + part(2) * 3600.0 + part(3) * 60.0 + part(4))
+}
+
+fn root_duration(content: &str, name: &str) -> Option<f64> {
+    let root = Regex::new(r"(?s)<MPD\b[^>]*>").unwrap().find(content)?;
+    let caps = Regex::new(&format!(r#"\b{}="([^"]*)""#, name)).unwrap().captures(root.as_str())?;
+    parse_iso_duration(&caps[1])
+}
+
+// 单个成员MPD的信息
+struct Member {
+    title: String,
+    duration: f64,
+    max_segment_duration: Option<f64>,
+    // 第一个Period之前的内容，包括MPD根元素
+    head: String,
+    // Period元素内部的内容
+    body: String,
+}
+
+fn read_member(dash_dir: &Path, source: &Path) -> Result<Member, String> {
+    let content = fs::read_to_string(dash_dir.join("main.mpd")).map_err(|e| format!("读取MPD失败: {}", e))?;
+    let opens: Vec<_> = Regex::new(r"<Period\b[^>]*>").unwrap().find_iter(&content).collect();
+    if opens.len() != 1 {
+        return Err(format!("MPD中有{}个Period, 只支持一个", opens.len()));
+    }
+    let end = content.rfind("</Period>").ok_or("MPD中缺少</Period>")?;
+    let duration = root_duration(&content, "mediaPresentationDuration").ok_or("MPD中缺少mediaPresentationDuration")?;
+
+    Ok(Member {
+        title: source.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        duration,
+        max_segment_duration: root_duration(&content, "maxSegmentDuration"),
+        head: content[..opens[0].start()].to_string(),
+        body: content[opens[0].end()..end].trim_end().to_string(),
+    })
+}
+
+// 读取各成员 period_N/main.mpd，合并为组目录下的main.mpd：
+// 每个成员一个Period，id为文件名，通过BaseURL指向成员目录，成功时返回总时长和每个Period对应的章节
+pub fn merge(group_dir: &Path, members: &[PathBuf]) -> Option<(f64, Vec<Chapter>)> {
+    let mut parts = Vec::new();
+    for (index, source) in members.iter().enumerate() {
+        match read_member(&group_dir.join(period_dir(index)), source) {
+            Ok(member) => parts.push(member),
+            Err(e) => {
+                println!("{} 合并Period失败 {}: {}", "❌".red(), source.display(), e);
+                return None;
+            }
+        }
+    }
+
+    let total: f64 = parts.iter().map(|member| member.duration).sum();
+    let max_segment_duration = parts.iter().filter_map(|member| member.max_segment_duration).fold(0.0, f64::max);
+
+    // 以第一个成员的MPD根元素为准，改写总时长和最大分片时长
+    let head = Regex::new(r"(?s)<MPD\b[^>]*>").unwrap().replace(&parts[0].head, |caps: &Captures| {
+        let root = Regex::new(r#"\bmediaPresentationDuration="[^"]*""#)
+            .unwrap()
+            .replace(&caps[0], format!(r#"mediaPresentationDuration="{}""#, iso_duration(total)).as_str())
+            .to_string();
+        Regex::new(r#"\bmaxSegmentDuration="[^"]*""#)
+            .unwrap()
+            .replace(&root, format!(r#"maxSegmentDuration="{}""#, iso_duration(max_segment_duration)).as_str())
+            .to_string()
+    });
+
+    let mut content = head.to_string();
+    let mut ids = HashSet::new();
+    let mut chapters = Vec::new();
+    let mut start = 0.0;
+    for (index, member) in parts.iter().enumerate() {
+        // Period id必须唯一，文件名相同(扩展名不同)时加序号
+        let id = (1..)
+            .map(|n| if n == 1 { member.title.clone() } else { format!("{}-{}", member.title, n) })
+            .find(|id| !ids.contains(id))
+            .unwrap();
+        ids.insert(id.clone());
+        content.push_str(&format!(
+            "<Period id=\"{}\" start=\"{}\" duration=\"{}\">\n\t\t<BaseURL>{}/</BaseURL>{}\n\t</Period>\n\t",
+            escape_xml(&id),
+            iso_duration(start),
+            iso_duration(member.duration),
+            period_dir(index),
+            member.body,
+        ));
+        chapters.push(Chapter { start, title: member.title.clone() });
+        start += member.duration;
+    }
+    content = content.trim_end().to_string();
+    content.push_str("\n</MPD>\n");
+
+    let mpd_path = group_dir.join("main.mpd");
+    let temp_path = group_dir.join("main.mpd.tmp");
+    if let Err(e) = fs::write(&temp_path, &content).and_then(|_| fs::rename(&temp_path, &mpd_path)) {
+        println!("{} 写入MPD文件失败: {}", "❌".red(), e);
+        return None;
+    }
+    println!("{} 多Period MPD已生成: {}个Period, 总时长 {:.1}秒", "✅".green(), parts.len(), total);
+    Some((total, chapters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member_mpd(duration: &str, segment: &str) -> String {
+        format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+                "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" type=\"static\" mediaPresentationDuration=\"{}\" maxSegmentDuration=\"{}\">\n",
+                "\t<Period id=\"0\" start=\"PT0.0S\">\n",
+                "\t\t<AdaptationSet contentType=\"video\"></AdaptationSet>\n",
+                "\t</Period>\n",
+                "</MPD>\n"
+            ),
+            duration, segment
+        )
+    }
+
+    #[test]
+    fn parses_iso_durations() {
+        assert_eq!(parse_iso_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_iso_duration("PT0H0M10.000S"), Some(10.0));
+        assert_eq!(parse_iso_duration("P1DT30S"), Some(86430.0));
+        assert_eq!(parse_iso_duration(" PT45S "), Some(45.0));
+        assert_eq!(parse_iso_duration("P"), Some(0.0));
+        assert_eq!(parse_iso_duration("1H2M"), None);
+        assert_eq!(parse_iso_duration("PT1X"), None);
+    }
+
+    #[test]
+    fn merges_members_into_periods() {
+        let group_dir = std::env::temp_dir().join(format!("dash_periods_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&group_dir);
+        for (index, (duration, segment)) in [("PT1M0.500S", "PT2.000S"), ("PT0H0M30S", "PT4.000S")].iter().enumerate() {
+            let dir = group_dir.join(period_dir(index));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("main.mpd"), member_mpd(duration, segment)).unwrap();
+        }
+        // 文件名相同、扩展名不同时Period id加序号
+        let members = [PathBuf::from("第1章/lecture.mp4"), PathBuf::from("第1章/lecture.mkv")];
+
+        let merged = merge(&group_dir, &members);
+        let content = fs::read_to_string(group_dir.join("main.mpd")).unwrap();
+        fs::remove_dir_all(&group_dir).unwrap();
+
+        let (total, chapters) = merged.unwrap();
+        assert_eq!(total, 90.5);
+        assert_eq!(chapters.iter().map(|c| c.start).collect::<Vec<_>>(), [0.0, 60.5]);
+        assert!(chapters.iter().all(|c| c.title == "lecture"));
+
+        assert_eq!(root_duration(&content, "mediaPresentationDuration"), Some(90.5));
+        assert_eq!(root_duration(&content, "maxSegmentDuration"), Some(4.0));
+        assert!(content.contains("<Period id=\"lecture\" start=\"PT0H0M0.000S\" duration=\"PT0H1M0.500S\">"));
+        assert!(content.contains("<Period id=\"lecture-2\" start=\"PT0H1M0.500S\" duration=\"PT0H0M30.000S\">"));
+        assert_eq!(content.matches("<BaseURL>period_1/</BaseURL>").count(), 1);
+        assert_eq!(content.matches("<BaseURL>period_2/</BaseURL>").count(), 1);
+        assert_eq!(content.matches("<AdaptationSet").count(), 2);
+        assert!(content.trim_end().ends_with("</MPD>"));
+        assert_eq!(content.matches("<MPD").count(), 1);
+    }
+
+    #[test]
+    fn merge_fails_when_a_member_is_missing() {
+        let group_dir = std::env::temp_dir().join(format!("dash_periods_missing_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&group_dir);
+        fs::create_dir_all(group_dir.join(period_dir(0))).unwrap();
+        fs::write(group_dir.join(period_dir(0)).join("main.mpd"), member_mpd("PT10S", "PT2S")).unwrap();
+
+        let merged = merge(&group_dir, &[PathBuf::from("a.mp4"), PathBuf::from("b.mp4")]);
+        let written = group_dir.join("main.mpd").exists();
+        fs::remove_dir_all(&group_dir).unwrap();
+        assert!(merged.is_none());
+        assert!(!written);
+    }
+
+    #[test]
+    fn groups_by_folder_in_natural_order() {
+        let videos = vec![
+            PathBuf::from("课程/第1章/第10讲.mp4"),
+            PathBuf::from("课程/第1章/第2讲.mp4"),
+            PathBuf::from("课程/第2章/单独.mp4"),
+        ];
+        let (sources, groups) = group(videos, &GroupMode::Folder);
+
+        // 只有一个文件的文件夹按普通视频处理
+        assert_eq!(sources, [PathBuf::from("课程/第1章/第1章"), PathBuf::from("课程/第2章/单独.mp4")]);
+        assert_eq!(
+            groups[&PathBuf::from("课程/第1章/第1章")],
+            [PathBuf::from("课程/第1章/第2讲.mp4"), PathBuf::from("课程/第1章/第10讲.mp4")]
+        );
+    }
+
+    #[test]
+    fn groups_by_glob() {
+        let mode = GroupMode::parse("part?.mp4").unwrap();
+        let videos = vec![
+            PathBuf::from("a/part2.mp4"),
+            PathBuf::from("a/part1.mp4"),
+            PathBuf::from("a/intro.mp4"),
+        ];
+        let (sources, groups) = group(videos, &mode);
+        assert_eq!(sources, [PathBuf::from("a/a"), PathBuf::from("a/intro.mp4")]);
+        assert_eq!(groups[&PathBuf::from("a/a")], [PathBuf::from("a/part1.mp4"), PathBuf::from("a/part2.mp4")]);
+    }
+}
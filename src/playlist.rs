@@ -0,0 +1,169 @@
+use std::fs;
+use std::path::Path;
+use colored::Colorize;
+
+use crate::json_generator::{IndexOptions, ServerConfig, ServerIndex, VideoEntry};
+
+// 播放列表格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaylistFormat {
+    M3u,
+    Xspf,
+    Rss,
+}
+
+impl PlaylistFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "m3u" => Some(PlaylistFormat::M3u),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            "rss" => Some(PlaylistFormat::Rss),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Rss => "rss",
+        }
+    }
+}
+
+// 为每个服务器生成播放列表，文件名与服务器JSON相同、扩展名不同
+pub fn generate_playlists(
+    indexes: &[ServerIndex],
+    servers: &[ServerConfig],
+    options: &IndexOptions,
+    formats: &[PlaylistFormat],
+) {
+    if indexes.is_empty() || formats.is_empty() {
+        return;
+    }
+
+    println!("{} 生成播放列表...", "📝".blue());
+
+    for (i, (index, server)) in indexes.iter().zip(servers).enumerate() {
+        let index_path = options.index_path(&options.index_name, i + 1, server);
+        for format in formats {
+            let content = match format {
+                PlaylistFormat::M3u => render_m3u(index),
+                PlaylistFormat::Xspf => render_xspf(index, server),
+                PlaylistFormat::Rss => render_rss(index, server, options),
+            };
+            write_playlist(&index_path.with_extension(format.extension()), &content);
+        }
+    }
+}
+
+// 优先使用HLS地址，兼容更多播放器
+fn play_url(video: &VideoEntry) -> &str {
+    video.hls_url.as_deref().unwrap_or(&video.url)
+}
+
+fn mime_type(video: &VideoEntry) -> &'static str {
+    if video.hls_url.is_some() {
+        "application/vnd.apple.mpegurl"
+    } else {
+        "application/dash+xml"
+    }
+}
+
+// enclosure的length为链接指向的清单文件大小，输出目录不存在时为0
+fn enclosure_length(video: &VideoEntry, options: &IndexOptions) -> u64 {
+    let file_name = play_url(video).rsplit('/').next().unwrap_or_default();
+    fs::metadata(options.dash_dir(video).join(file_name))
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+fn render_m3u(index: &ServerIndex) -> String {
+    let mut content = String::from("#EXTM3U\n");
+    for video in &index.videos {
+        content.push_str(&format!("#EXTINF:{},{}\n", video.duration.round() as i64, video.title));
+        content.push_str(play_url(video));
+        content.push('\n');
+    }
+    content
+}
+
+fn render_xspf(index: &ServerIndex, server: &ServerConfig) -> String {
+    let mut content = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    content.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    content.push_str(&format!("  <title>{}</title>\n", escape_xml(&server.host())));
+    content.push_str(&format!("  <date>{}</date>\n", escape_xml(&index.generated_at)));
+    content.push_str("  <trackList>\n");
+    for video in &index.videos {
+        content.push_str("    <track>\n");
+        content.push_str(&format!("      <location>{}</location>\n", escape_xml(play_url(video))));
+        content.push_str(&format!("      <title>{}</title>\n", escape_xml(&video.title)));
+        if !video.folder.is_empty() {
+            content.push_str(&format!("      <album>{}</album>\n", escape_xml(&video.folder)));
+        }
+        content.push_str(&format!("      <duration>{}</duration>\n", (video.duration * 1000.0).round() as i64));
+        if let Some(ref poster) = video.poster {
+            content.push_str(&format!("      <image>{}</image>\n", escape_xml(poster)));
+        }
+        content.push_str(&format!("      <identifier>{}</identifier>\n", escape_xml(&video.id)));
+        content.push_str("    </track>\n");
+    }
+    content.push_str("  </trackList>\n</playlist>\n");
+    content
+}
+
+fn render_rss(index: &ServerIndex, server: &ServerConfig, options: &IndexOptions) -> String {
+    let build_date = chrono::DateTime::parse_from_rfc3339(&index.generated_at)
+        .map(|date| date.to_rfc2822())
+        .unwrap_or_default();
+
+    let mut content = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    content.push_str("<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n");
+    content.push_str("  <channel>\n");
+    content.push_str(&format!("    <title>{}</title>\n", escape_xml(&server.host())));
+    content.push_str(&format!("    <link>{}</link>\n", escape_xml(&index.server)));
+    content.push_str(&format!("    <description>{} 的视频列表</description>\n", escape_xml(&server.host())));
+    content.push_str(&format!("    <lastBuildDate>{}</lastBuildDate>\n", build_date));
+    for video in &index.videos {
+        let duration = video.duration.round() as u64;
+        content.push_str("    <item>\n");
+        content.push_str(&format!("      <title>{}</title>\n", escape_xml(&video.title)));
+        content.push_str(&format!("      <guid isPermaLink=\"false\">{}</guid>\n", escape_xml(&video.id)));
+        content.push_str(&format!(
+            "      <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            escape_xml(play_url(video)),
+            enclosure_length(video, options),
+            mime_type(video)
+        ));
+        if !video.folder.is_empty() {
+            content.push_str(&format!("      <category>{}</category>\n", escape_xml(&video.folder)));
+        }
+        content.push_str(&format!(
+            "      <itunes:duration>{:02}:{:02}:{:02}</itunes:duration>\n",
+            duration / 3600,
+            duration % 3600 / 60,
+            duration % 60
+        ));
+        if let Some(ref poster) = video.poster {
+            content.push_str(&format!("      <itunes:image href=\"{}\"/>\n", escape_xml(poster)));
+        }
+        content.push_str("    </item>\n");
+    }
+    content.push_str("  </channel>\n</rss>\n");
+    content
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn write_playlist(path: &Path, content: &str) {
+    match fs::write(path, content) {
+        Ok(_) => println!("{} 成功创建播放列表: {}", "✅".green(), path.display()),
+        Err(e) => println!("{} 写入播放列表失败: {}", "❌".red(), e),
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+use std::process::Command;
+use colored::Colorize;
+use serde_json::Value;
+
+use crate::chapters::{normalize, Chapter};
+
+// ffprobe探测到的媒体信息
+#[derive(Debug, Clone, Default)]
+pub struct MediaInfo {
+    pub duration: f64,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+    pub video_duration: Option<f64>,
+    pub audio_duration: Option<f64>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+}
+
+// 使用ffprobe读取媒体信息
+pub fn probe_media(path: &Path) -> Option<MediaInfo> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v", "error",
+            "-print_format", "json",
+            "-show_format",
+            "-show_streams",
+            path.to_str().unwrap(),
+        ])
+        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            println!("{} 执行ffprobe失败: {}", "❌".red(), e);
+            return None;
+        }
+    };
+
+    let json: Value = match serde_json::from_slice(&output.stdout) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("{} 解析ffprobe输出失败: {}", "❌".red(), e);
+            return None;
+        }
+    };
+
+    let mut info = MediaInfo {
+        duration: json["format"]["duration"]
+            .as_str()
+            .and_then(|d| d.parse::<f64>().ok())
+            .unwrap_or(0.0),
+        ..Default::default()
+    };
+
+    if let Some(streams) = json["streams"].as_array() {
+        for stream in streams {
+            // 跳过封面图片之类的附加流
+            if stream["disposition"]["attached_pic"].as_u64() == Some(1) {
+                continue;
+            }
+            let codec = stream["codec_name"].as_str().map(|c| c.to_string());
+            match stream["codec_type"].as_str() {
+                Some("video") if info.video_codec.is_none() => {
+                    info.video_codec = codec;
+                    info.width = stream["width"].as_u64().unwrap_or(0) as u32;
+                    info.height = stream["height"].as_u64().unwrap_or(0) as u32;
+                    info.fps = stream["r_frame_rate"].as_str().map(parse_rational).unwrap_or(0.0);
+                    info.video_duration = stream_duration(stream);
+                }
+                Some("audio") if info.audio_codec.is_none() => {
+                    info.audio_codec = codec;
+                    info.audio_duration = stream_duration(stream);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Some(info)
+}
+
+// 读取容器中的章节(如MKV的章节、MP4的chapter轨道)，没有章节时返回空
+pub fn probe_chapters(path: &Path) -> Vec<Chapter> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v", "error",
+            "-print_format", "json",
+            "-show_chapters",
+            path.to_str().unwrap(),
+        ])
+        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            println!("{} 读取章节失败: {}", "❌".red(), e);
+            return Vec::new();
+        }
+    };
+
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    let chapters = json["chapters"]
+        .as_array()
+        .map(|chapters| {
+            chapters
+                .iter()
+                .filter_map(|chapter| {
+                    Some(Chapter {
+                        start: chapter["start_time"].as_str()?.parse::<f64>().ok()?,
+                        title: chapter["tags"]["title"].as_str().unwrap_or_default().trim().to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    normalize(chapters)
+}
+
+// 统计视频流的帧数（按数据包计数，比解码快得多）
+pub fn count_video_frames(path: &Path) -> Option<u64> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v", "error",
+            "-select_streams", "v:0",
+            "-count_packets",
+            "-show_entries", "stream=nb_read_packets",
+            "-of", "csv=p=0",
+            path.to_str().unwrap(),
+        ])
+        .output();
+
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().parse::<u64>().ok(),
+        Err(e) => {
+            println!("{} 统计视频帧数失败: {}", "❌".red(), e);
+            None
+        }
+    }
+}
+
+// 解析形如 30000/1001 的分数
+pub fn parse_rational(value: &str) -> f64 {
+    let mut parts = value.split('/');
+    let num = parts.next().and_then(|n| n.parse::<f64>().ok()).unwrap_or(0.0);
+    let den = parts.next().and_then(|d| d.parse::<f64>().ok()).unwrap_or(1.0);
+    if den == 0.0 { 0.0 } else { num / den }
+}
+
+fn stream_duration(stream: &Value) -> Option<f64> {
+    stream["duration"].as_str().and_then(|d| d.parse::<f64>().ok())
+}
@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use base64::Engine;
+use colored::Colorize;
+use walkdir::WalkDir;
+
+use crate::utils::{content_type, uri_encode};
+
+// 单个文件的上传结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadResult {
+    Uploaded,
+    Skipped,
+    Failed,
+}
+
+// 待发布的文件，relative为相对输出目录的路径（使用/分隔）
+#[derive(Debug, Clone)]
+pub struct PublishFile {
+    pub path: PathBuf,
+    pub relative: String,
+    pub size: u64,
+}
+
+impl PublishFile {
+    pub fn is_manifest(&self) -> bool {
+        matches!(
+            self.path.extension().and_then(|ext| ext.to_str()),
+            Some("mpd") | Some("m3u8")
+        )
+    }
+}
+
+// 发布目标：本地目录、WebDAV、rclone远程或S3
+pub trait Publisher: Send + Sync {
+    // 目标位置的描述，用于日志
+    fn describe(&self, name: &str) -> String;
+
+    // 上传单个文件，远端已有相同文件时返回Skipped
+    fn upload(&self, file: &PublishFile, name: &str) -> UploadResult;
+
+    fn workers(&self) -> usize {
+        1
+    }
+
+    fn retries(&self) -> u32 {
+        0
+    }
+
+    // 发布一个DASH输出目录：先并行上传分片等文件，全部成功后再上传清单文件
+    fn publish(&self, local_dir: &Path, name: &str) -> bool {
+        let (files, manifests) = list_files(local_dir);
+        println!("{} 正在发布 {} 个文件到 {}", "☁️".blue(), files.len() + manifests.len(), self.describe(name));
+
+        let mut results = upload_parallel(self, &files, name);
+        let failed = results.iter().filter(|r| **r == UploadResult::Failed).count();
+        if failed > 0 {
+            println!("{} {} 个文件上传失败, 不上传清单文件", "❌".red(), failed);
+            return false;
+        }
+
+        // 清单最后上传，播放器看到清单时所有分片都已就绪
+        for file in &manifests {
+            let result = upload_with_retry(self, file, name);
+            if result == UploadResult::Failed {
+                println!("{} 清单文件上传失败", "❌".red());
+                return false;
+            }
+            results.push(result);
+        }
+
+        let uploaded = results.iter().filter(|r| **r == UploadResult::Uploaded).count();
+        let skipped = results.iter().filter(|r| **r == UploadResult::Skipped).count();
+        println!("{} 发布完成: 上传 {} / 未变化跳过 {}", "✅".green(), uploaded, skipped);
+        true
+    }
+}
+
+// 根据 --publish 参数创建发布目标：
+// webdav:URL 或 http(s)://URL 为WebDAV，rclone:远程:路径 为rclone，其余为本地目录（可加dir:前缀）
+pub fn parse_target(spec: &str, retries: u32, workers: usize) -> Box<dyn Publisher> {
+    if let Some(url) = spec.strip_prefix("webdav:") {
+        Box::new(WebDavPublisher::new(url, retries, workers))
+    } else if spec.starts_with("http://") || spec.starts_with("https://") {
+        Box::new(WebDavPublisher::new(spec, retries, workers))
+    } else if let Some(remote) = spec.strip_prefix("rclone:") {
+        Box::new(RclonePublisher::new(remote, retries))
+    } else {
+        let root = spec.strip_prefix("dir:").unwrap_or(spec);
+        Box::new(LocalPublisher::new(Path::new(root), retries))
+    }
+}
+
+// rclone排除av1中间文件的过滤规则，不以/开头时匹配任意层级，包括多Period组的 period_N/av1/，
+// 与list_files一致
+const RCLONE_EXCLUDE_AV1: &str = "av1/**";
+
+// 列出需要发布的文件（不含任意层级的av1中间文件），返回(普通文件, 清单文件)
+fn list_files(local_dir: &Path) -> (Vec<PublishFile>, Vec<PublishFile>) {
+    let mut files = Vec::new();
+    let mut manifests = Vec::new();
+    for entry in WalkDir::new(local_dir)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != "av1")
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file = PublishFile {
+            path: path.to_path_buf(),
+            relative: path.strip_prefix(local_dir).unwrap().to_string_lossy().replace('\\', "/"),
+            size: entry.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+        };
+        if file.is_manifest() {
+            manifests.push(file);
+        } else {
+            files.push(file);
+        }
+    }
+    (files, manifests)
+}
+
+// 按workers()的线程数并行上传
+fn upload_parallel<P: Publisher + ?Sized>(publisher: &P, files: &[PublishFile], name: &str) -> Vec<UploadResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..publisher.workers().max(1).min(files.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(file) = files.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    let result = upload_with_retry(publisher, file, name);
+                    results.lock().unwrap().push(result);
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap()
+}
+
+// 失败时按 2、4、8... 秒的间隔重试
+fn upload_with_retry<P: Publisher + ?Sized>(publisher: &P, file: &PublishFile, name: &str) -> UploadResult {
+    let mut attempt = 0;
+    loop {
+        let result = publisher.upload(file, name);
+        if result != UploadResult::Failed || attempt >= publisher.retries() {
+            return result;
+        }
+        attempt += 1;
+        let wait = 1u64 << attempt.min(6);
+        println!("{} {} 上传失败, {}秒后第{}次重试", "🔄".yellow(), file.relative, wait, attempt);
+        thread::sleep(Duration::from_secs(wait));
+    }
+}
+
+// 发布到本地目录（如挂载的网盘），中断的文件在源文件未变化时从已写入的位置继续复制
+pub struct LocalPublisher {
+    root: PathBuf,
+    retries: u32,
+}
+
+impl LocalPublisher {
+    pub fn new(root: &Path, retries: u32) -> Self {
+        LocalPublisher {
+            root: root.to_path_buf(),
+            retries,
+        }
+    }
+}
+
+impl Publisher for LocalPublisher {
+    fn describe(&self, name: &str) -> String {
+        self.root.join(name).display().to_string()
+    }
+
+    fn upload(&self, file: &PublishFile, name: &str) -> UploadResult {
+        let target = self.root.join(name).join(&file.relative);
+        if !file.is_manifest() && fs::metadata(&target).map(|m| m.len() == file.size).unwrap_or(false) {
+            return UploadResult::Skipped;
+        }
+
+        match copy_resumable(&file.path, &target, file.size) {
+            Ok(_) => UploadResult::Uploaded,
+            Err(e) => {
+                println!("{} 复制失败 {}: {}", "❌".red(), target.display(), e);
+                UploadResult::Failed
+            }
+        }
+    }
+
+    fn retries(&self) -> u32 {
+        self.retries
+    }
+}
+
+// 先写入 .part 文件，校验大小后再重命名为目标文件。.part 旁边的 .part.src 记录源文件的大小和修改时间，
+// 两者都与当前源文件一致时才从 .part 末尾续写，否则源文件已经变化，从头重新复制
+fn copy_resumable(source: &Path, target: &Path, size: u64) -> io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+    let part = target.with_file_name(format!("{}.part", file_name));
+    let stamp_path = target.with_file_name(format!("{}.part.src", file_name));
+
+    let stamp = source_stamp(source, size)?;
+    let resumable = fs::read_to_string(&stamp_path).map(|saved| saved == stamp).unwrap_or(false);
+    let mut offset = if resumable { fs::metadata(&part).map(|m| m.len()).unwrap_or(0) } else { 0 };
+    if offset > size {
+        offset = 0;
+    }
+    if offset == 0 {
+        // 截断旧的 .part，并在写入数据前记录本次的源文件
+        File::create(&part)?;
+        fs::write(&stamp_path, &stamp)?;
+    }
+
+    let mut input = File::open(source)?;
+    input.seek(SeekFrom::Start(offset))?;
+    let mut output = OpenOptions::new().append(true).open(&part)?;
+    io::copy(&mut input, &mut output)?;
+    output.sync_all()?;
+
+    let written = fs::metadata(&part)?.len();
+    if written != size {
+        fs::remove_file(&part)?;
+        let _ = fs::remove_file(&stamp_path);
+        return Err(io::Error::other(format!("大小不一致: 本地 {} / 目标 {}", size, written)));
+    }
+    fs::rename(&part, target)?;
+    let _ = fs::remove_file(&stamp_path);
+    Ok(())
+}
+
+// 源文件的大小和修改时间(纳秒)，如 1048576:1700000000123456789
+fn source_stamp(source: &Path, size: u64) -> io::Result<String> {
+    let modified = fs::metadata(source)?.modified()?;
+    let nanos = modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    Ok(format!("{}:{}", size, nanos))
+}
+
+// 发布到WebDAV，账号密码从环境变量 WEBDAV_USER / WEBDAV_PASSWORD 读取
+pub struct WebDavPublisher {
+    base_url: String,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+    retries: u32,
+    workers: usize,
+    // 已确认存在的目录，避免重复MKCOL
+    collections: Mutex<HashSet<String>>,
+}
+
+impl WebDavPublisher {
+    pub fn new(base_url: &str, retries: u32, workers: usize) -> Self {
+        let authorization = std::env::var("WEBDAV_USER").ok().map(|user| {
+            let password = std::env::var("WEBDAV_PASSWORD").unwrap_or_default();
+            let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+            format!("Basic {}", credentials)
+        });
+        WebDavPublisher {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            authorization,
+            agent: ureq::AgentBuilder::new().build(),
+            retries,
+            workers,
+            collections: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, uri_encode(path, false))
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self.agent.request(method, &self.url(path));
+        match self.authorization {
+            Some(ref authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    // 逐级创建目录，已存在时服务器返回405
+    fn ensure_collection(&self, dir: &str) -> Result<(), String> {
+        let mut current = String::new();
+        for part in dir.split('/').filter(|part| !part.is_empty()) {
+            if !current.is_empty() {
+                current.push('/');
+            }
+            current.push_str(part);
+            if self.collections.lock().unwrap().contains(&current) {
+                continue;
+            }
+            match self.request("MKCOL", &current).call() {
+                Ok(_) | Err(ureq::Error::Status(405, _)) => {
+                    self.collections.lock().unwrap().insert(current.clone());
+                }
+                Err(e) => return Err(format!("创建目录 {} 失败: {}", current, e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn remote_size(&self, path: &str) -> Option<u64> {
+        let response = self.request("HEAD", path).call().ok()?;
+        response.header("Content-Length")?.parse().ok()
+    }
+
+    // 先上传到临时文件再MOVE到目标位置，播放器不会读到上传了一半的文件
+    fn put(&self, file: &PublishFile, path: &str) -> Result<(), String> {
+        if let Some((dir, _)) = path.rsplit_once('/') {
+            self.ensure_collection(dir)?;
+        }
+
+        let temp = format!("{}.uploading", path);
+        let body = File::open(&file.path).map_err(|e| e.to_string())?;
+        self.request("PUT", &temp)
+            .set("Content-Type", content_type(&file.path))
+            .set("Content-Length", &file.size.to_string())
+            .send(body)
+            .map_err(|e| format!("上传失败: {}", e))?;
+
+        self.request("MOVE", &temp)
+            .set("Destination", &self.url(path))
+            .set("Overwrite", "T")
+            .call()
+            .map_err(|e| format!("移动临时文件失败: {}", e))?;
+
+        match self.remote_size(path) {
+            Some(size) if size == file.size => Ok(()),
+            Some(size) => Err(format!("大小不一致: 本地 {} / 远端 {}", file.size, size)),
+            None => Err("无法读取远端文件大小".to_string()),
+        }
+    }
+}
+
+impl Publisher for WebDavPublisher {
+    fn describe(&self, name: &str) -> String {
+        self.url(name)
+    }
+
+    // 远端已有大小一致的文件时跳过，中断后重新运行只上传缺失的文件；
+    // 不支持断点续传，中断的文件会整个重新上传
+    fn upload(&self, file: &PublishFile, name: &str) -> UploadResult {
+        let path = format!("{}/{}", name, file.relative);
+        if !file.is_manifest() && self.remote_size(&path) == Some(file.size) {
+            return UploadResult::Skipped;
+        }
+
+        match self.put(file, &path) {
+            Ok(_) => UploadResult::Uploaded,
+            Err(e) => {
+                println!("{} WebDAV {}: {}", "❌".red(), path, e);
+                UploadResult::Failed
+            }
+        }
+    }
+
+    fn workers(&self) -> usize {
+        self.workers
+    }
+
+    fn retries(&self) -> u32 {
+        self.retries
+    }
+}
+
+// 通过rclone发布到其配置的任意远程（如各类网盘），断点续传由rclone处理
+pub struct RclonePublisher {
+    remote: String,
+    retries: u32,
+}
+
+impl RclonePublisher {
+    pub fn new(remote: &str, retries: u32) -> Self {
+        RclonePublisher {
+            remote: remote.trim_end_matches('/').to_string(),
+            retries,
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> bool {
+        match Command::new("rclone").args(args).status() {
+            Ok(status) => status.success(),
+            Err(e) => {
+                println!("{} 执行rclone失败: {}", "❌".red(), e);
+                false
+            }
+        }
+    }
+}
+
+impl Publisher for RclonePublisher {
+    fn describe(&self, name: &str) -> String {
+        format!("{}/{}", self.remote, name)
+    }
+
+    fn upload(&self, file: &PublishFile, name: &str) -> UploadResult {
+        let source = file.path.to_string_lossy();
+        let destination = format!("{}/{}/{}", self.remote, name, file.relative);
+        let retries = (self.retries + 1).to_string();
+        if self.run(&["copyto", &source, &destination, "--retries", &retries]) {
+            UploadResult::Uploaded
+        } else {
+            UploadResult::Failed
+        }
+    }
+
+    // 整个目录交给rclone批量处理：先传分片，再传清单，最后按大小校验
+    fn publish(&self, local_dir: &Path, name: &str) -> bool {
+        let source = local_dir.to_string_lossy();
+        let destination = self.describe(name);
+        let retries = (self.retries + 1).to_string();
+        println!("{} 正在通过rclone发布 {} 到 {}", "☁️".blue(), source, destination);
+
+        let segments = [
+            "copy", &source, &destination, "--retries", &retries,
+            "--exclude", RCLONE_EXCLUDE_AV1, "--exclude", "*.mpd", "--exclude", "*.m3u8",
+        ];
+        if !self.run(&segments) {
+            println!("{} rclone上传失败, 不上传清单文件", "❌".red());
+            return false;
+        }
+
+        let manifests = [
+            "copy", &source, &destination, "--retries", &retries,
+            "--include", "*.mpd", "--include", "*.m3u8",
+        ];
+        if !self.run(&manifests) {
+            println!("{} rclone上传清单文件失败", "❌".red());
+            return false;
+        }
+
+        let check = ["check", &source, &destination, "--one-way", "--size-only", "--exclude", RCLONE_EXCLUDE_AV1];
+        if !self.run(&check) {
+            println!("{} rclone校验失败, 远端文件大小与本地不一致", "❌".red());
+            return false;
+        }
+
+        println!("{} rclone发布完成: {}", "✅".green(), destination);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_files_skips_av1_at_any_depth() {
+        let root = std::env::temp_dir().join(format!("dash_publish_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for relative in ["main.mpd", "av1/x.mp4", "period_1/main.mpd", "period_1/av1/x.mp4", "period_1/live/chunk_1_1.m4s"] {
+            let path = root.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"data").unwrap();
+        }
+
+        let (files, manifests) = list_files(&root);
+        fs::remove_dir_all(&root).unwrap();
+        let relative = |files: &[PublishFile]| {
+            let mut names: Vec<String> = files.iter().map(|file| file.relative.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(relative(&files), ["period_1/live/chunk_1_1.m4s"]);
+        assert_eq!(relative(&manifests), ["main.mpd", "period_1/main.mpd"]);
+        assert!(files.iter().all(|file| file.size == 4));
+
+        // rclone的规则同样不能锚定在根目录
+        assert!(!RCLONE_EXCLUDE_AV1.starts_with('/'));
+    }
+}
@@ -0,0 +1,206 @@
+use std::path::Path;
+use std::process::Command;
+use colored::Colorize;
+use regex::Regex;
+
+use crate::analysis::VideoFilters;
+use crate::probe::probe_media;
+
+// 每个采样片段的时长（秒）
+const SAMPLE_SECONDS: f64 = 10.0;
+// CRF的取值范围
+pub const MIN_CRF: u32 = 10;
+pub const MAX_CRF: u32 = 63;
+
+// 质量评估指标
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QualityMetric {
+    Vmaf,
+    Ssim,
+    Psnr,
+}
+
+impl QualityMetric {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "vmaf" => Some(QualityMetric::Vmaf),
+            "ssim" => Some(QualityMetric::Ssim),
+            "psnr" => Some(QualityMetric::Psnr),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => "vmaf",
+            QualityMetric::Ssim => "ssim",
+            QualityMetric::Psnr => "psnr",
+        }
+    }
+
+    // 各指标的默认合格线
+    pub fn default_threshold(&self) -> f64 {
+        match self {
+            QualityMetric::Vmaf => 93.0,
+            QualityMetric::Ssim => 0.97,
+            QualityMetric::Psnr => 40.0,
+        }
+    }
+
+    fn filter(&self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => "libvmaf",
+            QualityMetric::Ssim => "ssim",
+            QualityMetric::Psnr => "psnr",
+        }
+    }
+
+    fn score_pattern(&self) -> &'static str {
+        match self {
+            QualityMetric::Vmaf => r"VMAF score[:=]\s*([0-9.]+)",
+            QualityMetric::Ssim => r"SSIM .*All:([0-9.]+|inf)",
+            QualityMetric::Psnr => r"PSNR .*average:([0-9.]+|inf)",
+        }
+    }
+}
+
+// 转码后质量校验的参数
+#[derive(Debug, Clone)]
+pub struct QualityOptions {
+    pub metric: QualityMetric,
+    pub threshold: f64,
+    pub retries: u32,
+    pub samples: u32,
+}
+
+// 目标质量编码的参数：在采样片段上搜索达到目标VMAF的CRF
+#[derive(Debug, Clone)]
+pub struct TargetQuality {
+    pub vmaf: f64,
+    pub samples: u32,
+}
+
+// 质量评估结果：平均分和各采样片段得分
+#[derive(Debug, Clone)]
+pub struct QualityScore {
+    pub score: f64,
+    pub samples: Vec<f64>,
+}
+
+// 对比源视频和编码后的视频，在若干采样片段上计算质量得分
+// 源视频先经过与编码时相同的裁剪/反交错滤镜，保证两边画面一致
+pub fn measure_quality(
+    source: &Path,
+    encoded: &Path,
+    options: &QualityOptions,
+    filters: &VideoFilters,
+) -> Option<QualityScore> {
+    let info = probe_media(source)?;
+    let (width, height) = filters.output_size().unwrap_or((info.width, info.height));
+    let reference_filter = filters.ffmpeg_filter().map(|f| format!("{},", f)).unwrap_or_default();
+    println!("{} 正在计算{}得分: {}", "📏".blue(), options.metric.name().to_uppercase(), encoded.display());
+
+    let mut samples = Vec::new();
+    for (start, length) in sample_windows(info.duration, options.samples) {
+        // 编码结果缩放到源分辨率后再与源视频比较
+        let lavfi = format!(
+            "[0:v]scale={}:{}:flags=bicubic,setpts=PTS-STARTPTS[dist];[1:v]{}setpts=PTS-STARTPTS[ref];[dist][ref]{}",
+            width, height, reference_filter, options.metric.filter()
+        );
+
+        let output = Command::new("ffmpeg")
+            .args([
+                "-hide_banner",
+                "-nostats",
+                "-ss", &format!("{:.3}", start),
+                "-t", &format!("{:.3}", length),
+                "-i", encoded.to_str().unwrap(),
+                "-ss", &format!("{:.3}", start),
+                "-t", &format!("{:.3}", length),
+                "-i", source.to_str().unwrap(),
+                "-lavfi", &lavfi,
+                "-f", "null",
+                "-",
+            ])
+            .output();
+
+        match output {
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                match parse_score(&stderr, options.metric) {
+                    Some(score) => samples.push(score),
+                    None => {
+                        println!("{} 未能解析{}得分", "⚠️".yellow(), options.metric.name().to_uppercase());
+                        return None;
+                    }
+                }
+            }
+            Err(e) => {
+                println!("{} 执行ffmpeg失败: {}", "❌".red(), e);
+                return None;
+            }
+        }
+    }
+
+    if samples.is_empty() {
+        return None;
+    }
+
+    let score = samples.iter().sum::<f64>() / samples.len() as f64;
+    println!("{} {}得分: {:.3} (采样 {:?})", "📏".blue(), options.metric.name().to_uppercase(), score, samples);
+    Some(QualityScore { score, samples })
+}
+
+// 在视频中均匀选取采样片段，视频太短时直接比较整段
+pub fn sample_windows(duration: f64, count: u32) -> Vec<(f64, f64)> {
+    let count = count.max(1);
+    if duration <= 0.0 || duration <= SAMPLE_SECONDS * count as f64 {
+        return vec![(0.0, duration.max(SAMPLE_SECONDS))];
+    }
+
+    let step = duration / count as f64;
+    (0..count)
+        .map(|i| (step * i as f64 + (step - SAMPLE_SECONDS) / 2.0, SAMPLE_SECONDS))
+        .collect()
+}
+
+fn parse_score(stderr: &str, metric: QualityMetric) -> Option<f64> {
+    let re = Regex::new(metric.score_pattern()).unwrap();
+    re.captures_iter(stderr)
+        .last()
+        .and_then(|caps| caps[1].parse::<f64>().ok())
+}
+
+// 根据各CRF的采样得分线性插值出刚好达到目标分数的CRF，结果限制在编码器的取值范围(min, max)内；
+// points必须来自同一个编码器
+pub fn interpolate_crf(points: &[(u32, f64)], target: f64, range: (u32, u32)) -> Option<u32> {
+    let mut points = points.to_vec();
+    points.sort_by_key(|(crf, _)| *crf);
+
+    let crf = match points.len() {
+        0 => return None,
+        1 => points[0].0 as f64,
+        _ => {
+            // 找到跨过目标分数的相邻两点，两端不足时用最外侧的两点外推
+            let pair = points
+                .windows(2)
+                .find(|pair| pair[0].1 >= target && pair[1].1 < target)
+                .map(|pair| (pair[0], pair[1]))
+                .unwrap_or_else(|| {
+                    if points[0].1 < target {
+                        (points[0], points[1])
+                    } else {
+                        (points[points.len() - 2], points[points.len() - 1])
+                    }
+                });
+            let ((crf_a, score_a), (crf_b, score_b)) = pair;
+            if (score_a - score_b).abs() < f64::EPSILON {
+                crf_a as f64
+            } else {
+                crf_a as f64 + (score_a - target) / (score_a - score_b) * (crf_b as f64 - crf_a as f64)
+            }
+        }
+    };
+
+    Some((crf.floor().max(0.0) as u32).clamp(range.0, range.1))
+}
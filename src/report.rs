@@ -0,0 +1,72 @@
+use std::fs;
+use std::path::Path;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+// 单个视频的处理报告，写入输出目录下的report.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobReport {
+    pub source: String,
+    pub encoder: Option<String>,
+    pub crf: Option<u32>,
+    pub crf_search: Vec<QualityAttempt>,
+    pub color: Option<String>,
+    pub filters: Option<String>,
+    pub quality: Option<QualityReport>,
+    pub flagged: bool,
+    pub notes: Vec<String>,
+}
+
+// 转码质量评估结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub metric: String,
+    pub threshold: f64,
+    pub attempts: Vec<QualityAttempt>,
+}
+
+// 每一次编码尝试的质量得分，crf为空表示使用编码器的默认质量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QualityAttempt {
+    pub crf: Option<u32>,
+    pub score: f64,
+    pub samples: Vec<f64>,
+}
+
+impl JobReport {
+    pub fn new(source: &Path) -> Self {
+        JobReport {
+            source: source.to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    // 读取输出目录中已有报告记录的源文件，用于判断输出目录属于哪个视频
+    pub fn load_source(dash_dir: &Path) -> Option<String> {
+        let content = fs::read_to_string(dash_dir.join("report.json")).ok()?;
+        serde_json::from_str::<JobReport>(&content).ok().map(|report| report.source)
+    }
+
+    pub fn note(&mut self, message: String) {
+        self.notes.push(message);
+    }
+
+    // 写入报告文件
+    pub fn write(&self, dash_dir: &Path) -> bool {
+        let report_path = dash_dir.join("report.json");
+        let json_str = match serde_json::to_string_pretty(self) {
+            Ok(json_str) => json_str,
+            Err(e) => {
+                println!("{} 报告序列化失败: {}", "❌".red(), e);
+                return false;
+            }
+        };
+
+        if let Err(e) = fs::write(&report_path, json_str) {
+            println!("{} 写入报告失败: {}", "❌".red(), e);
+            return false;
+        }
+
+        true
+    }
+}
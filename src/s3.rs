@@ -0,0 +1,329 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use colored::Colorize;
+use hmac::{Hmac, Mac};
+use md5::{Digest as _, Md5};
+use regex::Regex;
+use sha2::Sha256;
+
+use crate::publish::{PublishFile, Publisher, UploadResult};
+use crate::utils::{content_type, uri_encode};
+
+// 超过这个大小的文件使用分片上传，同时也是每个分片的大小
+const PART_SIZE: u64 = 16 * 1024 * 1024;
+
+// S3兼容对象存储的配置，访问密钥从环境变量读取
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub workers: usize,
+    pub retries: u32,
+}
+
+// 上传到S3兼容存储（如MinIO），使用path-style地址和SigV4签名
+pub struct S3Uploader {
+    config: S3Config,
+    agent: ureq::Agent,
+}
+
+impl S3Config {
+    // 从环境变量 AWS_ACCESS_KEY_ID / AWS_SECRET_ACCESS_KEY 读取密钥
+    pub fn from_env(endpoint: &str, bucket: &str, region: &str, prefix: &str, workers: usize, retries: u32) -> Option<Self> {
+        let access_key = std::env::var("AWS_ACCESS_KEY_ID").ok()?;
+        let secret_key = std::env::var("AWS_SECRET_ACCESS_KEY").ok()?;
+        Some(S3Config {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            access_key,
+            secret_key,
+            workers,
+            retries,
+        })
+    }
+}
+
+impl S3Uploader {
+    pub fn new(config: S3Config) -> Self {
+        S3Uploader {
+            config,
+            agent: ureq::AgentBuilder::new().build(),
+        }
+    }
+
+    // 对象键：前缀/名称/相对路径
+    fn object_key(&self, name: &str, relative: &str) -> String {
+        [self.config.prefix.as_str(), name, relative]
+            .iter()
+            .filter(|part| !part.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    // 上传单个文件，远端ETag与本地一致时跳过
+    fn upload_file(&self, path: &Path, key: &str) -> UploadResult {
+        let size = match fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                println!("{} 读取文件失败 {}: {}", "❌".red(), path.display(), e);
+                return UploadResult::Failed;
+            }
+        };
+
+        let remote = self.head(key);
+        if let (Some(local), Some((Some(etag), _))) = (local_etag(path, size), &remote) {
+            if local == *etag {
+                return UploadResult::Skipped;
+            }
+        }
+
+        let ok = if size > PART_SIZE {
+            self.upload_multipart(path, key, size)
+        } else {
+            match fs::read(path) {
+                Ok(body) => self
+                    .send("PUT", key, "", &body, Some(content_type(path)))
+                    .is_ok(),
+                Err(e) => {
+                    println!("{} 读取文件失败 {}: {}", "❌".red(), path.display(), e);
+                    false
+                }
+            }
+        };
+
+        if !ok {
+            println!("{} 上传失败: {}", "❌".red(), key);
+            return UploadResult::Failed;
+        }
+
+        // 上传后校验远端大小
+        match self.head(key) {
+            Some((_, Some(remote_size))) if remote_size == size => UploadResult::Uploaded,
+            _ => {
+                println!("{} 上传后大小校验失败: {}", "❌".red(), key);
+                UploadResult::Failed
+            }
+        }
+    }
+
+    // 读取远端对象的ETag和大小
+    fn head(&self, key: &str) -> Option<(Option<String>, Option<u64>)> {
+        let response = self.send("HEAD", key, "", &[], None).ok()?;
+        let etag = response.header("ETag").map(|etag| etag.trim_matches('"').to_string());
+        let size = response.header("Content-Length").and_then(|size| size.parse().ok());
+        Some((etag, size))
+    }
+
+    // 分片上传：初始化、按--s3-workers的线程数并行上传各分片、合并，失败时取消
+    fn upload_multipart(&self, path: &Path, key: &str, size: u64) -> bool {
+        let response = match self.send("POST", key, "uploads=", &[], Some(content_type(path))) {
+            Ok(response) => response,
+            Err(_) => return false,
+        };
+        let body = response.into_string().unwrap_or_default();
+        let upload_id = match Regex::new(r"<UploadId>([^<]+)</UploadId>").unwrap().captures(&body) {
+            Some(caps) => caps[1].to_string(),
+            None => {
+                println!("{} 初始化分片上传失败: {}", "❌".red(), key);
+                return false;
+            }
+        };
+        let upload_query = format!("uploadId={}", uri_encode(&upload_id, true));
+
+        let part_count = size.div_ceil(PART_SIZE);
+        let next = AtomicU64::new(1);
+        let failed = AtomicBool::new(false);
+        let etags = Mutex::new(vec![String::new(); part_count as usize]);
+        thread::scope(|scope| {
+            for _ in 0..(self.config.workers.max(1) as u64).min(part_count) {
+                scope.spawn(|| {
+                    while !failed.load(Ordering::SeqCst) {
+                        let part_number = next.fetch_add(1, Ordering::SeqCst);
+                        if part_number > part_count {
+                            break;
+                        }
+                        match self.upload_part(path, key, &upload_query, part_number) {
+                            Some(etag) => etags.lock().unwrap()[part_number as usize - 1] = etag,
+                            None => failed.store(true, Ordering::SeqCst),
+                        }
+                    }
+                });
+            }
+        });
+        if failed.load(Ordering::SeqCst) {
+            let _ = self.send("DELETE", key, &upload_query, &[], None);
+            return false;
+        }
+
+        let etags = etags.into_inner().unwrap();
+        let parts: String = etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag))
+            .collect();
+        let complete = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+        if self.send("POST", key, &upload_query, complete.as_bytes(), Some("application/xml")).is_err() {
+            let _ = self.send("DELETE", key, &upload_query, &[], None);
+            return false;
+        }
+        true
+    }
+
+    // 读取并上传第part_number个分片(从1开始)，返回分片的ETag
+    fn upload_part(&self, path: &Path, key: &str, upload_query: &str, part_number: u64) -> Option<String> {
+        let mut buffer = Vec::with_capacity(PART_SIZE as usize);
+        let read = File::open(path).and_then(|mut file| {
+            file.seek(SeekFrom::Start((part_number - 1) * PART_SIZE))?;
+            file.take(PART_SIZE).read_to_end(&mut buffer)
+        });
+        if let Err(e) = read {
+            println!("{} 读取文件失败 {}: {}", "❌".red(), path.display(), e);
+            return None;
+        }
+
+        let query = format!("partNumber={}&{}", part_number, upload_query);
+        let response = self.send("PUT", key, &query, &buffer, None).ok()?;
+        Some(response.header("ETag").unwrap_or_default().to_string())
+    }
+
+    // 发送带SigV4签名的请求，query需按参数名排序
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &str,
+        body: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<ureq::Response, ()> {
+        let config = &self.config;
+        // 默认端口不会出现在Host头中，签名时也要去掉
+        let host = config.endpoint.split("://").last().unwrap_or(&config.endpoint);
+        let host = match config.endpoint.split("://").next() {
+            Some("http") => host.trim_end_matches(":80"),
+            Some("https") => host.trim_end_matches(":443"),
+            _ => host,
+        };
+        let path = format!("/{}/{}", uri_encode(&config.bucket, true), uri_encode(key, false));
+        let url = if query.is_empty() {
+            format!("{}{}", config.endpoint, path)
+        } else {
+            format!("{}{}?{}", config.endpoint, path, query)
+        };
+
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(body));
+
+        let canonical_headers = format!(
+            "host:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n",
+            host, payload_hash, amz_date
+        );
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, path, query, canonical_headers, signed_headers, payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [date.as_str(), config.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", config.secret_key).into_bytes(), |key, part| {
+                hmac_sha256(&key, part.as_bytes())
+            });
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            config.access_key, scope, signed_headers, signature
+        );
+
+        let mut request = self
+            .agent
+            .request(method, &url)
+            .set("x-amz-date", &amz_date)
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("Authorization", &authorization);
+        if let Some(content_type) = content_type {
+            request = request.set("Content-Type", content_type);
+        }
+
+        match request.send_bytes(body) {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(404, _)) if method == "HEAD" => Err(()),
+            Err(ureq::Error::Status(code, response)) => {
+                let message = response.into_string().unwrap_or_default();
+                println!("{} S3请求失败 {} {}: {} {}", "❌".red(), method, key, code, message);
+                Err(())
+            }
+            Err(e) => {
+                println!("{} S3请求失败 {} {}: {}", "❌".red(), method, key, e);
+                Err(())
+            }
+        }
+    }
+}
+
+impl Publisher for S3Uploader {
+    fn describe(&self, name: &str) -> String {
+        format!("s3://{}/{}", self.config.bucket, self.object_key(name, ""))
+    }
+
+    fn upload(&self, file: &PublishFile, name: &str) -> UploadResult {
+        self.upload_file(&file.path, &self.object_key(name, &file.relative))
+    }
+
+    fn workers(&self) -> usize {
+        self.config.workers
+    }
+
+    fn retries(&self) -> u32 {
+        self.config.retries
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC可以使用任意长度的密钥");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// 按S3的规则计算本地文件的ETag：单次上传为MD5，分片上传为各分片MD5拼接后的MD5加分片数
+fn local_etag(path: &Path, size: u64) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    if size <= PART_SIZE {
+        let mut hasher = Md5::new();
+        std::io::copy(&mut file, &mut hasher).ok()?;
+        return Some(hex::encode(hasher.finalize()));
+    }
+
+    let mut digests = Vec::new();
+    let mut parts = 0;
+    loop {
+        let mut hasher = Md5::new();
+        let copied = std::io::copy(&mut (&mut file).take(PART_SIZE), &mut hasher).ok()?;
+        if copied == 0 {
+            break;
+        }
+        digests.extend_from_slice(&hasher.finalize());
+        parts += 1;
+    }
+    Some(format!("{}-{}", hex::encode(Md5::digest(&digests)), parts))
+}
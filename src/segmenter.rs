@@ -0,0 +1,478 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use colored::Colorize;
+
+use crate::encryption::{Encryption, Protection};
+use crate::hls::{init_name, segment_name, Hls, HlsMethod, KeyRing};
+use crate::mp4::{
+    earliest_presentation, init_segment, read_tracks, write_media_segment, write_single_file, Sample, SingleFileIndex, Track,
+    TrackKind,
+};
+
+// 内置的DASH打包器：把MP4按关键帧切成fMP4分片并生成MPD，不依赖ffmpeg的dash封装
+
+// MPD中的一个Representation，每个Representation单独作为一个AdaptationSet
+#[derive(Debug, Clone)]
+pub struct Representation {
+    pub id: usize,
+    pub kind: TrackKind,
+    pub codecs: String,
+    pub bandwidth: u64,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: Option<String>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub language: Option<String>,
+    pub timescale: u32,
+    pub presentation_time_offset: u64,
+    // 每个分片的(最早呈现时间, 时长)
+    pub segments: Vec<(u64, u64)>,
+    // 单文件模式下初始化部分和sidx的字节范围
+    pub single_file: Option<SingleFileIndex>,
+}
+
+// 静态点播MPD
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub single_file: bool,
+    pub duration: f64,
+    pub max_segment_duration: f64,
+    pub representations: Vec<Representation>,
+}
+
+// 打包输入：第一个文件使用全部视频和音频轨道，其余文件（如SDR版本）只使用视频轨道；
+// single_file为true时每个Representation只输出一个带sidx的fMP4文件，MPD使用SegmentBase按字节范围播放；
+// 指定encryption时对所有轨道进行CENC加密；指定hls时额外生成HLS播放列表，HLS加密时写出单独的分片
+pub fn package(
+    inputs: &[PathBuf],
+    dash_dir: &Path,
+    live_dir: &Path,
+    seg_duration: u32,
+    single_file: bool,
+    encryption: Option<&Encryption>,
+    hls: Option<&Hls>,
+) -> bool {
+    let mut manifest = Manifest {
+        single_file,
+        duration: 0.0,
+        max_segment_duration: 0.0,
+        representations: Vec::new(),
+    };
+
+    // 主视频轨道的分片起始时间（秒），音频在相同的位置切分以保持对齐
+    let mut boundaries: Option<Vec<f64>> = None;
+    let mut keys = KeyRing::default();
+    let sample_aes = hls.and_then(|hls| hls.encryption.as_ref()).is_some_and(|e| e.method == HlsMethod::SampleAes);
+
+    for (input_index, input) in inputs.iter().enumerate() {
+        let tracks = match read_tracks(input) {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                println!("{} 读取 {} 失败: {}", "❌".red(), input.display(), e);
+                return false;
+            }
+        };
+        let mut source = match File::open(input) {
+            Ok(file) => file,
+            Err(e) => {
+                println!("{} 打开 {} 失败: {}", "❌".red(), input.display(), e);
+                return false;
+            }
+        };
+
+        // 先处理视频轨道，得到切分位置后再处理音频
+        let mut ordered: Vec<&Track> = tracks.iter().filter(|t| t.kind == TrackKind::Video).collect();
+        if input_index == 0 {
+            ordered.extend(tracks.iter().filter(|t| t.kind == TrackKind::Audio));
+        }
+
+        for track in ordered {
+            // 视频的子样本按AV1的OBU划分，其他视频编码无法正确加密
+            if (encryption.is_some() || sample_aes) && track.kind == TrackKind::Video && !track.codec.starts_with("av01") {
+                println!("{} 加密只支持AV1视频, {} 的视频编码为 {}", "❌".red(), input.display(), track.codec);
+                return false;
+            }
+            let protection = encryption.map(|encryption| Protection::new(encryption, track.kind == TrackKind::Video));
+
+            let ranges = match track.kind {
+                TrackKind::Video => split_at_keyframes(&track.samples, track.timescale, seg_duration),
+                TrackKind::Audio => match boundaries {
+                    Some(ref times) => split_at_times(track, times),
+                    None => split_at_times(track, &fixed_times(track.duration(), seg_duration)),
+                },
+            };
+            if track.kind == TrackKind::Video && boundaries.is_none() {
+                boundaries = Some(
+                    ranges
+                        .iter()
+                        .map(|range| {
+                            let start = earliest_presentation(&track.samples[range.0..range.1]);
+                            start.saturating_sub(track.media_time) as f64 / track.timescale as f64
+                        })
+                        .collect(),
+                );
+            }
+
+            let id = manifest.representations.len();
+            let written = if single_file {
+                write_track_single(&mut source, track, &ranges, id, live_dir, protection.as_ref())
+            } else {
+                write_track(&mut source, track, &ranges, id, live_dir, protection.as_ref())
+            };
+            let written = match hls {
+                Some(hls) if hls.encryption.is_some() => written
+                    .and_then(|representation| write_hls_track(&mut source, track, &ranges, id, live_dir, hls, &mut keys).map(|_| representation)),
+                _ => written,
+            };
+            match written {
+                Ok(representation) => {
+                    manifest.duration = manifest.duration.max(track.duration());
+                    for (_, duration) in &representation.segments {
+                        let seconds = *duration as f64 / track.timescale as f64;
+                        manifest.max_segment_duration = manifest.max_segment_duration.max(seconds);
+                    }
+                    manifest.representations.push(representation);
+                }
+                Err(e) => {
+                    println!("{} 写入分片失败: {}", "❌".red(), e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    let mpd_path = dash_dir.join("main.mpd");
+    if let Err(e) = fs::write(&mpd_path, manifest.render()) {
+        println!("{} 写入MPD文件失败: {}", "❌".red(), e);
+        return false;
+    }
+    println!("{} 内置打包器生成 {} 个Representation", "📦".green(), manifest.representations.len());
+    hls.is_none_or(|hls| hls.write_playlists(dash_dir, live_dir, &manifest, &keys))
+}
+
+// 在达到目标时长后的第一个关键帧处切分，返回每个分片的样本范围[start, end)
+fn split_at_keyframes(samples: &[Sample], timescale: u32, seg_duration: u32) -> Vec<(usize, usize)> {
+    let target = seg_duration as u64 * timescale as u64;
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for (i, sample) in samples.iter().enumerate().skip(1) {
+        if sample.sync && sample.dts - samples[start].dts >= target {
+            ranges.push((start, i));
+            start = i;
+        }
+    }
+    ranges.push((start, samples.len()));
+    ranges
+}
+
+// 在给定的呈现时间点（秒）切分，用于音频对齐到视频分片
+fn split_at_times(track: &Track, times: &[f64]) -> Vec<(usize, usize)> {
+    let samples = &track.samples;
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for time in times.iter().skip(1) {
+        let boundary = (time * track.timescale as f64).round() as u64 + track.media_time;
+        let end = start + samples[start..].iter().take_while(|s| s.dts < boundary).count();
+        if end > start && end < samples.len() {
+            ranges.push((start, end));
+            start = end;
+        }
+    }
+    ranges.push((start, samples.len()));
+    ranges
+}
+
+fn fixed_times(duration: f64, seg_duration: u32) -> Vec<f64> {
+    let count = (duration / seg_duration.max(1) as f64).ceil().max(1.0) as usize;
+    (0..count).map(|i| (i * seg_duration as usize) as f64).collect()
+}
+
+// 写出一个轨道的初始化分片和所有媒体分片，分片命名与ffmpeg的dash封装一致
+fn write_track(
+    source: &mut File,
+    track: &Track,
+    ranges: &[(usize, usize)],
+    id: usize,
+    live_dir: &Path,
+    protection: Option<&Protection>,
+) -> Result<Representation, String> {
+    let init_path = live_dir.join(format!("init_{}.m4s", id));
+    fs::write(&init_path, init_segment(track, protection)).map_err(|e| format!("{}: {}", init_path.display(), e))?;
+
+    let mut segments = Vec::new();
+    let mut bandwidth = 0;
+    for (number, (start, end)) in ranges.iter().enumerate() {
+        let samples = &track.samples[*start..*end];
+        let path = live_dir.join(format!("chunk_{}_{}.m4s", id, number + 1));
+        let size = write_media_segment(source, &path, track, samples, number as u32 + 1, protection)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let duration: u64 = samples.iter().map(|s| s.duration as u64).sum();
+        // 带宽取各分片码率的最大值
+        if let Some(bitrate) = (size * 8 * track.timescale as u64).checked_div(duration) {
+            bandwidth = bandwidth.max(bitrate);
+        }
+        segments.push((earliest_presentation(samples), duration));
+    }
+
+    Ok(representation(track, id, bandwidth, segments, None))
+}
+
+// HLS加密时单独写出初始化分片和媒体分片：初始化分片不加密(SAMPLE-AES时带cbcs的sinf)，
+// 媒体分片按轮换使用的密钥加密，SAMPLE-AES加密样本，AES-128加密整个分片文件
+fn write_hls_track(
+    source: &mut File,
+    track: &Track,
+    ranges: &[(usize, usize)],
+    id: usize,
+    live_dir: &Path,
+    hls: &Hls,
+    keys: &mut KeyRing,
+) -> Result<(), String> {
+    let Some(ref encryption) = hls.encryption else {
+        return Ok(());
+    };
+    let video = track.kind == TrackKind::Video;
+
+    let init_path = live_dir.join(init_name(id, true));
+    let init = match encryption.method {
+        HlsMethod::SampleAes => init_segment(track, Some(&keys.get(0).protection(video))),
+        HlsMethod::Aes128 => init_segment(track, None),
+    };
+    fs::write(&init_path, init).map_err(|e| format!("{}: {}", init_path.display(), e))?;
+
+    for (number, (start, end)) in ranges.iter().enumerate() {
+        let samples = &track.samples[*start..*end];
+        let path = live_dir.join(segment_name(id, number + 1, true));
+        let key = keys.get(hls.key_index(number));
+        let written = match encryption.method {
+            HlsMethod::SampleAes => write_media_segment(source, &path, track, samples, number as u32 + 1, Some(&key.protection(video))),
+            HlsMethod::Aes128 => write_media_segment(source, &path, track, samples, number as u32 + 1, None)
+                // 媒体序列号从0开始，与分片序号一致
+                .and_then(|_| fs::write(&path, key.encrypt_segment(&fs::read(&path)?, number as u64)).map(|_| 0)),
+        };
+        written.map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+// 单文件模式：初始化部分、sidx和全部分片写入同一个文件 stream_{id}.mp4
+fn write_track_single(
+    source: &mut File,
+    track: &Track,
+    ranges: &[(usize, usize)],
+    id: usize,
+    live_dir: &Path,
+    protection: Option<&Protection>,
+) -> Result<Representation, String> {
+    let path = live_dir.join(format!("stream_{}.mp4", id));
+    let segments: Vec<&[Sample]> = ranges.iter().map(|(start, end)| &track.samples[*start..*end]).collect();
+    let (index, sizes) = write_single_file(source, &path, track, &segments, protection).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut bandwidth = 0;
+    let mut timeline = Vec::new();
+    for (samples, size) in segments.iter().zip(sizes) {
+        let duration: u64 = samples.iter().map(|s| s.duration as u64).sum();
+        if let Some(bitrate) = (size * 8 * track.timescale as u64).checked_div(duration) {
+            bandwidth = bandwidth.max(bitrate);
+        }
+        timeline.push((earliest_presentation(samples), duration));
+    }
+    Ok(representation(track, id, bandwidth, timeline, Some(index)))
+}
+
+fn representation(track: &Track, id: usize, bandwidth: u64, segments: Vec<(u64, u64)>, single_file: Option<SingleFileIndex>) -> Representation {
+    Representation {
+        id,
+        kind: track.kind,
+        codecs: track.codec.clone(),
+        bandwidth,
+        width: track.width,
+        height: track.height,
+        frame_rate: frame_rate(track),
+        sample_rate: track.sample_rate,
+        channels: track.channels,
+        language: track.language.clone(),
+        timescale: track.timescale,
+        presentation_time_offset: track.media_time,
+        segments,
+        single_file,
+    }
+}
+
+// 以最常见的样本时长计算帧率，如 24000/1001
+fn frame_rate(track: &Track) -> Option<String> {
+    if track.kind != TrackKind::Video {
+        return None;
+    }
+    let mut durations: Vec<u32> = track.samples.iter().map(|s| s.duration).filter(|d| *d > 0).collect();
+    durations.sort_unstable();
+    let (duration, _) = durations
+        .chunk_by(|a, b| a == b)
+        .map(|run| (run[0], run.len()))
+        .max_by_key(|(_, count)| *count)?;
+
+    let divisor = gcd(track.timescale as u64, duration as u64);
+    let (numerator, denominator) = (track.timescale as u64 / divisor, duration as u64 / divisor);
+    if denominator == 1 {
+        Some(numerator.to_string())
+    } else {
+        Some(format!("{}/{}", numerator, denominator))
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// ISO 8601时长，与ffmpeg的写法一致，如 PT0H10M0.000S
+pub fn iso_duration(seconds: f64) -> String {
+    let hours = (seconds / 3600.0).floor();
+    let minutes = ((seconds - hours * 3600.0) / 60.0).floor();
+    let rest = seconds - hours * 3600.0 - minutes * 60.0;
+    format!("PT{}H{}M{:.3}S", hours as u64, minutes as u64, rest)
+}
+
+impl Manifest {
+    pub fn render(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        // 单文件按字节范围播放属于on-demand profile
+        let profile = if self.single_file {
+            "urn:mpeg:dash:profile:isoff-on-demand:2011"
+        } else {
+            "urn:mpeg:dash:profile:isoff-live:2011"
+        };
+        xml.push_str(&format!(
+            "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"{}\" type=\"static\" mediaPresentationDuration=\"{}\" maxSegmentDuration=\"{}\" minBufferTime=\"{}\">\n",
+            profile,
+            iso_duration(self.duration),
+            iso_duration(self.max_segment_duration),
+            iso_duration(self.max_segment_duration.ceil()),
+        ));
+        xml.push_str("\t<Period id=\"0\" start=\"PT0.0S\">\n");
+        for representation in &self.representations {
+            xml.push_str(&representation.render());
+        }
+        xml.push_str("\t</Period>\n</MPD>\n");
+        xml
+    }
+}
+
+impl Representation {
+    fn render(&self) -> String {
+        let (content_type, mime_type) = match self.kind {
+            TrackKind::Video => ("video", "video/mp4"),
+            TrackKind::Audio => ("audio", "audio/mp4"),
+        };
+
+        let mut adaptation_set = format!(
+            "\t\t<AdaptationSet id=\"{}\" contentType=\"{}\" startWithSAP=\"1\" segmentAlignment=\"true\"",
+            self.id, content_type
+        );
+        if let Some(ref language) = self.language {
+            adaptation_set.push_str(&format!(" lang=\"{}\"", language));
+        }
+        let mut xml = format!("{}>\n", adaptation_set);
+
+        let mut attributes = format!(
+            "id=\"{}\" mimeType=\"{}\" codecs=\"{}\" bandwidth=\"{}\"",
+            self.id, mime_type, self.codecs, self.bandwidth
+        );
+        match self.kind {
+            TrackKind::Video => {
+                attributes.push_str(&format!(" width=\"{}\" height=\"{}\"", self.width, self.height));
+                if let Some(ref frame_rate) = self.frame_rate {
+                    attributes.push_str(&format!(" frameRate=\"{}\"", frame_rate));
+                }
+            }
+            TrackKind::Audio => {
+                attributes.push_str(&format!(" audioSamplingRate=\"{}\"", self.sample_rate));
+            }
+        }
+        xml.push_str(&format!("\t\t\t<Representation {}>\n", attributes));
+        if self.kind == TrackKind::Audio {
+            xml.push_str(&format!(
+                "\t\t\t\t<AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{}\" />\n",
+                self.channels
+            ));
+        }
+
+        if let Some(index) = self.single_file {
+            xml.push_str(&self.render_segment_base(&index));
+        } else {
+            xml.push_str(&self.render_segment_template());
+        }
+        xml.push_str("\t\t\t</Representation>\n");
+        xml.push_str("\t\t</AdaptationSet>\n");
+        xml
+    }
+
+    fn render_segment_base(&self, index: &SingleFileIndex) -> String {
+        let mut xml = format!("\t\t\t\t<BaseURL>live/stream_{}.mp4</BaseURL>\n", self.id);
+        let mut segment_base = format!(
+            "\t\t\t\t<SegmentBase timescale=\"{}\" indexRange=\"{}-{}\" indexRangeExact=\"true\"",
+            self.timescale, index.index_start, index.index_end
+        );
+        if self.presentation_time_offset > 0 {
+            segment_base.push_str(&format!(" presentationTimeOffset=\"{}\"", self.presentation_time_offset));
+        }
+        xml.push_str(&format!("{}>\n", segment_base));
+        xml.push_str(&format!("\t\t\t\t\t<Initialization range=\"0-{}\" />\n", index.init_end));
+        xml.push_str("\t\t\t\t</SegmentBase>\n");
+        xml
+    }
+
+    fn render_segment_template(&self) -> String {
+        let mut xml = String::new();
+        let mut template = format!(
+            "\t\t\t\t<SegmentTemplate timescale=\"{}\" initialization=\"live/init_$RepresentationID$.m4s\" media=\"live/chunk_$RepresentationID$_$Number$.m4s\" startNumber=\"1\"",
+            self.timescale
+        );
+        if self.presentation_time_offset > 0 {
+            template.push_str(&format!(" presentationTimeOffset=\"{}\"", self.presentation_time_offset));
+        }
+        xml.push_str(&format!("{}>\n", template));
+        xml.push_str("\t\t\t\t\t<SegmentTimeline>\n");
+        xml.push_str(&render_timeline(&self.segments));
+        xml.push_str("\t\t\t\t\t</SegmentTimeline>\n");
+        xml.push_str("\t\t\t\t</SegmentTemplate>\n");
+        xml
+    }
+}
+
+// 生成SegmentTimeline中的S元素，连续且时长相同的分片合并为一个（r为重复次数）
+pub fn render_timeline(segments: &[(u64, u64)]) -> String {
+    let mut xml = String::new();
+    let mut index = 0;
+    let mut expected_start = None;
+    while index < segments.len() {
+        let (start, duration) = segments[index];
+        let mut repeat = 0;
+        while let Some(next) = segments.get(index + repeat + 1) {
+            if next.1 != duration || next.0 != start + duration * (repeat as u64 + 1) {
+                break;
+            }
+            repeat += 1;
+        }
+
+        let mut element = String::from("\t\t\t\t\t\t<S");
+        if expected_start != Some(start) {
+            element.push_str(&format!(" t=\"{}\"", start));
+        }
+        element.push_str(&format!(" d=\"{}\"", duration));
+        if repeat > 0 {
+            element.push_str(&format!(" r=\"{}\"", repeat));
+        }
+        xml.push_str(&element);
+        xml.push_str(" />\n");
+
+        index += repeat + 1;
+        expected_start = Some(start + duration * (repeat as u64 + 1));
+    }
+    xml
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use colored::Colorize;
+use walkdir::WalkDir;
+
+// 把输出目录交付到目标位置：先放进目标所在文件系统上的暂存目录，校验后再一次性重命名，
+// 目标位置要么是完整的旧内容，要么是完整的新内容，不会出现复制到一半的目录
+pub fn deliver(source: &Path, target: &Path, copy: bool) -> Result<(), String> {
+    let parent = target.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent).map_err(|e| format!("创建目标目录失败: {}", e))?;
+
+    // 上次中断留下的暂存目录直接清理
+    let staging = sibling(target, "dash-staging");
+    if staging.exists() {
+        println!("{} 清理上次中断留下的暂存目录: {}", "🧹".yellow(), staging.display());
+        fs::remove_dir_all(&staging).map_err(|e| format!("清理暂存目录失败: {}", e))?;
+    }
+
+    // 移动时先尝试直接重命名，跨文件系统(EXDEV)等情况失败后改为复制
+    let moved = !copy && fs::rename(source, &staging).is_ok();
+    if !moved {
+        if !copy {
+            println!("{} 无法直接移动(可能跨磁盘), 改为复制后删除源目录", "🔄".yellow());
+        }
+        if let Err(e) = copy_verified(source, &staging) {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    }
+
+    if let Err(e) = replace_dir(&staging, target) {
+        // 直接重命名过来的暂存目录要还给源位置，避免丢失输出
+        if moved {
+            let _ = fs::rename(&staging, source);
+        } else {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        return Err(e);
+    }
+
+    // 跨文件系统移动：目标已完整就位后才删除源目录
+    if !copy && !moved {
+        fs::remove_dir_all(source).map_err(|e| format!("删除源目录失败: {}", e))?;
+    }
+    Ok(())
+}
+
+// 目标位置的同级隐藏目录，如 .video.dash-staging
+fn sibling(target: &Path, suffix: &str) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    target.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+// 复制整个目录并逐个校验文件大小
+fn copy_verified(source: &Path, destination: &Path) -> Result<(), String> {
+    let options = fs_extra::dir::CopyOptions {
+        content_only: true,
+        ..fs_extra::dir::CopyOptions::new()
+    };
+    fs::create_dir_all(destination).map_err(|e| format!("创建暂存目录失败: {}", e))?;
+    fs_extra::dir::copy(source, destination, &options).map_err(|e| format!("复制失败: {}", e))?;
+
+    for entry in WalkDir::new(source).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(source).unwrap();
+        let expected = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        match fs::metadata(destination.join(relative)) {
+            Ok(metadata) if metadata.len() == expected => {}
+            Ok(metadata) => {
+                return Err(format!(
+                    "校验失败 {}: 源 {} 字节 / 目标 {} 字节",
+                    relative.display(),
+                    expected,
+                    metadata.len()
+                ))
+            }
+            Err(e) => return Err(format!("校验失败 {}: {}", relative.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+// 用暂存目录替换目标目录：旧目录先改名备份，新目录就位后再删除备份
+fn replace_dir(staging: &Path, target: &Path) -> Result<(), String> {
+    if !target.exists() {
+        return fs::rename(staging, target).map_err(|e| format!("重命名暂存目录失败: {}", e));
+    }
+
+    let backup = sibling(target, "dash-old");
+    if backup.exists() {
+        fs::remove_dir_all(&backup).map_err(|e| format!("清理旧备份失败: {}", e))?;
+    }
+    fs::rename(target, &backup).map_err(|e| format!("备份已有输出失败: {}", e))?;
+    if let Err(e) = fs::rename(staging, target) {
+        let _ = fs::rename(&backup, target);
+        return Err(format!("重命名暂存目录失败: {}", e));
+    }
+    if let Err(e) = fs::remove_dir_all(&backup) {
+        println!("{} 删除旧输出失败 {}: {}", "⚠️".yellow(), backup.display(), e);
+    }
+    Ok(())
+}
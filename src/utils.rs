@@ -1,24 +1,31 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
 use std::path::{Path, PathBuf};
+use std::str::Chars;
 use std::fs;
 use colored::Colorize;
 use walkdir::WalkDir;
 
+// 按扩展名判断是否为视频文件
+pub fn is_video_file(path: &Path) -> bool {
+    let video_extensions = ["mp4", "mkv", "avi", "mov", "webm", "flv", "wmv"];
+    path.is_file()
+        && path
+            .extension()
+            .map(|ext| video_extensions.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+            .unwrap_or(false)
+}
+
 // 获取指定文件夹中的所有视频文件
 pub fn get_video_files(dir_path: &str) -> Vec<PathBuf> {
     let mut video_files = Vec::new();
-    let video_extensions = ["mp4", "mkv", "avi", "mov", "webm", "flv", "wmv"];
     
     println!("{} 正在搜索视频文件...", "🔍".blue());
     
     for entry in WalkDir::new(dir_path).into_iter().filter_map(|e| e.ok()) {
         let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if video_extensions.contains(&ext_str.as_str()) {
-                    video_files.push(path.to_path_buf());
-                }
-            }
+        if is_video_file(path) {
+            video_files.push(path.to_path_buf());
         }
     }
     
@@ -26,12 +33,8 @@ pub fn get_video_files(dir_path: &str) -> Vec<PathBuf> {
 }
 
 // 设置输出目录结构
-pub fn setup_output_dirs(video_path: &Path) -> (PathBuf, PathBuf, PathBuf) {
-    let parent_dir = video_path.parent().unwrap_or(Path::new("."));
-    let file_stem = video_path.file_stem().unwrap_or_default().to_string_lossy();
-    
-    // 创建和视频同名的文件夹
-    let dash_dir = parent_dir.join(&*file_stem);
+pub fn setup_output_dirs(dash_dir: &Path) -> (PathBuf, PathBuf, PathBuf) {
+    let dash_dir = dash_dir.to_path_buf();
     let av1_dir = dash_dir.join("av1");
     let live_dir = dash_dir.join("live");
     
@@ -49,4 +52,142 @@ pub fn setup_output_dirs(video_path: &Path) -> (PathBuf, PathBuf, PathBuf) {
     });
     
     (dash_dir, av1_dir, live_dir)
-}
\ No newline at end of file
+}
+
+// 自然排序比较，数字部分按数值比较，使 "第2讲" 排在 "第10讲" 之前
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_num = take_digits(&mut a_chars);
+                let b_num = take_digits(&mut b_chars);
+                // 去掉前导零后先比较位数，再逐位比较，避免大数溢出
+                let a_trimmed = a_num.trim_start_matches('0');
+                let b_trimmed = b_num.trim_start_matches('0');
+                let ordering = a_trimmed
+                    .len()
+                    .cmp(&b_trimmed.len())
+                    .then_with(|| a_trimmed.cmp(b_trimmed))
+                    .then_with(|| a_num.len().cmp(&b_num.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().copied() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+    digits
+}
+
+// 根据扩展名返回上传时使用的Content-Type
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    match ext.as_str() {
+        "mpd" => "application/dash+xml",
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "m4s" => "video/iso.segment",
+        "mp4" | "m4v" => "video/mp4",
+        "m4a" => "audio/mp4",
+        "ts" => "video/mp2t",
+        "vtt" => "text/vtt",
+        "json" => "application/json",
+        "xml" | "xspf" => "application/xml",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+// URL路径编码，除非保留字符外全部百分号编码（同时满足SigV4的要求）
+pub fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// 以可读的单位显示字节数，如 1.5 GB
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+// 目录中所有文件的总大小
+pub fn dir_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_cmp_orders_numbers_by_value() {
+        assert_eq!(natural_cmp("第2讲", "第10讲"), Ordering::Less);
+        assert_eq!(natural_cmp("第10讲", "第2讲"), Ordering::Greater);
+        assert_eq!(natural_cmp("ep9.mp4", "ep10.mp4"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_equal_prefix() {
+        assert_eq!(natural_cmp("第3讲", "第3讲"), Ordering::Equal);
+        assert_eq!(natural_cmp("第3讲", "第3讲下"), Ordering::Less);
+        assert_eq!(natural_cmp("第3讲 a", "第3讲 b"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_mixed_digits() {
+        // 数值相同时前导零少的在前
+        assert_eq!(natural_cmp("第2讲", "第02讲"), Ordering::Less);
+        assert_eq!(natural_cmp("第02讲", "第10讲"), Ordering::Less);
+        assert_eq!(natural_cmp("s1e10", "s1e9"), Ordering::Greater);
+        assert_eq!(natural_cmp("s2e1", "s10e1"), Ordering::Less);
+        // 超出u64范围的数字也能比较
+        assert_eq!(natural_cmp("a99999999999999999999999", "a100000000000000000000000"), Ordering::Less);
+    }
+}
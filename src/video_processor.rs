@@ -1,19 +1,133 @@
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use colored::Colorize;
+use std::cell::OnceCell;
 use std::fs;
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+use crate::analysis::{load_override, resolve_filters, Deinterlacer, VideoFilters};
+use crate::color::{probe_color, ColorInfo};
+use crate::dash_generator::DashGenerator;
+use crate::chunked::{concat_chunks, detect_scene_cuts, plan_chunks, verify_concat, Chunk, ChunkOptions};
+use crate::probe::{count_video_frames, probe_media};
+use crate::quality::{
+    interpolate_crf, measure_quality, sample_windows, QualityMetric, QualityOptions, TargetQuality,
+    MAX_CRF, MIN_CRF,
+};
+use crate::report::{JobReport, QualityAttempt, QualityReport};
+
+// ffmpeg备选转码使用的默认CRF
+const DEFAULT_CRF: u32 = 30;
+// 质量不达标时每次重新编码降低的CRF
+const CRF_STEP: u32 = 4;
+// 目标质量模式下试编码的质量参数，QSV的ICQ(1-51)和libaom的CRF(0-63)刻度不同，分别取候选值
+const QSV_CANDIDATES: [u32; 3] = [22, 28, 34];
+const LIBAOM_CANDIDATES: [u32; 3] = [24, 32, 40];
+// QSV的ICQ取值范围
+const QSV_CRF_RANGE: (u32, u32) = (1, 51);
+
+// 转码相关的选项
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    pub quality: Option<QualityOptions>,
+    pub target: Option<TargetQuality>,
+    pub chunked: Option<ChunkOptions>,
+    pub sdr_rendition: bool,
+    pub detect_filters: bool,
+    pub deinterlacer: Deinterlacer,
+    pub single_pass: bool,
+    // 关键帧间隔（秒），与DASH分片时长一致，0表示交给编码器决定
+    pub keyframe_seconds: u32,
+}
+
+// 固定的关键帧间隔，使每个DASH分片都从关键帧开始且时长一致
+#[derive(Debug, Clone, Copy)]
+struct Keyframes {
+    frames: u32,
+    seconds: u32,
+}
+
+impl Keyframes {
+    fn qsv_args(&self) -> Vec<String> {
+        vec![String::from("--gop-len"), self.frames.to_string(), String::from("--strict-gop")]
+    }
+
+    // 除GOP长度外再按时间强制插入关键帧，避免帧率不是整数时逐渐偏移
+    fn ffmpeg_args(&self) -> Vec<String> {
+        vec![
+            String::from("-g"), self.frames.to_string(),
+            String::from("-force_key_frames"), format!("expr:gte(t,n_forced*{})", self.seconds),
+        ]
+    }
+}
 
 pub struct VideoProcessor {
     video_path: PathBuf,
+    options: EncodeOptions,
+    color: OnceCell<Option<ColorInfo>>,
+    filters: OnceCell<VideoFilters>,
+    keyframes: OnceCell<Option<Keyframes>>,
 }
 
 impl VideoProcessor {
-    pub fn new(video_path: &Path) -> Self {
+    pub fn new(video_path: &Path, options: EncodeOptions) -> Self {
         VideoProcessor {
             video_path: PathBuf::from(video_path),
+            options,
+            color: OnceCell::new(),
+            filters: OnceCell::new(),
+            keyframes: OnceCell::new(),
         }
     }
 
+    // 源视频的色彩信息，首次使用时探测
+    fn color(&self) -> Option<&ColorInfo> {
+        self.color.get_or_init(|| probe_color(&self.video_path)).as_ref()
+    }
+
+    // 按源帧率换算的关键帧间隔，读取不到帧率时不设置
+    fn keyframes(&self) -> Option<Keyframes> {
+        *self.keyframes.get_or_init(|| {
+            if self.options.keyframe_seconds == 0 {
+                return None;
+            }
+            let fps = probe_media(&self.video_path).map(|info| info.fps).filter(|fps| *fps > 0.0)?;
+            Some(Keyframes {
+                frames: ((fps * self.options.keyframe_seconds as f64).round() as u32).max(1),
+                seconds: self.options.keyframe_seconds,
+            })
+        })
+    }
+
+    fn keyframe_qsv_args(&self) -> Vec<String> {
+        self.keyframes().map(|k| k.qsv_args()).unwrap_or_default()
+    }
+
+    fn keyframe_ffmpeg_args(&self) -> Vec<String> {
+        self.keyframes().map(|k| k.ffmpeg_args()).unwrap_or_default()
+    }
+
+    // 转码时应用的裁剪和反交错，单个文件的覆盖设置优先于自动检测
+    fn filters(&self) -> &VideoFilters {
+        self.filters.get_or_init(|| {
+            let filter_override = load_override(&self.video_path);
+            if !self.options.detect_filters && filter_override.is_none() {
+                return VideoFilters::default();
+            }
+            match probe_media(&self.video_path) {
+                Some(info) => resolve_filters(
+                    &self.video_path,
+                    &info,
+                    self.options.detect_filters,
+                    self.options.deinterlacer,
+                    filter_override.as_ref(),
+                ),
+                None => VideoFilters::default(),
+            }
+        })
+    }
+
     pub fn get_file_name(&self) -> String {
         self.video_path.file_name()
             .unwrap_or_default()
@@ -22,7 +136,7 @@ impl VideoProcessor {
     }
 
     // 处理视频，返回处理后的视频路径（用于后续生成DASH）
-    pub fn process(&self, av1_dir: &Path) -> Option<PathBuf> {
+    pub fn process(&self, av1_dir: &Path, report: &mut JobReport) -> Option<PathBuf> {
         // 确保输出目录存在
         if !av1_dir.exists() {
             fs::create_dir_all(av1_dir).unwrap_or_else(|e| {
@@ -40,23 +154,550 @@ impl VideoProcessor {
                 println!("{} 复制AV1视频失败: {}", "❌".red(), e);
                 return None;
             }
-            return Some(out_file);
+            report.encoder = Some(String::from("copy"));
+            Some(out_file)
         } else {
             // 需要转码为AV1
             println!("{} {} 不是AV1编码, 开始转码", "🔄".yellow(), file_name);
-            if self.transcode_to_av1(&out_file) {
-                println!("{} {} 转码为AV1成功", "✅".green(), file_name);
-                return Some(out_file);
+            let crf = self.prepare_encode(av1_dir, report);
+
+            if !self.encode(&out_file, crf, report) {
+                return None;
+            }
+            println!("{} {} 转码为AV1成功", "✅".green(), file_name);
+
+            if let Some(ref options) = self.options.quality {
+                let used = encoded_crf(crf, report.encoder.as_deref());
+                self.verify_quality(&out_file, options, used, report);
+            }
+            Some(out_file)
+        }
+    }
+
+    // 直接复制的AV1源关键帧间隔超过分片时长时，按分片时长的关键帧间隔重新编码，返回新的视频路径
+    pub fn realign(&self, av1_dir: &Path, report: &mut JobReport) -> Option<PathBuf> {
+        let file_name = self.get_file_name();
+        let out_file = av1_dir.join(&file_name);
+        println!("{} {} 关键帧间隔过长, 重新编码以对齐分片", "🔄".yellow(), file_name);
+
+        // 先编码到临时文件，失败时原有的视频和分片都保持不变
+        let temp_file = av1_dir.join(format!("realign_{}", file_name));
+        let crf = self.prepare_encode(av1_dir, report);
+        if !self.encode(&temp_file, crf, report) || fs::rename(&temp_file, &out_file).is_err() {
+            println!("{} {} 重新编码失败, 保留原有分片", "❌".red(), file_name);
+            let _ = fs::remove_file(&temp_file);
+            return None;
+        }
+        Some(out_file)
+    }
+
+    // 单遍模式：转码输出直接交给ffmpeg的DASH封装，不生成完整的AV1中间文件；
+    // 质量校验、分块编码和SDR版本都需要中间文件，此模式下不可用
+    pub fn process_streaming(&self, av1_dir: &Path, dash: &DashGenerator, live_dir: &Path, report: &mut JobReport) -> bool {
+        let file_name = self.get_file_name();
+        if self.is_av1_encoded() {
+            println!("{} {} 已经是AV1编码, 直接封装", "ℹ️".blue(), file_name);
+            report.encoder = Some(String::from("copy"));
+            return dash.generate_dash(live_dir);
+        }
+
+        println!("{} {} 不是AV1编码, 开始单遍转码", "🔄".yellow(), file_name);
+        let crf = self.prepare_encode(av1_dir, report);
+
+        // QSVEncC64把matroska写到标准输出，ffmpeg只做封装
+        let mut qsv_args = vec![
+            String::from("--codec"), String::from("av1"),
+            String::from("--input"), self.video_path.to_str().unwrap().to_string(),
+            String::from("--output"), String::from("-"),
+            String::from("--output-format"), String::from("matroska"),
+            String::from("--audio-copy"),
+            String::from("--avhw"),
+            String::from("--fallback-rc"),
+        ];
+        if let Some(crf) = crf {
+            qsv_args.push(String::from("--icq"));
+            qsv_args.push(crf.clamp(QSV_CRF_RANGE.0, QSV_CRF_RANGE.1).to_string());
+        }
+        if let Some(color) = self.color() {
+            qsv_args.extend(color.qsv_args());
+        }
+        qsv_args.extend(self.filters().qsv_args());
+        qsv_args.extend(self.keyframe_qsv_args());
+        let mut qsv = Command::new("QSVEncC64");
+        qsv.args(&qsv_args);
+
+        let copy_args: Vec<String> = ["-f", "matroska", "-i", "pipe:0", "-map", "0", "-c", "copy"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        if dash.generate_dash_streaming(live_dir, Some(qsv), &copy_args) {
+            report.encoder = Some(String::from("QSVEncC64"));
+            return true;
+        }
+
+        // QSVEncC64失败时由ffmpeg直接编码并封装
+        println!("{} 尝试使用ffmpeg进行单遍转码", "🔄".yellow());
+        let mut ffmpeg_args: Vec<String> = [
+            "-i", self.video_path.to_str().unwrap(),
+            "-map", "0:v:0",
+            "-map", "0:a?",
+            "-c:v", "libaom-av1",
+            "-crf", &crf.unwrap_or(DEFAULT_CRF).to_string(),
+            "-b:v", "0",
+            "-c:a", "copy",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        ffmpeg_args.extend(self.color().map(|c| c.ffmpeg_args()).unwrap_or_default());
+        ffmpeg_args.extend(self.keyframe_ffmpeg_args());
+        if let Some(filter) = self.filters().ffmpeg_filter() {
+            ffmpeg_args.push(String::from("-vf"));
+            ffmpeg_args.push(filter);
+        }
+        if !dash.generate_dash_streaming(live_dir, None, &ffmpeg_args) {
+            return false;
+        }
+        report.encoder = Some(String::from("libaom-av1"));
+        if self.color().is_some_and(|c| c.mastering.is_some()) {
+            report.note(String::from("libaom-av1未写入母版显示元数据"));
+        }
+        true
+    }
+
+    // 转码前记录色彩和滤镜信息，目标质量模式下先为这个视频搜索合适的CRF
+    fn prepare_encode(&self, av1_dir: &Path, report: &mut JobReport) -> Option<u32> {
+        let file_name = self.get_file_name();
+        if let Some(color) = self.color() {
+            println!("{} {} 色彩: {}", "🎨".blue(), file_name, color.describe());
+            report.color = Some(color.describe());
+        }
+        let filters = self.filters();
+        if !filters.is_empty() {
+            println!("{} {} 应用滤镜: {}", "✂️".blue(), file_name, filters.describe());
+            report.filters = Some(filters.describe());
+        }
+        let crf = match self.options.target {
+            Some(ref target) => self.search_crf(target, av1_dir, report),
+            None => None,
+        };
+        report.crf = crf;
+        crf
+    }
+
+    // 校验转码质量，不达标时降低CRF重新编码，多次仍不达标则标记该视频；
+    // crf为编码实际使用的质量，未知(QSV默认质量)时第一次重新编码从默认CRF开始
+    fn verify_quality(&self, out_file: &Path, options: &QualityOptions, crf: Option<u32>, report: &mut JobReport) {
+        let file_name = self.get_file_name();
+        let mut quality_report = QualityReport {
+            metric: options.metric.name().to_string(),
+            threshold: options.threshold,
+            attempts: Vec::new(),
+        };
+        let mut crf = crf;
+
+        for attempt in 0..=options.retries {
+            if attempt > 0 {
+                let next = match crf {
+                    Some(crf) => crf.saturating_sub(CRF_STEP).max(MIN_CRF),
+                    None => DEFAULT_CRF,
+                };
+                println!("{} {} 质量不达标, 使用CRF {} 重新编码", "🔄".yellow(), file_name, next);
+                if !self.encode(out_file, Some(next), report) {
+                    report.note(format!("CRF {} 重新编码失败", next));
+                    break;
+                }
+                crf = encoded_crf(Some(next), report.encoder.as_deref());
+            }
+
+            let score = match measure_quality(&self.video_path, out_file, options, self.filters()) {
+                Some(score) => score,
+                None => {
+                    report.note(String::from("质量评估失败"));
+                    break;
+                }
+            };
+
+            let passed = score.score >= options.threshold;
+            quality_report.attempts.push(QualityAttempt {
+                crf,
+                score: score.score,
+                samples: score.samples,
+            });
+
+            if passed {
+                println!("{} {} 质量达标: {:.3} >= {}", "✅".green(), file_name, score.score, options.threshold);
+                report.quality = Some(quality_report);
+                return;
+            }
+        }
+
+        println!("{} {} 质量未达到阈值 {}, 已标记", "⚠️".yellow(), file_name, options.threshold);
+        report.flagged = true;
+        report.quality = Some(quality_report);
+    }
+
+    // 编码整个视频，开启分块模式时优先分块并行编码，失败则回退为整段编码
+    fn encode(&self, output_path: &Path, crf: Option<u32>, report: &mut JobReport) -> bool {
+        if let Some(ref chunked) = self.options.chunked {
+            if self.encode_chunked(output_path, crf, chunked, report) {
+                return true;
+            }
+            println!("{} {} 分块编码未完成, 回退为整段编码", "⚠️".yellow(), self.get_file_name());
+        }
+
+        self.transcode_to_av1(&self.video_path, output_path, crf, report)
+    }
+
+    // 在场景切换处切分视频，并行编码各分块后无损拼接
+    fn encode_chunked(&self, output_path: &Path, crf: Option<u32>, options: &ChunkOptions, report: &mut JobReport) -> bool {
+        let file_name = self.get_file_name();
+        let info = match probe_media(&self.video_path) {
+            Some(info) if info.fps > 0.0 => info,
+            _ => {
+                report.note(String::from("无法读取帧率，跳过分块编码"));
+                return false;
+            }
+        };
+        let total_frames = match count_video_frames(&self.video_path) {
+            Some(frames) if frames > 0 => frames,
+            _ => {
+                report.note(String::from("无法统计帧数，跳过分块编码"));
+                return false;
+            }
+        };
+
+        let cuts = detect_scene_cuts(&self.video_path);
+        let chunks = plan_chunks(&cuts, info.duration, info.fps, total_frames, options.min_seconds);
+        if chunks.len() < 2 {
+            report.note(String::from("没有合适的场景切分点，使用整段编码"));
+            return false;
+        }
+
+        let work_dir = output_path.parent().unwrap_or(Path::new(".")).join("chunks");
+        if let Err(e) = fs::create_dir_all(&work_dir) {
+            println!("{} 创建分块目录失败: {}", "❌".red(), e);
+            return false;
+        }
+
+        println!("{} {} 切分为 {} 个分块, 使用 {} 个线程编码", "🧩".blue(), file_name, chunks.len(), options.workers);
+
+        let pool = ThreadPool::new(options.workers.max(1));
+        let results = Arc::new(Mutex::new(Vec::new()));
+        for chunk in chunks.iter().cloned() {
+            let results = results.clone();
+            let source = self.video_path.clone();
+            let chunk_file = work_dir.join(format!("chunk_{:04}.mp4", chunk.index));
+            let color = self.color().cloned();
+            let filters = self.filters().clone();
+            let keyframes = self.keyframes();
+
+            pool.execute(move || {
+                let encoder = VideoProcessor::encode_chunk(&source, &chunk_file, &chunk, crf, color.as_ref(), &filters, keyframes);
+                results.lock().unwrap().push((chunk.index, chunk_file, encoder));
+            });
+        }
+        pool.join();
+
+        let mut results = results.lock().unwrap().clone();
+        results.sort_by_key(|(index, _, _)| *index);
+
+        // 不同编码器输出的序列头不一致，不能直接拼接
+        let mut encoders: Vec<&str> = results.iter().filter_map(|(_, _, encoder)| *encoder).collect();
+        encoders.sort();
+        encoders.dedup();
+
+        let ok = if results.len() != chunks.len() || results.iter().any(|(_, _, encoder)| encoder.is_none()) {
+            report.note(String::from("部分分块编码失败"));
+            false
+        } else if encoders.len() > 1 {
+            println!("{} {} 分块使用了不同的编码器({}), 无法拼接", "❌".red(), file_name, encoders.join("+"));
+            report.note(format!("分块使用了不同的编码器: {}", encoders.join("+")));
+            false
+        } else {
+            let chunk_files: Vec<PathBuf> = results.iter().map(|(_, file, _)| file.clone()).collect();
+            if !concat_chunks(&chunk_files, &self.video_path, output_path, &work_dir) {
+                report.note(String::from("分块拼接失败"));
+                false
+            } else {
+                match verify_concat(&self.video_path, output_path) {
+                    Ok(()) => {
+                        report.encoder = Some(format!("{} (分块 x{})", encoders[0], chunks.len()));
+                        true
+                    }
+                    Err(reason) => {
+                        println!("{} {} 分块拼接校验失败: {}", "❌".red(), file_name, reason);
+                        report.note(format!("分块拼接校验失败: {}", reason));
+                        false
+                    }
+                }
+            }
+        };
+
+        if let Err(e) = fs::remove_dir_all(&work_dir) {
+            println!("{} 清理分块目录失败: {}", "⚠️".yellow(), e);
+        }
+
+        ok
+    }
+
+    // 编码单个分块（仅视频），返回使用的编码器
+    fn encode_chunk(
+        source: &Path,
+        output_path: &Path,
+        chunk: &Chunk,
+        crf: Option<u32>,
+        color: Option<&ColorInfo>,
+        filters: &VideoFilters,
+        keyframes: Option<Keyframes>,
+    ) -> Option<&'static str> {
+        println!("{} 正在编码分块 {}: 帧 {}-{}", "🛠️".yellow(), chunk.index, chunk.start_frame, chunk.end_frame);
+
+        // QSVEncC64的trim区间包含结束帧
+        let mut qsv_args = vec![
+            String::from("--codec"), String::from("av1"),
+            String::from("--input"), source.to_str().unwrap().to_string(),
+            String::from("--output"), output_path.to_str().unwrap().to_string(),
+            String::from("--avhw"),
+            String::from("--fallback-rc"),
+            String::from("--trim"), format!("{}:{}", chunk.start_frame, chunk.end_frame - 1),
+        ];
+        if let Some(crf) = crf {
+            qsv_args.push(String::from("--icq"));
+            qsv_args.push(crf.clamp(QSV_CRF_RANGE.0, QSV_CRF_RANGE.1).to_string());
+        }
+        if let Some(color) = color {
+            qsv_args.extend(color.qsv_args());
+        }
+        qsv_args.extend(filters.qsv_args());
+        qsv_args.extend(keyframes.map(|k| k.qsv_args()).unwrap_or_default());
+
+        match Command::new("QSVEncC64").args(&qsv_args).status() {
+            Ok(status) if status.success() => return Some("QSVEncC64"),
+            Ok(status) => println!("{} 分块 {} QSVEncC64转码失败，退出码: {:?}", "❌".red(), chunk.index, status.code()),
+            Err(e) => println!("{} 执行QSVEncC64失败: {}", "❌".red(), e),
+        }
+
+        println!("{} 分块 {} 尝试使用ffmpeg进行备选转码", "🔄".yellow(), chunk.index);
+        // 先定位到分块开始处再解码，trim的帧号相对于分块开始，避免每个分块都从头解码
+        let mut video_filter = format!("trim=end_frame={},setpts=PTS-STARTPTS", chunk.end_frame - chunk.start_frame);
+        if let Some(filter) = filters.ffmpeg_filter() {
+            video_filter = format!("{},{}", video_filter, filter);
+        }
+        let status = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-ss", &format!("{:.6}", chunk.seek_time),
+                "-i", source.to_str().unwrap(),
+                "-map", "0:v:0",
+                "-an",
+                "-vf", &video_filter,
+                "-c:v", "libaom-av1",
+                "-crf", &crf.unwrap_or(DEFAULT_CRF).to_string(),
+                "-b:v", "0",
+            ])
+            .args(color.map(|c| c.ffmpeg_args()).unwrap_or_default())
+            .args(keyframes.map(|k| k.ffmpeg_args()).unwrap_or_default())
+            .arg(output_path)
+            .status();
+
+        match status {
+            Ok(status) if status.success() => Some("libaom-av1"),
+            Ok(status) => {
+                println!("{} 分块 {} ffmpeg转码失败，退出码: {:?}", "❌".red(), chunk.index, status.code());
+                None
+            }
+            Err(e) => {
+                println!("{} 执行ffmpeg失败: {}", "❌".red(), e);
+                None
+            }
+        }
+    }
+
+    // 截取若干采样片段，用不同CRF试编码并计算VMAF，插值得到达到目标分数的CRF
+    fn search_crf(&self, target: &TargetQuality, av1_dir: &Path, report: &mut JobReport) -> Option<u32> {
+        let file_name = self.get_file_name();
+        let info = probe_media(&self.video_path)?;
+        println!("{} {} 正在搜索达到VMAF {} 的CRF", "🎯".blue(), file_name, target.vmaf);
+
+        let work_dir = av1_dir.join("crf_search");
+        if let Err(e) = fs::create_dir_all(&work_dir) {
+            println!("{} 创建CRF搜索目录失败: {}", "❌".red(), e);
+            return None;
+        }
+
+        // 截取采样片段，流复制不会引入额外损失
+        let mut clips = Vec::new();
+        for (i, (start, length)) in sample_windows(info.duration, target.samples).into_iter().enumerate() {
+            let clip = work_dir.join(format!("sample_{}.mkv", i));
+            let status = Command::new("ffmpeg")
+                .args([
+                    "-y",
+                    "-v", "error",
+                    "-ss", &format!("{:.3}", start),
+                    "-t", &format!("{:.3}", length),
+                    "-i", self.video_path.to_str().unwrap(),
+                    "-map", "0:v:0",
+                    "-map", "0:a?",
+                    "-c", "copy",
+                    clip.to_str().unwrap(),
+                ])
+                .status();
+            match status {
+                Ok(status) if status.success() => clips.push(clip),
+                Ok(status) => println!("{} 截取采样片段失败，退出码: {:?}", "⚠️".yellow(), status.code()),
+                Err(e) => println!("{} 执行ffmpeg失败: {}", "❌".red(), e),
+            }
+        }
+
+        let measure = QualityOptions {
+            metric: QualityMetric::Vmaf,
+            threshold: target.vmaf,
+            retries: 0,
+            samples: 1,
+        };
+
+        // 先按QSV的刻度试编码，第一次编码实际使用libaom时改用libaom的候选值重新开始；
+        // 之后换了编码器的结果刻度不同，不参与插值
+        let mut encoder: Option<String> = None;
+        let mut candidates = QSV_CANDIDATES;
+        let mut points = Vec::new();
+        let mut next = 0;
+        'candidates: while let Some(&crf) = candidates.get(next) {
+            next += 1;
+            let mut samples = Vec::new();
+            for (i, clip) in clips.iter().enumerate() {
+                let encoded = work_dir.join(format!("sample_{}_crf{}.mp4", i, crf));
+                if !self.transcode_to_av1(clip, &encoded, Some(crf), report) {
+                    continue;
+                }
+                let used = report.encoder.clone();
+                match encoder {
+                    None => {
+                        encoder = used.clone();
+                        if is_libaom(used.as_deref()) && candidates != LIBAOM_CANDIDATES {
+                            candidates = LIBAOM_CANDIDATES;
+                            next = 0;
+                            continue 'candidates;
+                        }
+                    }
+                    Some(ref first) if used.as_ref() != Some(first) => {
+                        report.note(format!("CRF {} 的采样由 {} 编码, 不参与插值", crf, used.unwrap_or_default()));
+                        continue;
+                    }
+                    _ => {}
+                }
+                if let Some(score) = measure_quality(clip, &encoded, &measure, self.filters()) {
+                    samples.push(score.score);
+                }
             }
+
+            if samples.is_empty() {
+                continue;
+            }
+            // 取最差片段的得分，保证每个片段都能达到目标
+            let score = samples.iter().cloned().fold(f64::INFINITY, f64::min);
+            println!("{} CRF {} => VMAF {:.3}", "🎯".blue(), crf, score);
+            points.push((crf, score));
+            report.crf_search.push(QualityAttempt { crf: Some(crf), score, samples });
+        }
+
+        if let Err(e) = fs::remove_dir_all(&work_dir) {
+            println!("{} 清理CRF搜索目录失败: {}", "⚠️".yellow(), e);
         }
 
-        None
+        let range = if is_libaom(encoder.as_deref()) { (MIN_CRF, MAX_CRF) } else { QSV_CRF_RANGE };
+        match interpolate_crf(&points, target.vmaf, range) {
+            Some(crf) => {
+                println!("{} {} 选用CRF {}", "🎯".green(), file_name, crf);
+                Some(crf)
+            }
+            None => {
+                println!("{} {} CRF搜索失败, 使用默认CRF {}", "⚠️".yellow(), file_name, DEFAULT_CRF);
+                report.note(String::from("CRF搜索失败，使用默认CRF"));
+                None
+            }
+        }
+    }
+
+    // 为HDR源额外生成一个色调映射后的SDR版本，非HDR源返回None
+    pub fn tonemap_sdr(&self, av1_dir: &Path, report: &mut JobReport) -> Option<PathBuf> {
+        let color = self.color()?;
+        if !color.is_hdr() {
+            return None;
+        }
+
+        let file_name = self.get_file_name();
+        let stem = self.video_path.file_stem().unwrap_or_default().to_string_lossy();
+        let sdr_file = av1_dir.join(format!("{}_sdr.mp4", stem));
+        let source_transfer = color.transfer.clone().unwrap_or_else(|| String::from("smpte2084"));
+        println!("{} {} 正在生成SDR版本: {}", "🎨".yellow(), file_name, sdr_file.display());
+
+        let qsv_status = Command::new("QSVEncC64")
+            .args([
+                "--codec", "av1",
+                "--input", self.video_path.to_str().unwrap(),
+                "--output", sdr_file.to_str().unwrap(),
+                "--avhw",
+                "--fallback-rc",
+                "--vpp-colorspace",
+                &format!(
+                    "matrix=bt2020nc:bt709,colorprim=bt2020:bt709,transfer={}:bt709,hdr2sdr=hable",
+                    source_transfer
+                ),
+                "--colorprim", "bt709",
+                "--transfer", "bt709",
+                "--colormatrix", "bt709",
+            ])
+            .args(self.filters().qsv_args())
+            .args(self.keyframe_qsv_args())
+            .status();
+
+        let ok = match qsv_status {
+            Ok(status) if status.success() => true,
+            _ => {
+                println!("{} QSVEncC64色调映射失败, 尝试使用ffmpeg", "🔄".yellow());
+                let tonemap = "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+                let video_filter = match self.filters().ffmpeg_filter() {
+                    Some(filter) => format!("{},{}", filter, tonemap),
+                    None => tonemap.to_string(),
+                };
+                let ffmpeg_status = Command::new("ffmpeg")
+                    .args([
+                        "-y",
+                        "-i", self.video_path.to_str().unwrap(),
+                        "-map", "0:v:0",
+                        "-an",
+                        "-vf", &video_filter,
+                        "-c:v", "libaom-av1",
+                        "-crf", &DEFAULT_CRF.to_string(),
+                        "-b:v", "0",
+                        "-color_primaries", "bt709",
+                        "-color_trc", "bt709",
+                        "-colorspace", "bt709",
+                    ])
+                    .args(self.keyframe_ffmpeg_args())
+                    .arg(&sdr_file)
+                    .status();
+                matches!(ffmpeg_status, Ok(status) if status.success())
+            }
+        };
+
+        if ok {
+            println!("{} {} SDR版本生成成功", "✅".green(), file_name);
+            report.note(String::from("已生成色调映射的SDR版本"));
+            Some(sdr_file)
+        } else {
+            println!("{} {} SDR版本生成失败", "❌".red(), file_name);
+            report.note(String::from("SDR版本生成失败"));
+            None
+        }
     }
 
     // 检查视频是否是AV1编码
     fn is_av1_encoded(&self) -> bool {
         let output = Command::new("ffprobe")
-            .args(&[
+            .args([
                 "-v", "error",
                 "-select_streams", "v:0",
                 "-show_entries", "stream=codec_name",
@@ -78,25 +719,37 @@ impl VideoProcessor {
         }
     }
 
-    // 使用QSVEncC64转码视频为AV1格式
-    fn transcode_to_av1(&self, output_path: &Path) -> bool {
+    // 使用QSVEncC64转码视频为AV1格式，crf为None时使用编码器默认质量
+    fn transcode_to_av1(&self, input_path: &Path, output_path: &Path, crf: Option<u32>, report: &mut JobReport) -> bool {
         println!("{} 正在使用QSVEncC64转码为AV1: {}", "🛠️".yellow(), output_path.display());
         
         // 使用QSVEncC64进行转码，多级编码回退
+        let mut qsv_args = vec![
+            String::from("--codec"), String::from("av1"),
+            String::from("--input"), input_path.to_str().unwrap().to_string(),
+            String::from("--output"), output_path.to_str().unwrap().to_string(),
+            String::from("--audio-copy"),
+            String::from("--avhw"),
+            String::from("--fallback-rc"),
+        ];
+        if let Some(crf) = crf {
+            qsv_args.push(String::from("--icq"));
+            qsv_args.push(crf.clamp(QSV_CRF_RANGE.0, QSV_CRF_RANGE.1).to_string());
+        }
+        if let Some(color) = self.color() {
+            qsv_args.extend(color.qsv_args());
+        }
+        qsv_args.extend(self.filters().qsv_args());
+        qsv_args.extend(self.keyframe_qsv_args());
+
         let result = Command::new("QSVEncC64")
-            .args(&[
-                "--codec", "av1",
-                "--input", self.video_path.to_str().unwrap(),
-                "--output", output_path.to_str().unwrap(),
-                "--audio-copy",
-                "--avhw",
-                "--fallback-rc",
-            ])
+            .args(&qsv_args)
             .status();
 
         match result {
             Ok(status) => {
                 if status.success() {
+                    report.encoder = Some(String::from("QSVEncC64"));
                     // 检查转码后的文件是否有视频和音频流
                     self.check_streams(output_path)
                 } else {
@@ -105,20 +758,28 @@ impl VideoProcessor {
                     // 如果QSVEncC64失败，尝试使用ffmpeg作为备选
                     println!("{} 尝试使用ffmpeg进行备选转码", "🔄".yellow());
                     let ffmpeg_result = Command::new("ffmpeg")
-                        .args(&[
-                            "-i", self.video_path.to_str().unwrap(),
+                        .args([
+                            "-y",
+                            "-i", input_path.to_str().unwrap(),
                             "-c:v", "libaom-av1",
-                            "-crf", "30",
+                            "-crf", &crf.unwrap_or(DEFAULT_CRF).to_string(),
                             "-b:v", "0",
                             "-c:a", "copy",
-                            output_path.to_str().unwrap(),
                         ])
+                        .args(self.color().map(|c| c.ffmpeg_args()).unwrap_or_default())
+                        .args(self.filters().ffmpeg_filter().map(|f| vec![String::from("-vf"), f]).unwrap_or_default())
+                        .args(self.keyframe_ffmpeg_args())
+                        .arg(output_path)
                         .status();
                         
                     match ffmpeg_result {
                         Ok(ffmpeg_status) => {
                             if ffmpeg_status.success() {
                                 println!("{} ffmpeg转码成功", "✅".green());
+                                report.encoder = Some(String::from("libaom-av1"));
+                                if self.color().is_some_and(|c| c.mastering.is_some()) {
+                                    report.note(String::from("libaom-av1未写入母版显示元数据"));
+                                }
                                 self.check_streams(output_path)
                             } else {
                                 println!("{} ffmpeg转码失败，退出码: {:?}", "❌".red(), ffmpeg_status.code());
@@ -144,7 +805,7 @@ impl VideoProcessor {
         println!("{} 检查转码后的流...", "🔍".blue());
         
         let video_check = Command::new("ffprobe")
-            .args(&[
+            .args([
                 "-v", "error",
                 "-select_streams", "v:0",
                 "-count_packets",
@@ -155,7 +816,7 @@ impl VideoProcessor {
             .output();
             
         let audio_check = Command::new("ffprobe")
-            .args(&[
+            .args([
                 "-v", "error",
                 "-select_streams", "a:0",
                 "-count_packets",
@@ -204,4 +865,19 @@ impl VideoProcessor {
         
         has_video && has_audio
     }
-}
\ No newline at end of file
+}
+
+// 编码实际使用的质量参数：QSV的ICQ限制在1-51，未指定时libaom使用默认CRF，QSV使用编码器默认质量(未知)
+fn encoded_crf(crf: Option<u32>, encoder: Option<&str>) -> Option<u32> {
+    let libaom = is_libaom(encoder);
+    match crf {
+        Some(crf) if libaom => Some(crf),
+        Some(crf) => Some(crf.clamp(QSV_CRF_RANGE.0, QSV_CRF_RANGE.1)),
+        None if libaom => Some(DEFAULT_CRF),
+        None => None,
+    }
+}
+
+fn is_libaom(encoder: Option<&str>) -> bool {
+    encoder.is_some_and(|encoder| encoder.starts_with("libaom"))
+}